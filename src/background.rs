@@ -0,0 +1,521 @@
+use std::time::{Duration, Instant};
+
+use eframe::egui::{self, Color32, Slider};
+use egui_plot::{Line, PlotPoints, PlotUi, Points};
+
+use crate::events::EventLog;
+use crate::export::Svg;
+use crate::geometry::{distance, OrientedBox};
+use crate::overlay::bearing_deg;
+use crate::scan::Scan;
+
+const FOREGROUND_COLOR: Color32 = Color32::from_rgb(255, 60, 60);
+const BACKGROUND_COLOR: Color32 = Color32::from_gray(110);
+
+/// Foreground points farther apart start a new region.
+const REGION_GAP: f64 = 0.2;
+
+/// Range statistics of one angular bin of the static scene.
+#[derive(Debug, Clone, Copy)]
+struct BinModel {
+    range: f32,
+    /// robust standard deviation
+    sigma: f32,
+}
+
+/// Ranges seen in each angular bin while learning.
+type BinSamples = Vec<Vec<f32>>;
+
+/// Learns the static scene per sensor and angular bin and reports points in front of it.
+pub struct BackgroundSubtraction {
+    pub enabled: bool,
+    bin_size_deg: f64,
+    learn_duration_s: f64,
+    /// minimum distance in front of the background
+    threshold: f32,
+    /// additional margin in standard deviations of the bin
+    sigma_factor: f32,
+    min_region_points: usize,
+    /// scans without motion before it is considered over
+    hold_scans: usize,
+    show_background: bool,
+    /// learning ends with the first scan completed after the instant,
+    /// the samples are indexed by sensor
+    learning: Option<(Instant, Vec<BinSamples>)>,
+    /// bins of each sensor
+    model: Vec<Vec<Option<BinModel>>>,
+    /// the learned background of each sensor in the robot frame
+    outline: Vec<Vec<[f64; 2]>>,
+    foreground: Vec<[f64; 2]>,
+    regions: Vec<OrientedBox>,
+    in_motion: bool,
+    quiet_scans: usize,
+    pub events: EventLog,
+    /// the scan the foreground belongs to
+    source: Option<Instant>,
+}
+
+impl Default for BackgroundSubtraction {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            bin_size_deg: 1.0,
+            learn_duration_s: 5.0,
+            threshold: 0.1,
+            sigma_factor: 3.0,
+            min_region_points: 3,
+            hold_scans: 10,
+            show_background: false,
+            learning: None,
+            model: vec![],
+            outline: vec![],
+            foreground: vec![],
+            regions: vec![],
+            in_motion: false,
+            quiet_scans: 0,
+            events: Default::default(),
+            source: None,
+        }
+    }
+}
+
+impl BackgroundSubtraction {
+    /// Forgets the model, e.g. when the device changes.
+    pub fn reset(&mut self) {
+        self.learning = None;
+        self.model.clear();
+        self.outline.clear();
+        self.foreground.clear();
+        self.regions.clear();
+        self.in_motion = false;
+        self.source = None;
+    }
+
+    fn bins(&self) -> usize {
+        (360.0 / self.bin_size_deg).ceil() as usize
+    }
+
+    fn bin(&self, angle: f32) -> usize {
+        ((angle as f64).rem_euclid(360.0) / self.bin_size_deg) as usize % self.bins()
+    }
+
+    /// Learns from the scans completed until the instant.
+    fn start_learning(&mut self, until: Instant) {
+        // the sensors are added with their first points
+        self.learning = Some((until, vec![]));
+    }
+
+    fn finish_learning(&mut self, samples: Vec<BinSamples>, scan: &Scan) {
+        self.model = samples
+            .into_iter()
+            .map(|bins| bins.into_iter().map(bin_model).collect())
+            .collect();
+
+        self.outline = self
+            .model
+            .iter()
+            .enumerate()
+            .map(|(sensor, bins)| {
+                let mounting = scan.mounting_of(sensor);
+                bins.iter()
+                    .enumerate()
+                    .filter_map(|(i, bin)| {
+                        let angle = ((i as f64 + 0.5) * self.bin_size_deg).to_radians();
+                        let range = (*bin)?.range as f64;
+                        Some(mounting.transform([angle.sin() * range, angle.cos() * range]))
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let bins = self.model.iter().flatten();
+        self.events.push(format!(
+            "Background learned, {} of {} bins",
+            bins.clone().filter(|b| b.is_some()).count(),
+            bins.count()
+        ));
+    }
+
+    /// Learns from or compares the scan with the background unless already done.
+    pub fn update(&mut self, scan: &Scan) {
+        if self.source == Some(scan.completed) {
+            return;
+        }
+        self.source = Some(scan.completed);
+
+        let bins = self.bins();
+        let ranges: Vec<_> = scan
+            .points
+            .iter()
+            .filter(|p| p.distance > 0.0)
+            .map(|p| (p.sensor, self.bin(p.angle), p.distance))
+            .collect();
+        if let Some((until, samples)) = &mut self.learning {
+            for (sensor, bin, range) in ranges {
+                if samples.len() <= sensor {
+                    samples.resize(sensor + 1, vec![vec![]; bins]);
+                }
+                samples[sensor][bin].push(range);
+            }
+
+            if scan.completed >= *until {
+                let (_, samples) = self.learning.take().expect("learning");
+                self.finish_learning(samples, scan);
+            }
+            return;
+        }
+
+        if !self.enabled || self.model.is_empty() {
+            return;
+        }
+
+        self.foreground.clear();
+        let mut groups: Vec<Vec<[f64; 2]>> = vec![];
+        for run in scan.sensor_runs() {
+            // sensors connected after learning have no background
+            let Some(model) = self
+                .model
+                .get(run[0].sensor)
+                .filter(|m| m.iter().any(Option::is_some))
+            else {
+                continue;
+            };
+
+            let foreground: Vec<_> = run
+                .iter()
+                .filter(|p| {
+                    p.distance > 0.0
+                        && match model[self.bin(p.angle)] {
+                            Some(bin) => {
+                                p.distance
+                                    < bin.range - self.threshold.max(self.sigma_factor * bin.sigma)
+                            }
+                            // nothing was seen there while learning
+                            None => true,
+                        }
+                })
+                .map(|p| scan.position(p))
+                .collect();
+
+            // neighbouring foreground points of a sensor form a changed region
+            let first = groups.len();
+            for p in foreground {
+                match groups[first..].last_mut() {
+                    Some(group) if distance(*group.last().expect("not empty"), p) <= REGION_GAP => {
+                        group.push(p)
+                    }
+                    _ => groups.push(vec![p]),
+                }
+                self.foreground.push(p);
+            }
+        }
+        groups.retain(|g| g.len() >= self.min_region_points);
+        self.regions = groups
+            .iter()
+            .filter_map(|g| OrientedBox::from_points(g))
+            .collect();
+
+        let sensor = scan.mounting.transform([0.0, 0.0]);
+        if !self.regions.is_empty() {
+            self.quiet_scans = 0;
+            if !self.in_motion {
+                self.in_motion = true;
+
+                let nearest = self
+                    .regions
+                    .iter()
+                    .map(|r| r.center)
+                    .min_by(|a, b| distance(*a, sensor).total_cmp(&distance(*b, sensor)))
+                    .expect("not empty");
+                self.events.push(format!(
+                    "Motion in {} regions, nearest at d={:.2}m θ={:.1}°",
+                    self.regions.len(),
+                    distance(nearest, sensor),
+                    bearing_deg(nearest[0] - sensor[0], nearest[1] - sensor[1])
+                ));
+            }
+        } else if self.in_motion {
+            self.quiet_scans += 1;
+            if self.quiet_scans >= self.hold_scans {
+                self.in_motion = false;
+                self.events.push("Motion ended".to_owned());
+            }
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.enabled, "Highlight foreground");
+
+        match &self.learning {
+            Some(_) => {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label("Learning, keep the scene static");
+                });
+            }
+            None => {
+                ui.add(
+                    Slider::new(&mut self.learn_duration_s, 1.0..=30.0)
+                        .text("Learn for (s)")
+                        .step_by(0.5),
+                );
+                ui.horizontal(|ui| {
+                    if ui.button("Learn background").clicked() {
+                        let until = Instant::now() + Duration::from_secs_f64(self.learn_duration_s);
+                        self.start_learning(until);
+                    }
+                    if !self.model.is_empty() && ui.button("Forget").clicked() {
+                        self.reset();
+                    }
+                });
+            }
+        }
+
+        // the bins of a learned model are fixed
+        ui.add_enabled(
+            self.model.is_empty() && self.learning.is_none(),
+            Slider::new(&mut self.bin_size_deg, 0.25..=5.0)
+                .text("Bin size (°)")
+                .step_by(0.25),
+        );
+        ui.add(
+            Slider::new(&mut self.threshold, 0.01..=1.0)
+                .text("Min. distance (m)")
+                .step_by(0.01),
+        );
+        ui.add(Slider::new(&mut self.sigma_factor, 0.0..=10.0).text("Margin (σ)"));
+        ui.add(Slider::new(&mut self.min_region_points, 1..=30).text("Min. region points"));
+        ui.add(Slider::new(&mut self.hold_scans, 1..=100).text("Quiet scans to end motion"));
+        ui.checkbox(&mut self.show_background, "Show background");
+
+        if !self.model.is_empty() {
+            ui.label(format!(
+                "{} foreground points in {} regions",
+                self.foreground.len(),
+                self.regions.len()
+            ));
+        }
+        self.events.ui(ui, "motion_events");
+    }
+
+    pub fn show(&self, plot_ui: &mut PlotUi) {
+        for outline in self.outline.iter().filter(|_| self.show_background) {
+            if let Some(first) = outline.first() {
+                let mut outline = outline.clone();
+                outline.push(*first);
+                plot_ui.line(
+                    Line::new(PlotPoints::new(outline))
+                        .color(BACKGROUND_COLOR)
+                        .name("Background"),
+                );
+            }
+        }
+
+        if !self.enabled || self.model.is_empty() {
+            return;
+        }
+
+        plot_ui.points(
+            Points::new(self.foreground.clone())
+                .radius(3.0)
+                .color(FOREGROUND_COLOR)
+                .name("Foreground"),
+        );
+        for region in &self.regions {
+            let corners = region.corners();
+            let mut outline = corners.to_vec();
+            outline.push(corners[0]);
+            plot_ui.line(
+                Line::new(PlotPoints::new(outline))
+                    .color(FOREGROUND_COLOR)
+                    .width(2.0)
+                    .allow_hover(false),
+            );
+        }
+    }
+
+    pub fn write_svg(&self, svg: &mut Svg) {
+        for outline in self.outline.iter().filter(|_| self.show_background) {
+            if let Some(first) = outline.first() {
+                let mut outline = outline.clone();
+                outline.push(*first);
+                svg.polyline(&outline, 1.0, BACKGROUND_COLOR, false);
+            }
+        }
+
+        if !self.enabled || self.model.is_empty() {
+            return;
+        }
+
+        for p in &self.foreground {
+            svg.circle(*p, 3.0, FOREGROUND_COLOR);
+        }
+        for region in &self.regions {
+            let corners = region.corners();
+            let mut outline = corners.to_vec();
+            outline.push(corners[0]);
+            svg.polyline(&outline, 2.0, FOREGROUND_COLOR, false);
+        }
+    }
+}
+
+/// Median range and spread of the samples of a bin, none if nothing was seen.
+fn bin_model(mut ranges: Vec<f32>) -> Option<BinModel> {
+    if ranges.is_empty() {
+        return None;
+    }
+
+    ranges.sort_by(f32::total_cmp);
+    let range = ranges[ranges.len() / 2];
+    let mut deviations: Vec<_> = ranges.iter().map(|r| (r - range).abs()).collect();
+    deviations.sort_by(f32::total_cmp);
+
+    Some(BinModel {
+        range,
+        // median absolute deviation scaled to a normal distribution
+        sigma: deviations[deviations.len() / 2] * 1.4826,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A room at 3m seen in one degree steps, `closer` overrides some ranges.
+    fn room(closer: &[(f32, f32)]) -> Scan {
+        Scan::from_readings((0..360).map(|angle| {
+            let angle = angle as f32;
+            let range = closer
+                .iter()
+                .find(|(a, _)| *a == angle)
+                .map_or(3.0, |(_, r)| *r);
+            (angle, range, 200)
+        }))
+    }
+
+    /// The scan as if completed `secs` after `scan`.
+    fn later(mut scan: Scan, after: &Scan, secs: u64) -> Scan {
+        scan.completed = after.completed + Duration::from_secs(secs);
+        scan
+    }
+
+    #[test]
+    fn closer_point_is_foreground_after_learning() {
+        let mut background = BackgroundSubtraction::default();
+        let learned = room(&[]);
+        background.start_learning(learned.completed);
+        background.update(&learned);
+        assert!(background.learning.is_none());
+
+        let scan = later(room(&[(40.0, 2.5), (41.0, 2.95)]), &learned, 1);
+        background.update(&scan);
+
+        let closer = scan.position(&scan.points[40]);
+        assert_eq!(background.foreground, vec![closer]);
+    }
+
+    #[test]
+    fn learning_spans_the_scans_until_the_deadline() {
+        let mut background = BackgroundSubtraction::default();
+        let first = room(&[]);
+        background.start_learning(first.completed + Duration::from_secs(3));
+
+        // the ranges of bin 10 vary from scan to scan
+        for (i, range) in [2.9, 3.1, 3.0].into_iter().enumerate() {
+            background.update(&later(room(&[(10.0, range)]), &first, i as u64));
+        }
+        assert!(background.learning.is_some());
+        background.update(&later(room(&[]), &first, 3));
+        assert!(background.learning.is_none());
+
+        assert_eq!(background.model.len(), 1);
+        assert_eq!(background.model[0].len(), 360);
+        let bin = background.model[0][10].expect("learned");
+        assert!((bin.range - 3.0).abs() < 1e-3, "{:?}", bin);
+        assert!(bin.sigma > 0.0);
+        assert_eq!(background.model[0][11].expect("learned").sigma, 0.0);
+        assert_eq!(background.outline[0].len(), 360);
+        assert!(background
+            .events
+            .to_text()
+            .ends_with("Background learned, 360 of 360 bins\n"));
+    }
+
+    #[test]
+    fn motion_starts_and_ends_with_the_foreground() {
+        let mut background = BackgroundSubtraction {
+            hold_scans: 2,
+            ..Default::default()
+        };
+        let learned = room(&[]);
+        background.start_learning(learned.completed);
+        background.update(&learned);
+
+        // a person of a few points at 1.5m
+        let person: Vec<_> = (100..106).map(|a| (a as f32, 1.5)).collect();
+        background.update(&later(room(&person), &learned, 1));
+        assert_eq!(background.foreground.len(), 6);
+        assert_eq!(background.regions.len(), 1);
+        assert!(background.in_motion);
+
+        background.update(&later(room(&[]), &learned, 2));
+        assert!(background.in_motion);
+        background.update(&later(room(&[]), &learned, 3));
+        assert!(!background.in_motion);
+        let log = background.events.to_text();
+        let messages: Vec<_> = log.lines().map(|l| l.split(" UTC ").nth(1)).collect();
+        assert_eq!(messages.len(), 3);
+        assert!(messages[1].is_some_and(|m| m.starts_with("Motion in 1 regions")));
+        assert_eq!(messages[2], Some("Motion ended"));
+    }
+
+    #[test]
+    fn second_sensor_has_its_own_background() {
+        let mut background = BackgroundSubtraction::default();
+        let mut learned = room(&[]);
+        // the second sensor sees a nearer wall, mounted behind the first one
+        let mut rear = Scan::from_readings((0..360).map(|a| (a as f32, 1.0, 200)));
+        rear.mounting.y = -0.5;
+        learned.merge(&rear, 1);
+        background.start_learning(learned.completed);
+        background.update(&learned);
+        assert_eq!(background.model.len(), 2);
+        assert!((background.model[1][0].expect("learned").range - 1.0).abs() < 1e-3);
+
+        let mut scan = room(&[]);
+        let rear = Scan::from_readings((0..360).map(|a| {
+            let range = if a == 200 { 0.6 } else { 1.0 };
+            (a as f32, range, 200)
+        }));
+        scan.mounting = learned.mounting;
+        scan.merge(&rear, 1);
+        let mut scan = later(scan, &learned, 1);
+        scan.merged[0].1.y = -0.5;
+        background.update(&scan);
+
+        let closer = scan
+            .points
+            .iter()
+            .find(|p| p.sensor == 1 && p.distance < 1.0)
+            .expect("closer point");
+        assert_eq!(background.foreground, vec![scan.position(closer)]);
+    }
+
+    #[test]
+    fn sensor_without_background_is_skipped() {
+        let mut background = BackgroundSubtraction::default();
+        let learned = room(&[]);
+        background.start_learning(learned.completed);
+        background.update(&learned);
+
+        // connected after learning, everything is closer than the room
+        let mut scan = room(&[]);
+        scan.merge(
+            &Scan::from_readings((0..360).map(|a| (a as f32, 0.5, 200))),
+            1,
+        );
+        background.update(&later(scan, &learned, 1));
+
+        assert!(background.foreground.is_empty());
+    }
+}
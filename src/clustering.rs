@@ -0,0 +1,427 @@
+use std::time::Instant;
+
+use eframe::egui::{self, Align2, Color32, Slider};
+use egui_plot::{Line, PlotPoint, PlotPoints, PlotUi, Points, Text};
+
+use crate::export::Svg;
+use crate::geometry::{distance, OrientedBox};
+use crate::scan::{LidarPoint, Scan};
+
+/// Colours cycled through by the clusters.
+const PALETTE: [Color32; 8] = [
+    Color32::from_rgb(230, 25, 75),
+    Color32::from_rgb(60, 180, 75),
+    Color32::from_rgb(255, 225, 25),
+    Color32::from_rgb(67, 99, 216),
+    Color32::from_rgb(245, 130, 49),
+    Color32::from_rgb(145, 30, 180),
+    Color32::from_rgb(66, 212, 244),
+    Color32::from_rgb(240, 50, 230),
+];
+
+pub fn cluster_color(index: usize) -> Color32 {
+    PALETTE[index % PALETTE.len()]
+}
+
+#[derive(Debug, Clone)]
+pub struct Cluster {
+    pub points: Vec<[f64; 2]>,
+    pub centroid: [f64; 2],
+    pub bbox: OrientedBox,
+}
+
+impl Cluster {
+    fn new(points: Vec<[f64; 2]>) -> Option<Self> {
+        let bbox = OrientedBox::from_points(&points)?;
+        let n = points.len() as f64;
+        let centroid = [
+            points.iter().map(|p| p[0]).sum::<f64>() / n,
+            points.iter().map(|p| p[1]).sum::<f64>() / n,
+        ];
+
+        Some(Self {
+            points,
+            centroid,
+            bbox,
+        })
+    }
+
+    /// Length and width of the bounding box.
+    pub fn size(&self) -> [f64; 2] {
+        let [l, w] = self.bbox.half_extents;
+        [l * 2.0, w * 2.0]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Method {
+    Breakpoint,
+    Dbscan,
+}
+
+/// Splits the scan where neighbouring points are farther apart than expected
+/// for a surface seen at grazing angle `lambda` (adaptive breakpoint detector),
+/// the points of each sensor are split on their own.
+pub fn breakpoints(scan: &Scan, lambda_deg: f64, sigma: f64) -> Vec<Vec<[f64; 2]>> {
+    scan.sensor_runs()
+        .flat_map(|run| split_run(scan, run, lambda_deg, sigma))
+        .collect()
+}
+
+fn split_run(scan: &Scan, run: &[LidarPoint], lambda_deg: f64, sigma: f64) -> Vec<Vec<[f64; 2]>> {
+    let positions: Vec<_> = run.iter().map(|p| scan.position(p)).collect();
+    let mut groups: Vec<Vec<[f64; 2]>> = vec![];
+
+    let connected = |a: usize, b: usize| {
+        let (pa, pb) = (&run[a], &run[b]);
+        let step = (pb.angle as f64 - pa.angle as f64)
+            .rem_euclid(360.0)
+            .to_radians();
+        let lambda = lambda_deg.to_radians();
+        if step >= lambda {
+            return false;
+        }
+
+        let max_gap = pa.distance as f64 * step.sin() / (lambda - step).sin() + 3.0 * sigma;
+        distance(positions[a], positions[b]) <= max_gap
+    };
+
+    for (i, p) in positions.iter().enumerate() {
+        match groups.last_mut() {
+            Some(group) if connected(i - 1, i) => group.push(*p),
+            _ => groups.push(vec![*p]),
+        }
+    }
+
+    // the scan wraps around
+    if groups.len() > 1 && connected(positions.len() - 1, 0) {
+        let last = groups.pop().expect("more than one group");
+        groups[0].splice(0..0, last);
+    }
+
+    groups
+}
+
+/// Density based clustering, points with fewer than `min_points` neighbours
+/// within `eps` that are not reachable from a core point are noise.
+pub fn dbscan(points: &[[f64; 2]], eps: f64, min_points: usize) -> Vec<Vec<[f64; 2]>> {
+    let neighbours = |i: usize| -> Vec<usize> {
+        (0..points.len())
+            .filter(|j| distance(points[i], points[*j]) <= eps)
+            .collect()
+    };
+
+    let mut labels: Vec<Option<usize>> = vec![None; points.len()];
+    let mut visited = vec![false; points.len()];
+    let mut clusters: Vec<Vec<[f64; 2]>> = vec![];
+
+    for i in 0..points.len() {
+        if visited[i] {
+            continue;
+        }
+        visited[i] = true;
+
+        let seeds = neighbours(i);
+        if seeds.len() < min_points {
+            continue;
+        }
+
+        let label = clusters.len();
+        clusters.push(vec![]);
+        labels[i] = Some(label);
+
+        let mut queue = seeds;
+        while let Some(j) = queue.pop() {
+            if labels[j].is_none() {
+                labels[j] = Some(label);
+            }
+            if visited[j] {
+                continue;
+            }
+            visited[j] = true;
+
+            let more = neighbours(j);
+            if more.len() >= min_points {
+                queue.extend(more);
+            }
+        }
+    }
+
+    for (p, label) in points.iter().zip(labels) {
+        if let Some(label) = label {
+            clusters[label].push(*p);
+        }
+    }
+
+    clusters
+}
+
+pub struct Clustering {
+    pub enabled: bool,
+    method: Method,
+    lambda_deg: f64,
+    sigma: f64,
+    eps: f64,
+    min_neighbours: usize,
+    min_points: usize,
+    show_boxes: bool,
+    clusters: Vec<Cluster>,
+    /// the scan the clusters belong to
+    source: Option<Instant>,
+}
+
+impl Default for Clustering {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            method: Method::Breakpoint,
+            lambda_deg: 10.0,
+            sigma: 0.01,
+            eps: 0.1,
+            min_neighbours: 4,
+            min_points: 3,
+            show_boxes: true,
+            clusters: vec![],
+            source: None,
+        }
+    }
+}
+
+impl Clustering {
+    /// Forgets the clusters, e.g. when the device changes.
+    pub fn reset(&mut self) {
+        self.clusters.clear();
+        self.source = None;
+    }
+
+    /// Clusters of the last processed scan, empty while disabled.
+    pub fn clusters(&self) -> &[Cluster] {
+        if self.enabled {
+            &self.clusters
+        } else {
+            &[]
+        }
+    }
+
+    /// Clusters the points of the scan unless already done.
+    pub fn update(&mut self, scan: &Scan) {
+        if !self.enabled || self.source == Some(scan.completed) {
+            return;
+        }
+
+        let groups = match self.method {
+            Method::Breakpoint => breakpoints(scan, self.lambda_deg, self.sigma),
+            Method::Dbscan => {
+                let points: Vec<_> = scan.positions().collect();
+                dbscan(&points, self.eps, self.min_neighbours)
+            }
+        };
+        self.clusters = groups
+            .into_iter()
+            .filter(|g| g.len() >= self.min_points)
+            .filter_map(Cluster::new)
+            .collect();
+        self.source = Some(scan.completed);
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        let before = (
+            self.enabled,
+            self.method,
+            self.lambda_deg,
+            self.sigma,
+            self.eps,
+            self.min_neighbours,
+            self.min_points,
+        );
+
+        ui.checkbox(&mut self.enabled, "Cluster points");
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.method, Method::Breakpoint, "Breakpoints");
+            ui.selectable_value(&mut self.method, Method::Dbscan, "DBSCAN");
+        });
+        match self.method {
+            Method::Breakpoint => {
+                ui.add(
+                    Slider::new(&mut self.lambda_deg, 1.0..=45.0)
+                        .text("Min. incidence (°)")
+                        .step_by(0.5),
+                )
+                .on_hover_text("Surfaces seen at a shallower angle are split");
+                ui.add(
+                    Slider::new(&mut self.sigma, 0.0..=0.1)
+                        .text("Range noise σ (m)")
+                        .step_by(0.001),
+                );
+            }
+            Method::Dbscan => {
+                ui.add(Slider::new(&mut self.eps, 0.01..=1.0).text("Radius (m)"));
+                ui.add(Slider::new(&mut self.min_neighbours, 1..=20).text("Min. neighbours"));
+            }
+        }
+        ui.add(Slider::new(&mut self.min_points, 1..=50).text("Min. points"));
+        ui.checkbox(&mut self.show_boxes, "Bounding boxes");
+
+        // cluster again with the new parameters
+        if before
+            != (
+                self.enabled,
+                self.method,
+                self.lambda_deg,
+                self.sigma,
+                self.eps,
+                self.min_neighbours,
+                self.min_points,
+            )
+        {
+            self.source = None;
+        }
+
+        if !self.enabled {
+            return;
+        }
+
+        ui.label(format!("{} clusters", self.clusters.len()));
+        egui::ScrollArea::vertical()
+            .id_source("clusters")
+            .max_height(200.0)
+            .show(ui, |ui| {
+                egui::Grid::new("clusters")
+                    .num_columns(4)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.label("#");
+                        ui.label("Points");
+                        ui.label("Centre");
+                        ui.label("Size");
+                        ui.end_row();
+                        for (i, c) in self.clusters.iter().enumerate() {
+                            ui.colored_label(cluster_color(i), format!("{}", i + 1));
+                            ui.label(format!("{}", c.points.len()));
+                            ui.label(format!("({:.2}, {:.2})", c.centroid[0], c.centroid[1]));
+                            let [l, w] = c.size();
+                            ui.label(format!("{:.2} × {:.2}m", l, w));
+                            ui.end_row();
+                        }
+                    });
+            });
+    }
+
+    pub fn show(&self, plot_ui: &mut PlotUi) {
+        if !self.enabled {
+            return;
+        }
+
+        for (i, c) in self.clusters.iter().enumerate() {
+            let color = cluster_color(i);
+            plot_ui.points(
+                Points::new(c.points.clone())
+                    .radius(2.5)
+                    .color(color)
+                    .name(format!("Cluster {}", i + 1)),
+            );
+
+            if !self.show_boxes {
+                continue;
+            }
+
+            let corners = c.bbox.corners();
+            let mut outline = corners.to_vec();
+            outline.push(corners[0]);
+            plot_ui.line(
+                Line::new(PlotPoints::new(outline))
+                    .color(color)
+                    .allow_hover(false),
+            );
+            plot_ui.points(
+                Points::new(vec![c.centroid])
+                    .radius(3.0)
+                    .shape(egui_plot::MarkerShape::Cross)
+                    .color(color)
+                    .allow_hover(false),
+            );
+
+            let [l, w] = c.size();
+            let top = corners
+                .iter()
+                .max_by(|a, b| a[1].total_cmp(&b[1]))
+                .expect("four corners");
+            plot_ui.text(
+                Text::new(
+                    PlotPoint::new(top[0], top[1]),
+                    format!("{}: {:.2} × {:.2}m", i + 1, l, w),
+                )
+                .color(color)
+                .anchor(Align2::CENTER_BOTTOM),
+            );
+        }
+    }
+
+    pub fn write_svg(&self, svg: &mut Svg) {
+        if !self.enabled {
+            return;
+        }
+
+        for (i, c) in self.clusters.iter().enumerate() {
+            let color = cluster_color(i);
+            for p in &c.points {
+                svg.circle(*p, 2.5, color);
+            }
+            if self.show_boxes {
+                let corners = c.bbox.corners();
+                let mut outline = corners.to_vec();
+                outline.push(corners[0]);
+                svg.polyline(&outline, 1.0, color, false);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Points along a row starting at `start`, `spacing` apart.
+    fn row(start: [f64; 2], n: usize, spacing: f64) -> Vec<[f64; 2]> {
+        (0..n)
+            .map(|i| [start[0] + i as f64 * spacing, start[1]])
+            .collect()
+    }
+
+    #[test]
+    fn dbscan_separates_clusters_and_noise() {
+        let mut points = row([0.0, 0.0], 6, 0.05);
+        points.extend(row([2.0, 1.0], 4, 0.05));
+        // far from everything
+        points.push([-3.0, 3.0]);
+
+        let mut clusters = dbscan(&points, 0.1, 3);
+        clusters.sort_by_key(|c| c.len());
+
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[0], row([2.0, 1.0], 4, 0.05));
+        assert_eq!(clusters[1], row([0.0, 0.0], 6, 0.05));
+    }
+
+    #[test]
+    fn dbscan_keeps_border_points_without_chaining_through_them() {
+        // the point between the rows reaches both, but has too few neighbours to join them
+        let mut points = row([0.0, 0.0], 5, 0.05);
+        points.push([0.28, 0.0]);
+        points.extend(row([0.36, 0.0], 5, 0.05));
+
+        let clusters = dbscan(&points, 0.11, 4);
+
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters.iter().map(Vec::len).sum::<usize>(), 11);
+        assert!(clusters[0].contains(&[0.28, 0.0]));
+    }
+
+    #[test]
+    fn dbscan_of_sparse_points_is_empty() {
+        let points = row([0.0, 0.0], 5, 1.0);
+
+        assert!(dbscan(&points, 0.5, 2).is_empty());
+        assert!(dbscan(&[], 0.5, 2).is_empty());
+    }
+}
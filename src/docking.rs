@@ -0,0 +1,495 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::time::Instant;
+
+use eframe::egui::{self, Align2, Color32, DragValue, Slider};
+use egui_plot::{Arrows, Line, MarkerShape, PlotPoint, PlotPoints, PlotUi, Points, Text};
+
+use crate::events::EventLog;
+use crate::export::{self, Svg};
+use crate::geometry::{distance, fit_line, normalize_deg, Pose2D};
+use crate::landmarks::Reflector;
+use crate::mounting::Mounting;
+use crate::overlay::bearing_deg;
+
+const PATTERN_COLOR: Color32 = Color32::from_rgb(0, 255, 255);
+
+/// Estimates kept to compute the jitter.
+const JITTER_WINDOW: usize = 20;
+
+/// Poses beyond this are dropped from the log, oldest first.
+const MAX_LOG: usize = 10_000;
+
+#[derive(Debug, Clone, Copy)]
+pub struct DockingPose {
+    /// pattern frame in the robot frame
+    pub pose: Pose2D,
+    /// root mean square distance of the matched strips
+    pub rms: f64,
+    pub matched: usize,
+}
+
+/// Yaw of the pattern in degrees, 0° while the sensor faces it squarely.
+fn heading_deg(pose: &Pose2D) -> f64 {
+    // clockwise like the θ of the coordinates
+    normalize_deg(180.0 - pose.theta.to_degrees())
+}
+
+/// Rigid transform mapping `from` onto `to` in the least squares sense.
+fn fit_rigid(pairs: &[([f64; 2], [f64; 2])]) -> Pose2D {
+    let n = pairs.len() as f64;
+    let (mut a, mut b) = ([0.0, 0.0], [0.0, 0.0]);
+    for (p, q) in pairs {
+        a = [a[0] + p[0] / n, a[1] + p[1] / n];
+        b = [b[0] + q[0] / n, b[1] + q[1] / n];
+    }
+
+    let (mut cross, mut dot) = (0.0, 0.0);
+    for (p, q) in pairs {
+        let (p, q) = ([p[0] - a[0], p[1] - a[1]], [q[0] - b[0], q[1] - b[1]]);
+        cross += p[0] * q[1] - p[1] * q[0];
+        dot += p[0] * q[0] + p[1] * q[1];
+    }
+
+    let rotation = Pose2D::new(0.0, 0.0, cross.atan2(dot));
+    let [x, y] = rotation.transform(a);
+    Pose2D::new(b[0] - x, b[1] - y, rotation.theta)
+}
+
+/// Finds the pose of a pattern of reflective strips among the detected reflectors.
+pub struct Docking {
+    pub enabled: bool,
+    /// strip positions in the pattern frame, the robot approaches from +y
+    pattern: Vec<[f64; 2]>,
+    /// strips farther from a reflector are not matched
+    tolerance: f64,
+    min_matched: usize,
+    record: bool,
+    estimate: Option<DockingPose>,
+    recent: VecDeque<Pose2D>,
+    log: VecDeque<(String, DockingPose)>,
+    pub events: EventLog,
+    /// reflectors of the last scan, to capture the pattern
+    reflectors: Vec<Reflector>,
+    /// the sensor the reflectors were seen with
+    mounting: Mounting,
+    /// the scan the estimate belongs to
+    source: Option<Instant>,
+}
+
+impl Default for Docking {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            // asymmetric, the pose is unique
+            pattern: vec![[-0.15, 0.0], [0.0, 0.0], [0.25, 0.0]],
+            tolerance: 0.03,
+            min_matched: 3,
+            record: false,
+            estimate: None,
+            recent: VecDeque::new(),
+            log: VecDeque::new(),
+            events: Default::default(),
+            reflectors: vec![],
+            mounting: Mounting::default(),
+            source: None,
+        }
+    }
+}
+
+impl Docking {
+    pub fn reset(&mut self) {
+        self.estimate = None;
+        self.recent.clear();
+        self.reflectors.clear();
+        self.source = None;
+    }
+
+    /// Position of the sensor in the robot frame.
+    fn sensor(&self) -> [f64; 2] {
+        self.mounting.transform([0.0, 0.0])
+    }
+
+    /// Pose of the pattern relative to the sensor, as shown and logged.
+    fn in_sensor_frame(&self, pose: &Pose2D) -> Pose2D {
+        let local = self.mounting.pose().inverse().compose(pose);
+        if self.mounting.mirror {
+            // keeps +y of the pattern towards the approach
+            Pose2D::new(-local.x, local.y, -local.theta)
+        } else {
+            local
+        }
+    }
+
+    /// Best match of the pattern, hypotheses are built from pairs of strips
+    /// and pairs of reflectors with the same spacing.
+    fn find(&self, reflectors: &[[f64; 2]]) -> Option<DockingPose> {
+        let matches = |pose: &Pose2D| -> Vec<([f64; 2], [f64; 2])> {
+            self.pattern
+                .iter()
+                .filter_map(|p| {
+                    let expected = pose.transform(*p);
+                    reflectors
+                        .iter()
+                        .map(|r| (distance(expected, *r), *r))
+                        .filter(|(d, _)| *d <= self.tolerance)
+                        .min_by(|a, b| a.0.total_cmp(&b.0))
+                        .map(|(_, r)| (*p, r))
+                })
+                .collect()
+        };
+
+        let mut best: Option<DockingPose> = None;
+        for (i, p) in self.pattern.iter().enumerate() {
+            for q in &self.pattern[i + 1..] {
+                let spacing = distance(*p, *q);
+                for a in reflectors {
+                    for b in reflectors {
+                        if (distance(*a, *b) - spacing).abs() > self.tolerance {
+                            continue;
+                        }
+
+                        let pairs = matches(&fit_rigid(&[(*p, *a), (*q, *b)]));
+                        if pairs.len() < self.min_matched.max(2) {
+                            continue;
+                        }
+
+                        let pose = fit_rigid(&pairs);
+                        // the strips face the sensor
+                        if pose.inverse().transform(self.sensor())[1] <= 0.0 {
+                            continue;
+                        }
+
+                        let rms = (pairs
+                            .iter()
+                            .map(|(p, r)| distance(pose.transform(*p), *r).powi(2))
+                            .sum::<f64>()
+                            / pairs.len() as f64)
+                            .sqrt();
+                        let better = best.is_none_or(|b| (pairs.len(), -rms) > (b.matched, -b.rms));
+                        if better {
+                            best = Some(DockingPose {
+                                pose,
+                                rms,
+                                matched: pairs.len(),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        best
+    }
+
+    /// Matches the pattern with the reflectors of the scan completed at `time`,
+    /// `mounting` places the sensor on the robot.
+    pub fn update(&mut self, reflectors: &[Reflector], mounting: &Mounting, time: Instant) {
+        if !self.enabled || self.source == Some(time) {
+            return;
+        }
+        self.source = Some(time);
+        self.reflectors = reflectors.to_vec();
+        self.mounting = *mounting;
+
+        let centers: Vec<_> = reflectors.iter().map(|r| r.center).collect();
+        let estimate = self.find(&centers);
+
+        match (self.estimate, estimate) {
+            (None, Some(e)) => self.events.push(format!(
+                "Pattern found at {:.2}m, {} strips matched",
+                distance(self.sensor(), [e.pose.x, e.pose.y]),
+                e.matched
+            )),
+            (Some(_), None) => self.events.push("Pattern lost".to_owned()),
+            _ => {}
+        }
+        self.estimate = estimate;
+
+        match estimate {
+            Some(e) => {
+                self.recent.push_back(e.pose);
+                if self.recent.len() > JITTER_WINDOW {
+                    self.recent.pop_front();
+                }
+                if self.record {
+                    let logged = DockingPose {
+                        pose: self.in_sensor_frame(&e.pose),
+                        ..e
+                    };
+                    self.log.push_back((export::log_time(), logged));
+                    if self.log.len() > MAX_LOG {
+                        self.log.pop_front();
+                    }
+                }
+            }
+            None => self.recent.clear(),
+        }
+    }
+
+    /// Standard deviation of the recent positions and headings in degrees.
+    fn jitter(&self) -> Option<(f64, f64)> {
+        if self.recent.len() < 2 {
+            return None;
+        }
+
+        let n = self.recent.len() as f64;
+        let mean = |f: fn(&Pose2D) -> f64| self.recent.iter().map(f).sum::<f64>() / n;
+        let (x, y, heading) = (mean(|p| p.x), mean(|p| p.y), mean(heading_deg));
+        let position = self
+            .recent
+            .iter()
+            .map(|p| (p.x - x).powi(2) + (p.y - y).powi(2))
+            .sum::<f64>()
+            / n;
+        let heading_spread = self
+            .recent
+            .iter()
+            .map(|p| (heading_deg(p) - heading).powi(2))
+            .sum::<f64>()
+            / n;
+
+        Some((position.sqrt(), heading_spread.sqrt()))
+    }
+
+    /// Takes the current reflectors as pattern, centered on them with +y towards the sensor.
+    fn capture(&mut self) {
+        let centers: Vec<_> = self.reflectors.iter().map(|r| r.center).collect();
+        let Some(fit) = fit_line(&centers) else {
+            return;
+        };
+
+        let d = fit.direction;
+        let mut frame = Pose2D::new(fit.centroid[0], fit.centroid[1], d[1].atan2(d[0]));
+        if frame.inverse().transform(self.sensor())[1] < 0.0 {
+            frame.theta += std::f64::consts::PI;
+        }
+
+        let view = frame.inverse();
+        self.pattern = centers.iter().map(|c| view.transform(*c)).collect();
+        self.pattern.sort_by(|a, b| a[0].total_cmp(&b[0]));
+        self.min_matched = self.pattern.len();
+        self.reset();
+    }
+
+    pub fn to_csv(&self) -> String {
+        // the poses are logged relative to the sensor, like the panel shows them
+        let mut csv =
+            "time,sensor_x_m,sensor_y_m,sensor_heading_deg,sensor_distance_m,rms_m,matched\n"
+                .to_owned();
+        for (time, e) in &self.log {
+            let _ = writeln!(
+                csv,
+                "{},{:.4},{:.4},{:.2},{:.4},{:.5},{}",
+                time,
+                e.pose.x,
+                e.pose.y,
+                heading_deg(&e.pose),
+                e.pose.x.hypot(e.pose.y),
+                e.rms,
+                e.matched
+            );
+        }
+
+        csv
+    }
+
+    pub fn has_log(&self) -> bool {
+        !self.log.is_empty()
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        let before = (self.enabled, self.tolerance, self.min_matched);
+
+        ui.checkbox(&mut self.enabled, "Find pattern");
+
+        ui.label("Strips (m), the robot approaches from +y");
+        let mut remove = None;
+        for (i, p) in self.pattern.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(format!("{}", i + 1));
+                ui.add(DragValue::new(&mut p[0]).speed(0.001).prefix("x "));
+                ui.add(DragValue::new(&mut p[1]).speed(0.001).prefix("y "));
+                if ui.small_button("🗑").on_hover_text("Remove").clicked() {
+                    remove = Some(i);
+                }
+            });
+        }
+        if let Some(i) = remove {
+            self.pattern.remove(i);
+        }
+        ui.horizontal(|ui| {
+            if ui.button("Add strip").clicked() {
+                let x = self.pattern.last().map_or(0.0, |p| p[0] + 0.1);
+                self.pattern.push([x, 0.0]);
+            }
+            if ui
+                .add_enabled(self.reflectors.len() >= 2, egui::Button::new("Capture"))
+                .on_hover_text("Use the reflectors in view as pattern")
+                .clicked()
+            {
+                self.capture();
+            }
+        });
+
+        ui.add(
+            Slider::new(&mut self.tolerance, 0.005..=0.2)
+                .text("Tolerance (m)")
+                .logarithmic(true),
+        );
+        let strips = self.pattern.len().max(2);
+        self.min_matched = self.min_matched.clamp(2, strips);
+        ui.add(Slider::new(&mut self.min_matched, 2..=strips).text("Min. matched strips"));
+
+        if before != (self.enabled, self.tolerance, self.min_matched) {
+            self.source = None;
+        }
+
+        if !self.enabled {
+            return;
+        }
+
+        egui::Grid::new("docking")
+            .num_columns(2)
+            .striped(true)
+            .show(ui, |ui| match self.estimate {
+                Some(e) => {
+                    let pose = self.in_sensor_frame(&e.pose);
+                    let [x, y] = [pose.x, pose.y];
+                    ui.label("Position")
+                        .on_hover_text("Pattern origin in the sensor frame");
+                    ui.label(format!("({:.3}, {:.3})m", x, y));
+                    ui.end_row();
+                    ui.label("Distance");
+                    ui.label(format!("{:.3}m at {:.1}°", x.hypot(y), bearing_deg(x, y)));
+                    ui.end_row();
+                    ui.label("Heading");
+                    ui.label(format!("{:.1}°", heading_deg(&pose)));
+                    ui.end_row();
+                    ui.label("Matched");
+                    ui.label(format!("{} of {} strips", e.matched, self.pattern.len()));
+                    ui.end_row();
+                    ui.label("RMS");
+                    ui.label(format!("{:.1}mm", e.rms * 1e3));
+                    ui.end_row();
+                    if let Some((position, heading)) = self.jitter() {
+                        ui.label("Jitter");
+                        ui.label(format!("{:.1}mm, {:.2}°", position * 1e3, heading));
+                        ui.end_row();
+                    }
+                }
+                None => {
+                    ui.label("Pattern");
+                    ui.label("not found");
+                    ui.end_row();
+                }
+            });
+
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.record, "Record poses");
+            ui.label(format!("{} recorded", self.log.len()));
+            if ui.small_button("Clear").clicked() {
+                self.log.clear();
+            }
+        });
+        self.events.ui(ui, "docking_events");
+    }
+
+    /// The strips and the approach direction at the estimated pose.
+    fn outline(&self, e: &DockingPose) -> (Vec<[f64; 2]>, [f64; 2], [f64; 2]) {
+        let strips = self.pattern.iter().map(|p| e.pose.transform(*p)).collect();
+        let origin = e.pose.transform([0.0, 0.0]);
+        let approach = e.pose.transform([0.0, 0.3]);
+        (strips, origin, approach)
+    }
+
+    pub fn show(&self, plot_ui: &mut PlotUi) {
+        let Some(e) = self.estimate.as_ref().filter(|_| self.enabled) else {
+            return;
+        };
+
+        let (strips, origin, approach) = self.outline(e);
+        plot_ui.line(
+            Line::new(PlotPoints::new(strips.clone()))
+                .color(PATTERN_COLOR)
+                .width(2.0)
+                .name("Docking pattern"),
+        );
+        plot_ui.points(
+            Points::new(strips)
+                .radius(5.0)
+                .shape(MarkerShape::Circle)
+                .filled(false)
+                .color(PATTERN_COLOR)
+                .allow_hover(false),
+        );
+        plot_ui.arrows(
+            Arrows::new(
+                PlotPoints::new(vec![origin]),
+                PlotPoints::new(vec![approach]),
+            )
+            .color(PATTERN_COLOR),
+        );
+        plot_ui.text(
+            Text::new(
+                PlotPoint::new(approach[0], approach[1]),
+                format!("Dock {:.2}m", distance(self.sensor(), origin)),
+            )
+            .color(PATTERN_COLOR)
+            .anchor(Align2::CENTER_BOTTOM),
+        );
+    }
+
+    pub fn write_svg(&self, svg: &mut Svg) {
+        let Some(e) = self.estimate.as_ref().filter(|_| self.enabled) else {
+            return;
+        };
+
+        let (strips, origin, approach) = self.outline(e);
+        svg.polyline(&strips, 2.0, PATTERN_COLOR, false);
+        svg.polyline(&[origin, approach], 1.5, PATTERN_COLOR, false);
+        svg.text(approach, "Dock", PATTERN_COLOR);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_pose(pose: Pose2D, expected: Pose2D, tolerance: f64) {
+        let error = (pose.x - expected.x).hypot(pose.y - expected.y)
+            + normalize_deg((pose.theta - expected.theta).to_degrees())
+                .to_radians()
+                .abs();
+        assert!(error < tolerance, "{:?} != {:?}", pose, expected);
+    }
+
+    #[test]
+    fn rigid_fit_recovers_the_pose() {
+        let pattern = [[-0.15, 0.0], [0.0, 0.0], [0.25, 0.0], [0.05, 0.1]];
+        // beyond ±90° to catch a wrapped heading
+        let pose = Pose2D::new(1.2, -0.4, 150f64.to_radians());
+        let pairs: Vec<_> = pattern.iter().map(|p| (*p, pose.transform(*p))).collect();
+
+        assert_pose(fit_rigid(&pairs), pose, 1e-9);
+    }
+
+    #[test]
+    fn rigid_fit_averages_the_noise() {
+        let pattern = [[-0.15, 0.0], [0.0, 0.0], [0.25, 0.0]];
+        let pose = Pose2D::new(-0.3, 0.8, -0.4);
+        // offsets along the strips that cancel out
+        let noise = [[0.004, 0.0], [-0.008, 0.0], [0.004, 0.0]];
+        let pairs: Vec<_> = pattern
+            .iter()
+            .zip(noise)
+            .map(|(p, e)| (*p, pose.transform([p[0] + e[0], p[1] + e[1]])))
+            .collect();
+
+        let fit = fit_rigid(&pairs);
+        assert_pose(fit, pose, 0.01);
+        for (p, q) in &pairs {
+            assert!(distance(fit.transform(*p), *q) < 0.01);
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+use eframe::egui;
+
+use crate::export;
+
+/// Entries beyond this are dropped, oldest first.
+const MAX_EVENTS: usize = 500;
+
+/// Timestamped messages shown newest first.
+#[derive(Default)]
+pub struct EventLog {
+    events: VecDeque<(String, String)>,
+}
+
+impl EventLog {
+    pub fn push(&mut self, message: String) {
+        self.events.push_front((export::log_time(), message));
+        self.events.truncate(MAX_EVENTS);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// One event per line, oldest first.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for (time, message) in self.events.iter().rev() {
+            let _ = writeln!(text, "{} UTC {}", time, message);
+        }
+
+        text
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, id: &str) {
+        egui::ScrollArea::vertical()
+            .id_source(id)
+            .max_height(150.0)
+            .show(ui, |ui| {
+                for (time, message) in &self.events {
+                    ui.label(format!("{} {}", time, message));
+                }
+            });
+        if !self.events.is_empty() && ui.button("Clear log").clicked() {
+            self.events.clear();
+        }
+    }
+}
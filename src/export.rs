@@ -0,0 +1,372 @@
+use std::fmt::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use eframe::egui::{Color32, ColorImage, Rect};
+use image::codecs::png::PngEncoder;
+use image::{ExtendedColorType, ImageEncoder};
+
+/// Minimal SVG writer that maps plot coordinates (meters) onto an image.
+pub struct Svg {
+    min: [f64; 2],
+    max: [f64; 2],
+    width: f64,
+    height: f64,
+    body: String,
+}
+
+impl Svg {
+    pub fn new(min: [f64; 2], max: [f64; 2], width: f32, height: f32) -> Self {
+        Self {
+            min,
+            max,
+            width: width as f64,
+            height: height as f64,
+            body: String::new(),
+        }
+    }
+
+    fn map(&self, p: [f64; 2]) -> (f64, f64) {
+        let x = (p[0] - self.min[0]) / (self.max[0] - self.min[0]) * self.width;
+        let y = (self.max[1] - p[1]) / (self.max[1] - self.min[1]) * self.height;
+        (x, y)
+    }
+
+    pub fn rect(&mut self, color: Color32) {
+        let _ = writeln!(
+            self.body,
+            r#"<rect width="100%" height="100%" {}/>"#,
+            fill(color)
+        );
+    }
+
+    pub fn circle(&mut self, p: [f64; 2], radius: f32, color: Color32) {
+        let (x, y) = self.map(p);
+        let _ = writeln!(
+            self.body,
+            r#"<circle cx="{x:.2}" cy="{y:.2}" r="{radius}" {}/>"#,
+            fill(color)
+        );
+    }
+
+    pub fn polyline(&mut self, points: &[[f64; 2]], width: f32, color: Color32, dashed: bool) {
+        let dash = if dashed {
+            r#"stroke-dasharray="4 4" "#
+        } else {
+            ""
+        };
+        let _ = writeln!(
+            self.body,
+            r#"<polyline points="{}" fill="none" stroke-width="{width}" {dash}{}/>"#,
+            self.path(points),
+            stroke(color)
+        );
+    }
+
+    pub fn polygon(&mut self, points: &[[f64; 2]], fill_color: Color32, stroke_color: Color32) {
+        let _ = writeln!(
+            self.body,
+            r#"<polygon points="{}" {} {}/>"#,
+            self.path(points),
+            fill(fill_color),
+            stroke(stroke_color)
+        );
+    }
+
+    pub fn text(&mut self, p: [f64; 2], text: &str, color: Color32) {
+        let (x, y) = self.map(p);
+        let _ = writeln!(
+            self.body,
+            r#"<text x="{x:.2}" y="{y:.2}" font-family="sans-serif" font-size="12" {}>{}</text>"#,
+            fill(color),
+            escape(text)
+        );
+    }
+
+    /// Embeds an image as PNG, centered at `center` and rotated counter-clockwise
+    /// by `rotation` radians like a plot image.
+    pub fn image(
+        &mut self,
+        image: &ColorImage,
+        center: [f64; 2],
+        size: [f64; 2],
+        rotation: f64,
+        opacity: f32,
+    ) {
+        let [w, h] = image.size;
+        let rgba = image
+            .pixels
+            .iter()
+            .flat_map(|c| c.to_srgba_unmultiplied())
+            .collect::<Vec<_>>();
+        let mut png = vec![];
+        let encoded = PngEncoder::new(&mut png).write_image(
+            &rgba,
+            w as u32,
+            h as u32,
+            ExtendedColorType::Rgba8,
+        );
+        if encoded.is_err() {
+            return;
+        }
+
+        let (cx, cy) = self.map(center);
+        let width = size[0] / (self.max[0] - self.min[0]) * self.width;
+        let height = size[1] / (self.max[1] - self.min[1]) * self.height;
+        let _ = writeln!(
+            self.body,
+            concat!(
+                r#"<image x="{:.2}" y="{:.2}" width="{:.2}" height="{:.2}" opacity="{:.3}" "#,
+                r#"preserveAspectRatio="none" style="image-rendering:pixelated" "#,
+                r#"transform="rotate({:.2} {:.2} {:.2})" href="data:image/png;base64,{}"/>"#
+            ),
+            cx - width * 0.5,
+            cy - height * 0.5,
+            width,
+            height,
+            opacity,
+            // the y axis points down in the image
+            -rotation.to_degrees(),
+            cx,
+            cy,
+            base64(&png)
+        );
+    }
+
+    /// Draws grid lines with labels similar to the plot's axes.
+    pub fn axes(&mut self, color: Color32) {
+        for axis in 0..2 {
+            let step = nice_step((self.max[axis] - self.min[axis]) / 8.0);
+            let mut v = (self.min[axis] / step).ceil() * step;
+
+            while v <= self.max[axis] {
+                let (line, label_pos) = if axis == 0 {
+                    (
+                        [[v, self.min[1]], [v, self.max[1]]],
+                        [v, self.min[1] + (self.max[1] - self.min[1]) * 0.01],
+                    )
+                } else {
+                    (
+                        [[self.min[0], v], [self.max[0], v]],
+                        [self.min[0] + (self.max[0] - self.min[0]) * 0.01, v],
+                    )
+                };
+
+                let weight = if v.abs() < step * 1e-3 { 1.0 } else { 0.4 };
+                self.polyline(&line, 1.0, color.gamma_multiply(weight), false);
+                self.text(label_pos, &format!("{}m", round_label(v, step)), color);
+                v += step;
+            }
+        }
+    }
+
+    pub fn finish(self, title: &str, description: &str) -> String {
+        format!(
+            concat!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w:.0}" height="{h:.0}" viewBox="0 0 {w:.0} {h:.0}">"#,
+                "\n<title>{}</title>\n<desc>{}</desc>\n{}</svg>\n"
+            ),
+            escape(title),
+            escape(description),
+            self.body,
+            w = self.width,
+            h = self.height,
+        )
+    }
+
+    fn path(&self, points: &[[f64; 2]]) -> String {
+        points
+            .iter()
+            .map(|p| {
+                let (x, y) = self.map(*p);
+                format!("{x:.2},{y:.2}")
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+fn fill(color: Color32) -> String {
+    let [r, g, b, a] = color.to_srgba_unmultiplied();
+    format!(
+        r#"fill="rgb({r},{g},{b})" fill-opacity="{:.3}""#,
+        a as f32 / 255.0
+    )
+}
+
+fn stroke(color: Color32) -> String {
+    let [r, g, b, a] = color.to_srgba_unmultiplied();
+    format!(
+        r#"stroke="rgb({r},{g},{b})" stroke-opacity="{:.3}""#,
+        a as f32 / 255.0
+    )
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+
+    text
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Rounds `raw` up to 1, 2 or 5 times a power of ten.
+fn nice_step(raw: f64) -> f64 {
+    let magnitude = 10f64.powf(raw.max(1e-9).log10().floor());
+    let norm = raw / magnitude;
+    let nice = if norm <= 1.0 {
+        1.0
+    } else if norm <= 2.0 {
+        2.0
+    } else if norm <= 5.0 {
+        5.0
+    } else {
+        10.0
+    };
+
+    nice * magnitude
+}
+
+fn round_label(v: f64, step: f64) -> String {
+    let decimals = (-step.log10().floor()).max(0.0) as usize;
+    format!("{:.*}", decimals, v)
+}
+
+/// Writes the region `rect` (in points) of a screenshot as PNG.
+pub fn save_png(
+    path: &Path,
+    screenshot: &ColorImage,
+    rect: Rect,
+    pixels_per_point: f32,
+) -> Result<(), image::ImageError> {
+    let region = screenshot.region(&rect, Some(pixels_per_point));
+    let bytes = region
+        .pixels
+        .iter()
+        .flat_map(|c| c.to_srgba_unmultiplied())
+        .collect();
+
+    image::RgbaImage::from_raw(region.width() as u32, region.height() as u32, bytes)
+        .expect("pixel buffer matches the image size")
+        .save(path)
+}
+
+/// Current UTC date and time as year, month, day, hours, minutes and seconds.
+fn utc_now() -> [i64; 6] {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+
+    utc_from_unix(secs)
+}
+
+/// UTC date and time of the seconds since the unix epoch, leap seconds aside.
+fn utc_from_unix(secs: i64) -> [i64; 6] {
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+
+    // civil date from days since the unix epoch (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    [year, month, day, rem / 3600, rem % 3600 / 60, rem % 60]
+}
+
+/// Current UTC time formatted as `YYYYMMDD-hhmmss`.
+pub fn timestamp() -> String {
+    let [year, month, day, h, m, s] = utc_now();
+    format!("{:04}{:02}{:02}-{:02}{:02}{:02}", year, month, day, h, m, s)
+}
+
+/// Current UTC time formatted as `YYYY-MM-DD hh:mm:ss` for logs.
+pub fn log_time() -> String {
+    let [year, month, day, h, m, s] = utc_now();
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year, month, day, h, m, s
+    )
+}
+
+/// File name (without extension) for an export, e.g. `ld19-ttyUSB0-0001-20240101-120000`.
+pub fn file_stem(device: &[&str]) -> String {
+    let mut stem = "ld19".to_owned();
+    for part in device.iter().filter(|p| !p.is_empty()) {
+        let part: String = part
+            .rsplit(['/', '\\'])
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        stem.push('-');
+        stem.push_str(&part);
+    }
+    stem.push('-');
+    stem.push_str(&timestamp());
+
+    stem
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utc_date_of_the_epoch() {
+        assert_eq!(utc_from_unix(0), [1970, 1, 1, 0, 0, 0]);
+        assert_eq!(utc_from_unix(-1), [1969, 12, 31, 23, 59, 59]);
+    }
+
+    #[test]
+    fn utc_date_across_leap_years() {
+        // divisible by 400
+        assert_eq!(utc_from_unix(951782400), [2000, 2, 29, 0, 0, 0]);
+        assert_eq!(utc_from_unix(1709210096), [2024, 2, 29, 12, 34, 56]);
+        assert_eq!(utc_from_unix(1735689599), [2024, 12, 31, 23, 59, 59]);
+        // no February 29 in a common year
+        assert_eq!(utc_from_unix(1677628800), [2023, 3, 1, 0, 0, 0]);
+        // divisible by 100 but not by 400
+        assert_eq!(utc_from_unix(4107542399), [2100, 2, 28, 23, 59, 59]);
+        assert_eq!(utc_from_unix(4107542400), [2100, 3, 1, 0, 0, 0]);
+        assert_eq!(utc_from_unix(13574584800), [2400, 2, 29, 6, 0, 0]);
+    }
+
+    #[test]
+    fn file_stem_keeps_the_device_name() {
+        let stem = file_stem(&["/dev/ttyUSB0", "", "AB-12"]);
+
+        assert!(stem.starts_with("ld19-ttyUSB0-AB_12-"), "{}", stem);
+        assert_eq!(
+            stem.len(),
+            "ld19-ttyUSB0-AB_12-".len() + "YYYYMMDD-hhmmss".len()
+        );
+    }
+}
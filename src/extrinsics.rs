@@ -0,0 +1,470 @@
+use std::time::{Duration, Instant};
+
+use eframe::egui::{self, Color32, ComboBox, Slider};
+use egui_plot::{PlotUi, Points};
+
+use crate::export::Svg;
+use crate::geometry::{normalize_deg, Pose2D};
+use crate::icp::{Alignment, IcpParams, Reference};
+use crate::mounting::{Mounting, MountingStore};
+use crate::scan::Scan;
+use crate::sensor::Sensor;
+
+const CANDIDATE_COLOR: Color32 = Color32::from_rgb(255, 255, 80);
+
+/// Scans further apart are not considered simultaneous.
+const MAX_OFFSET: Duration = Duration::from_millis(60);
+
+/// Yaw hypotheses of the search, evenly spaced around the current mounting.
+const SEARCH_STEPS: usize = 12;
+
+/// Refined estimates beyond this are dropped, oldest first.
+const MAX_SAMPLES: usize = 100;
+
+/// Mounting of one sensor relative to another, averaged over several scan pairs.
+struct Estimate {
+    samples: Vec<Pose2D>,
+    last: Alignment,
+    /// points of the target sensor placed with the estimate
+    preview: Vec<[f64; 2]>,
+}
+
+impl Estimate {
+    fn mean(&self) -> Pose2D {
+        let n = self.samples.len() as f64;
+        let (mut x, mut y, mut sin, mut cos) = (0.0, 0.0, 0.0, 0.0);
+        for s in &self.samples {
+            x += s.x / n;
+            y += s.y / n;
+            sin += s.theta.sin();
+            cos += s.theta.cos();
+        }
+
+        Pose2D::new(x, y, sin.atan2(cos))
+    }
+
+    /// Standard deviation of the position and yaw of the samples.
+    fn spread(&self) -> (f64, f64) {
+        let mean = self.mean();
+        let n = self.samples.len() as f64;
+        let (mut position, mut yaw) = (0.0, 0.0);
+        for s in &self.samples {
+            position += ((s.x - mean.x).powi(2) + (s.y - mean.y).powi(2)) / n;
+            yaw += normalize_deg((s.theta - mean.theta).to_degrees()).powi(2) / n;
+        }
+
+        (position.sqrt(), yaw.sqrt())
+    }
+}
+
+/// Estimates the mounting of a sensor by aligning its scans with the scans of
+/// a reference sensor taken at the same time.
+pub struct Extrinsics {
+    reference: usize,
+    target: usize,
+    params: IcpParams,
+    /// try several yaws instead of starting from the current mounting only
+    search: bool,
+    /// keep aligning new scan pairs
+    refine: bool,
+    requested: bool,
+    estimate: Option<Estimate>,
+    status: Option<String>,
+    /// the scan of the target the estimate belongs to
+    source: Option<Instant>,
+}
+
+impl Default for Extrinsics {
+    fn default() -> Self {
+        Self {
+            reference: 0,
+            target: 1,
+            params: IcpParams {
+                max_correspondence: 0.5,
+                max_iterations: 50,
+                min_matches: 30,
+            },
+            search: true,
+            refine: false,
+            requested: false,
+            estimate: None,
+            status: None,
+            source: None,
+        }
+    }
+}
+
+impl Extrinsics {
+    /// Drops the estimate, e.g. when sensors are added or removed.
+    pub fn reset(&mut self) {
+        self.reference = 0;
+        self.target = 1;
+        self.refine = false;
+        self.requested = false;
+        self.estimate = None;
+        self.status = None;
+        self.source = None;
+    }
+
+    /// Aligns the latest scans of both sensors when requested or refining.
+    pub fn update(&mut self, sensors: &[Sensor]) {
+        if !self.requested && !self.refine {
+            return;
+        }
+        let (Some(reference), Some(target)) =
+            (sensors.get(self.reference), sensors.get(self.target))
+        else {
+            return;
+        };
+        let (Some(reference_scan), Some(target_scan)) = (&reference.latest, &target.latest) else {
+            return;
+        };
+
+        self.align(reference_scan, target_scan, target.mounting.pose());
+    }
+
+    /// Aligns the scans unless already done or not taken at the same time,
+    /// `guess` is the current mounting of the target.
+    fn align(&mut self, reference_scan: &Scan, target_scan: &Scan, guess: Pose2D) {
+        if self.source == Some(target_scan.completed) {
+            return;
+        }
+        let offset = target_scan
+            .completed
+            .max(reference_scan.completed)
+            .duration_since(target_scan.completed.min(reference_scan.completed));
+        if offset > MAX_OFFSET {
+            return;
+        }
+        self.source = Some(target_scan.completed);
+
+        let reference_points: Vec<_> = reference_scan.positions().collect();
+        let reference_points = Reference::new(&reference_points, self.params.max_correspondence);
+        let points = local_positions(target_scan);
+
+        if std::mem::take(&mut self.requested) {
+            let alignment = if self.search {
+                (0..SEARCH_STEPS)
+                    .map(|i| {
+                        let yaw = std::f64::consts::TAU * i as f64 / SEARCH_STEPS as f64;
+                        Pose2D::new(guess.x, guess.y, guess.theta + yaw)
+                    })
+                    .filter_map(|initial| reference_points.align(&points, initial, &self.params))
+                    .max_by(|a, b| a.matches.cmp(&b.matches).then(b.rms.total_cmp(&a.rms)))
+            } else {
+                reference_points.align(&points, guess, &self.params)
+            };
+
+            match alignment {
+                Some(alignment) => {
+                    self.estimate = Some(Estimate {
+                        samples: vec![alignment.pose],
+                        last: alignment,
+                        preview: vec![],
+                    });
+                    self.status = None;
+                }
+                None => {
+                    self.estimate = None;
+                    self.status = Some("The scans do not overlap enough".to_owned());
+                }
+            }
+        } else if let Some(estimate) = &mut self.estimate {
+            match reference_points.align(&points, estimate.mean(), &self.params) {
+                Some(alignment) => {
+                    estimate.samples.push(alignment.pose);
+                    if estimate.samples.len() > MAX_SAMPLES {
+                        estimate.samples.remove(0);
+                    }
+                    estimate.last = alignment;
+                }
+                None => self.status = Some("Refinement failed for a scan pair".to_owned()),
+            }
+        }
+
+        if let Some(estimate) = &mut self.estimate {
+            let pose = estimate.mean();
+            estimate.preview = points.iter().map(|p| pose.transform(*p)).collect();
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, sensors: &mut [Sensor], mountings: &mut MountingStore) {
+        if sensors.len() < 2 {
+            ui.label("Connect two sensors with overlapping views");
+            return;
+        }
+
+        let before = (self.reference, self.target);
+        for (label, index) in [
+            ("Reference", &mut self.reference),
+            ("Target", &mut self.target),
+        ] {
+            ComboBox::from_label(label)
+                .selected_text(sensors.get(*index).map_or("-", |s| s.port.as_str()))
+                .show_ui(ui, |ui| {
+                    for (i, sensor) in sensors.iter().enumerate() {
+                        ui.selectable_value(index, i, &sensor.port);
+                    }
+                });
+        }
+        if before != (self.reference, self.target) {
+            self.estimate = None;
+            self.refine = false;
+        }
+
+        ui.add(
+            Slider::new(&mut self.params.max_correspondence, 0.05..=2.0)
+                .text("Max. match distance (m)"),
+        );
+        ui.add(Slider::new(&mut self.params.max_iterations, 1..=200).text("Max. iterations"));
+        ui.add(Slider::new(&mut self.params.min_matches, 3..=200).text("Min. matches"));
+        ui.checkbox(&mut self.search, "Search yaw")
+            .on_hover_text("Try several headings instead of starting from the current mounting");
+
+        let valid = self.reference != self.target
+            && self.reference < sensors.len()
+            && self.target < sensors.len();
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(valid, egui::Button::new("Estimate"))
+                .on_hover_text("Align the next simultaneous scans, keep the robot still")
+                .clicked()
+            {
+                self.requested = true;
+                self.refine = false;
+                self.status = Some("Waiting for simultaneous scans".to_owned());
+            }
+            ui.add_enabled(
+                self.estimate.is_some(),
+                egui::Checkbox::new(&mut self.refine, "Refine"),
+            )
+            .on_hover_text("Average the alignment of the following scan pairs");
+        });
+
+        if let Some(estimate) = &self.estimate {
+            let mut candidate = sensors[self.target].mounting;
+            candidate.set_pose(estimate.mean());
+            let (position_spread, yaw_spread) = estimate.spread();
+
+            egui::Grid::new("extrinsics")
+                .num_columns(2)
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("Position");
+                    ui.label(format!("({:.3}, {:.3})m", candidate.x, candidate.y));
+                    ui.end_row();
+                    ui.label("Yaw");
+                    ui.label(format!("{:.2}°", candidate.yaw_deg));
+                    ui.end_row();
+                    ui.label("Scan pairs");
+                    ui.label(format!("{}", estimate.samples.len()));
+                    ui.end_row();
+                    ui.label("Spread");
+                    ui.label(format!(
+                        "{:.1}mm, {:.2}°",
+                        position_spread * 1e3,
+                        yaw_spread
+                    ));
+                    ui.end_row();
+                    ui.label("Last match");
+                    ui.label(format!(
+                        "{} points, {:.1}mm rms",
+                        estimate.last.matches,
+                        estimate.last.rms * 1e3
+                    ));
+                });
+
+            let mut accept = false;
+            ui.horizontal(|ui| {
+                accept = ui
+                    .button("Accept")
+                    .on_hover_text("Use and save the estimated mounting of the target")
+                    .clicked();
+                if ui.button("Discard").clicked() {
+                    self.estimate = None;
+                    self.refine = false;
+                    self.status = None;
+                }
+            });
+
+            if accept {
+                let target = &mut sensors[self.target];
+                target.mounting = candidate;
+                self.status = Some(match mountings.save(&target.key, candidate) {
+                    Ok(()) => format!("Saved the mounting of {}", target.port),
+                    Err(err) => format!("Cannot save the mounting: {}", err),
+                });
+                self.estimate = None;
+                self.refine = false;
+            }
+        }
+
+        if let Some(status) = &self.status {
+            ui.label(status);
+        }
+    }
+
+    pub fn show(&self, plot_ui: &mut PlotUi) {
+        let Some(estimate) = &self.estimate else {
+            return;
+        };
+
+        plot_ui.points(
+            Points::new(estimate.preview.clone())
+                .radius(2.0)
+                .color(CANDIDATE_COLOR)
+                .name("Candidate mounting"),
+        );
+    }
+
+    pub fn write_svg(&self, svg: &mut Svg) {
+        let Some(estimate) = &self.estimate else {
+            return;
+        };
+
+        for p in &estimate.preview {
+            svg.circle(*p, 2.0, CANDIDATE_COLOR);
+        }
+    }
+}
+
+/// Positions of the points in the sensor frame, mirrored like the mounting of the scan.
+fn local_positions(scan: &Scan) -> Vec<[f64; 2]> {
+    let mirror = Mounting {
+        mirror: scan.mounting.mirror,
+        ..Default::default()
+    };
+
+    scan.points
+        .iter()
+        .filter(|p| p.distance > 0.0)
+        .map(|p| mirror.transform(p.position()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn estimate(samples: Vec<Pose2D>) -> Estimate {
+        Estimate {
+            last: Alignment {
+                pose: samples[0],
+                rms: 0.0,
+                matches: 0,
+                iterations: 0,
+            },
+            samples,
+            preview: vec![],
+        }
+    }
+
+    /// Range from `pose` along the bearing to the walls of a 4m × 3.5m room.
+    fn cast(pose: Pose2D, bearing_deg: f64) -> f32 {
+        let rad = bearing_deg.to_radians();
+        let origin = pose.transform([0.0, 0.0]);
+        let end = pose.transform([rad.sin(), rad.cos()]);
+        let d = [end[0] - origin[0], end[1] - origin[1]];
+        let (lo, hi) = ([-2.0, -1.0], [2.0, 2.5]);
+
+        (0..2)
+            .filter(|axis| d[*axis] != 0.0)
+            .map(|axis| {
+                let bound = if d[axis] > 0.0 { hi[axis] } else { lo[axis] };
+                (bound - origin[axis]) / d[axis]
+            })
+            .fold(f64::INFINITY, f64::min) as f32
+    }
+
+    /// Scan of a sensor at `pose` in the robot frame, in its own frame.
+    fn scan(pose: Pose2D) -> Scan {
+        Scan::from_readings((0..360).map(|angle| (angle as f32, cast(pose, angle as f64), 200)))
+    }
+
+    #[test]
+    fn mean_yaw_wraps_around() {
+        let deg = |d: f64| d.to_radians();
+        let estimate = estimate(vec![
+            Pose2D::new(1.0, 0.0, deg(179.0)),
+            Pose2D::new(2.0, 0.0, deg(-179.0)),
+            Pose2D::new(3.0, 0.0, deg(-178.0)),
+        ]);
+
+        let mean = estimate.mean();
+        assert!((mean.x - 2.0).abs() < 1e-9);
+        assert!(
+            normalize_deg(mean.theta.to_degrees() + 179.33).abs() < 0.01,
+            "{:?}",
+            mean
+        );
+
+        let (position, yaw) = estimate.spread();
+        assert!(
+            (position - (2.0f64 / 3.0).sqrt()).abs() < 1e-9,
+            "{}",
+            position
+        );
+        assert!((yaw - 1.247).abs() < 0.01, "{}", yaw);
+    }
+
+    #[test]
+    fn identical_samples_do_not_spread() {
+        let pose = Pose2D::new(0.1, -0.2, 3.1);
+        let estimate = estimate(vec![pose; 5]);
+
+        let mean = estimate.mean();
+        assert!((mean.x - pose.x).abs() < 1e-12 && (mean.y - pose.y).abs() < 1e-12);
+        assert!((mean.theta - pose.theta).abs() < 1e-9);
+        let (position, yaw) = estimate.spread();
+        assert!(position < 1e-9 && yaw < 1e-6, "{} {}", position, yaw);
+    }
+
+    #[test]
+    fn recovers_the_mounting_of_the_target() {
+        let truth = Pose2D::new(0.25, -0.15, 12f64.to_radians());
+        let mut extrinsics = Extrinsics {
+            requested: true,
+            refine: true,
+            ..Default::default()
+        };
+
+        let reference = scan(Pose2D::default());
+        extrinsics.align(&reference, &scan(truth), Pose2D::default());
+        // refined with a second pair
+        let mut target = scan(truth);
+        target.completed = reference.completed + Duration::from_millis(10);
+        extrinsics.align(&reference, &target, Pose2D::default());
+
+        let estimate = extrinsics.estimate.as_ref().expect("aligned");
+        assert_eq!(estimate.samples.len(), 2);
+        let mean = estimate.mean();
+        assert!(
+            (mean.x - truth.x).hypot(mean.y - truth.y) < 0.01,
+            "{:?}",
+            mean
+        );
+        assert!(
+            (mean.theta - truth.theta).abs() < 0.5f64.to_radians(),
+            "{:?}",
+            mean
+        );
+        assert_eq!(estimate.preview.len(), 360);
+    }
+
+    #[test]
+    fn scans_apart_in_time_are_not_aligned() {
+        let mut extrinsics = Extrinsics {
+            requested: true,
+            ..Default::default()
+        };
+        let reference = scan(Pose2D::default());
+        let mut target = scan(Pose2D::default());
+        target.completed = reference.completed + MAX_OFFSET * 2;
+        extrinsics.align(&reference, &target, Pose2D::default());
+
+        assert!(extrinsics.estimate.is_none());
+        assert!(extrinsics.requested);
+    }
+}
@@ -0,0 +1,30 @@
+use eframe::egui::{self, Slider};
+
+use crate::pipeline::ScanFilter;
+use crate::scan::Scan;
+
+/// Drops points with a weak return.
+pub struct IntensityFilter {
+    threshold: f32,
+}
+
+impl Default for IntensityFilter {
+    fn default() -> Self {
+        Self { threshold: 0.1 }
+    }
+}
+
+impl ScanFilter for IntensityFilter {
+    fn name(&self) -> &'static str {
+        "Intensity"
+    }
+
+    fn apply(&mut self, scan: &mut Scan) {
+        scan.points
+            .retain(|p| p.point.normalized_intensity() > self.threshold);
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.add(Slider::new(&mut self.threshold, 0.0..=1.0).text("Intensity threshold"));
+    }
+}
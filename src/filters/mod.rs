@@ -0,0 +1,27 @@
+use crate::pipeline::ScanFilter;
+
+mod intensity;
+mod outliers;
+mod region;
+mod smoothing;
+
+pub use intensity::IntensityFilter;
+pub use outliers::{RadiusOutlierFilter, StatisticalOutlierFilter};
+pub use region::RegionFilter;
+pub use smoothing::{MedianFilter, TemporalFilter};
+
+pub type FilterFactory = fn() -> Box<dyn ScanFilter>;
+
+/// All filters that can be added to the processing pipeline.
+pub fn available() -> Vec<(&'static str, FilterFactory)> {
+    vec![
+        ("Intensity", || Box::<IntensityFilter>::default()),
+        ("Region", || Box::<RegionFilter>::default()),
+        ("Median", || Box::<MedianFilter>::default()),
+        ("Temporal", || Box::<TemporalFilter>::default()),
+        ("Statistical outliers", || {
+            Box::<StatisticalOutlierFilter>::default()
+        }),
+        ("Radius outliers", || Box::<RadiusOutlierFilter>::default()),
+    ]
+}
@@ -0,0 +1,101 @@
+use eframe::egui::{self, Slider};
+
+use crate::geometry::distance;
+use crate::pipeline::ScanFilter;
+use crate::scan::Scan;
+
+/// Removes points whose mean distance to their nearest neighbours is unusually large.
+pub struct StatisticalOutlierFilter {
+    neighbours: usize,
+    /// points further than `mean + std_ratio * std` are outliers
+    std_ratio: f32,
+}
+
+impl Default for StatisticalOutlierFilter {
+    fn default() -> Self {
+        Self {
+            neighbours: 6,
+            std_ratio: 1.5,
+        }
+    }
+}
+
+impl ScanFilter for StatisticalOutlierFilter {
+    fn name(&self) -> &'static str {
+        "Statistical outliers"
+    }
+
+    fn apply(&mut self, scan: &mut Scan) {
+        let positions: Vec<_> = scan.positions().collect();
+        if positions.len() <= self.neighbours {
+            return;
+        }
+
+        let mut dists = Vec::with_capacity(positions.len());
+        let mean_knn: Vec<f64> = positions
+            .iter()
+            .map(|p| {
+                dists.clear();
+                dists.extend(positions.iter().map(|q| distance(*p, *q)));
+                // the first entry is the point itself
+                dists.select_nth_unstable_by(self.neighbours, f64::total_cmp);
+                dists[..=self.neighbours].iter().sum::<f64>() / self.neighbours as f64
+            })
+            .collect();
+
+        let n = mean_knn.len() as f64;
+        let mean = mean_knn.iter().sum::<f64>() / n;
+        let std = (mean_knn.iter().map(|d| (d - mean).powi(2)).sum::<f64>() / n).sqrt();
+        let limit = mean + self.std_ratio as f64 * std;
+
+        let mut keep = mean_knn.iter().map(|d| *d <= limit);
+        scan.points.retain(|_| keep.next().unwrap_or(true));
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.add(Slider::new(&mut self.neighbours, 1..=20).text("Neighbours"));
+        ui.add(Slider::new(&mut self.std_ratio, 0.1..=5.0).text("Std. deviations"))
+            .on_hover_text("Points further than mean + n·σ from their neighbours are removed");
+    }
+}
+
+/// Removes points with too few neighbours within a radius.
+pub struct RadiusOutlierFilter {
+    radius: f32,
+    min_neighbours: usize,
+}
+
+impl Default for RadiusOutlierFilter {
+    fn default() -> Self {
+        Self {
+            radius: 0.05,
+            min_neighbours: 2,
+        }
+    }
+}
+
+impl ScanFilter for RadiusOutlierFilter {
+    fn name(&self) -> &'static str {
+        "Radius outliers"
+    }
+
+    fn apply(&mut self, scan: &mut Scan) {
+        let positions: Vec<_> = scan.positions().collect();
+        let radius = self.radius as f64;
+
+        let mut keep = positions.iter().map(|p| {
+            positions
+                .iter()
+                .filter(|q| distance(*p, **q) <= radius)
+                .count()
+                // the point itself is not a neighbour
+                > self.min_neighbours
+        });
+        scan.points.retain(|_| keep.next().unwrap_or(true));
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.add(Slider::new(&mut self.radius, 0.01..=0.5).text("Radius (m)"));
+        ui.add(Slider::new(&mut self.min_neighbours, 1..=10).text("Min. neighbours"));
+    }
+}
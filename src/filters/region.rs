@@ -0,0 +1,275 @@
+use eframe::egui::{self, Color32, DragValue, Slider};
+use egui_plot::{Line, LineStyle, PlotPoint, PlotPoints, PlotUi, Points, Polygon};
+
+use crate::export::Svg;
+use crate::geometry::point_in_polygon;
+use crate::mounting::Mounting;
+use crate::overlay::{arc, sector_span, sector_wedges, MAX_RANGE};
+use crate::pipeline::ScanFilter;
+use crate::scan::Scan;
+
+const REGION_COLOR: Color32 = Color32::LIGHT_RED;
+
+/// Distance in screen pixels to the first corner that closes the polygon.
+const CLOSE_RADIUS_PX: f32 = 12.0;
+
+/// Crops the scan to a range band, a polygon and masks out angular sectors,
+/// e.g. to hide the robot's own body.
+pub struct RegionFilter {
+    min_range: f32,
+    max_range: f32,
+    /// excluded sectors, from the first bearing clockwise to the second
+    masks: Vec<[f64; 2]>,
+    /// points outside are removed, ignored if empty
+    roi: Vec<[f64; 2]>,
+    drawing: bool,
+    show_region: bool,
+}
+
+impl Default for RegionFilter {
+    fn default() -> Self {
+        Self {
+            min_range: 0.0,
+            max_range: MAX_RANGE as f32,
+            masks: vec![],
+            roi: vec![],
+            drawing: false,
+            show_region: true,
+        }
+    }
+}
+
+impl RegionFilter {
+    /// The bearing lies in a masked sector, the sectors include both bounds.
+    fn is_masked(&self, bearing: f64) -> bool {
+        self.masks.iter().any(|[from, to]| {
+            let span = sector_span(*from, *to);
+            span > 0.0 && (bearing - from).rem_euclid(360.0) <= span
+        })
+    }
+}
+
+impl ScanFilter for RegionFilter {
+    fn name(&self) -> &'static str {
+        "Region"
+    }
+
+    fn apply(&mut self, scan: &mut Scan) {
+        let roi_active = self.roi.len() >= 3 && !self.drawing;
+
+        let mut positions = scan.positions().collect::<Vec<_>>().into_iter();
+
+        scan.points.retain(|p| {
+            let pos = positions.next().unwrap_or_default();
+            (self.min_range..=self.max_range).contains(&p.distance)
+                && !self.is_masked(p.angle as f64)
+                && (!roi_active || point_in_polygon(pos, &self.roi))
+        });
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.add(Slider::new(&mut self.min_range, 0.0..=MAX_RANGE as f32).text("Min range (m)"));
+        ui.add(Slider::new(&mut self.max_range, 0.0..=MAX_RANGE as f32).text("Max range (m)"));
+
+        ui.label("Masked sectors (θ from → to, clockwise)");
+        let mut remove = None;
+        for (i, [from, to]) in self.masks.iter_mut().enumerate() {
+            let before = [*from, *to];
+            ui.horizontal(|ui| {
+                ui.add(DragValue::new(from).range(-180.0..=180.0).suffix("°"));
+                ui.label("→");
+                ui.add(DragValue::new(to).range(-180.0..=180.0).suffix("°"));
+                if ui.small_button("🗑").clicked() {
+                    remove = Some(i);
+                }
+            });
+            // an empty sector masks nothing, keep the previous bounds
+            if from == to {
+                [*from, *to] = before;
+            }
+        }
+        if let Some(i) = remove {
+            self.masks.remove(i);
+        }
+        if ui.button("Add mask").clicked() {
+            self.masks.push([150.0, -150.0]);
+        }
+
+        ui.label(format!("Polygon ROI ({} corners)", self.roi.len()));
+        ui.horizontal(|ui| {
+            if self.drawing {
+                if ui.button("Finish").clicked() {
+                    self.drawing = false;
+                }
+            } else if ui
+                .button("Draw")
+                .on_hover_text("Click the corners on the plot")
+                .clicked()
+            {
+                self.roi.clear();
+                self.drawing = true;
+            }
+            if ui.button("Clear").clicked() {
+                self.roi.clear();
+                self.drawing = false;
+            }
+        });
+        ui.checkbox(&mut self.show_region, "Show on plot");
+    }
+
+    fn show(&mut self, plot_ui: &mut PlotUi, mounting: &Mounting, click: &mut Option<PlotPoint>) {
+        if self.drawing {
+            if let Some(pointer) = click.take() {
+                let closes = self.roi.first().is_some_and(|first| {
+                    let first = plot_ui.screen_from_plot(PlotPoint::new(first[0], first[1]));
+                    let pos = plot_ui.screen_from_plot(pointer);
+                    self.roi.len() >= 3 && first.distance(pos) < CLOSE_RADIUS_PX
+                });
+
+                if closes {
+                    self.drawing = false;
+                } else {
+                    self.roi.push([pointer.x, pointer.y]);
+                }
+            }
+        }
+
+        if !self.show_region && !self.drawing {
+            return;
+        }
+
+        // masks and range limits apply to the sensor frame, the ROI to the robot frame
+        let place = |points: Vec<[f64; 2]>| -> Vec<[f64; 2]> {
+            points.into_iter().map(|p| mounting.transform(p)).collect()
+        };
+        for [from, to] in &self.masks {
+            for wedge in sector_wedges(MAX_RANGE, *from, *to) {
+                plot_ui.polygon(
+                    Polygon::new(PlotPoints::new(place(wedge)))
+                        .fill_color(REGION_COLOR.gamma_multiply(0.1))
+                        .stroke((0.0, Color32::TRANSPARENT))
+                        .allow_hover(false),
+                );
+            }
+        }
+
+        for r in [self.min_range, self.max_range] {
+            if r > 0.0 && r < MAX_RANGE as f32 {
+                plot_ui.line(
+                    Line::new(PlotPoints::new(place(arc(r as f64, -180.0, 180.0))))
+                        .color(REGION_COLOR.gamma_multiply(0.5))
+                        .style(LineStyle::dashed_loose())
+                        .allow_hover(false),
+                );
+            }
+        }
+
+        if !self.roi.is_empty() {
+            let mut outline = self.roi.clone();
+            if self.drawing {
+                if let Some(pointer) = plot_ui.pointer_coordinate() {
+                    outline.push([pointer.x, pointer.y]);
+                }
+            } else {
+                outline.push(self.roi[0]);
+            }
+
+            plot_ui.line(
+                Line::new(PlotPoints::new(outline))
+                    .color(REGION_COLOR)
+                    .allow_hover(false),
+            );
+            plot_ui.points(
+                Points::new(self.roi.clone())
+                    .radius(3.0)
+                    .color(REGION_COLOR)
+                    .allow_hover(false),
+            );
+        }
+    }
+
+    fn write_svg(&self, svg: &mut Svg, mounting: &Mounting) {
+        if !self.show_region && !self.drawing {
+            return;
+        }
+
+        let place = |points: Vec<[f64; 2]>| -> Vec<[f64; 2]> {
+            points.into_iter().map(|p| mounting.transform(p)).collect()
+        };
+        for [from, to] in &self.masks {
+            for wedge in sector_wedges(MAX_RANGE, *from, *to) {
+                svg.polygon(
+                    &place(wedge),
+                    REGION_COLOR.gamma_multiply(0.1),
+                    Color32::TRANSPARENT,
+                );
+            }
+        }
+
+        for r in [self.min_range, self.max_range] {
+            if r > 0.0 && r < MAX_RANGE as f32 {
+                let arc = place(arc(r as f64, -180.0, 180.0));
+                svg.polyline(&arc, 1.0, REGION_COLOR.gamma_multiply(0.5), true);
+            }
+        }
+
+        if !self.roi.is_empty() {
+            let mut outline = self.roi.clone();
+            if !self.drawing {
+                outline.push(self.roi[0]);
+            }
+            svg.polyline(&outline, 1.0, REGION_COLOR, false);
+            for p in &self.roi {
+                svg.circle(*p, 3.0, REGION_COLOR);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn masks(masks: &[[f64; 2]]) -> RegionFilter {
+        RegionFilter {
+            masks: masks.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn mask_wraps_around_behind_the_sensor() {
+        let filter = masks(&[[150.0, -150.0]]);
+
+        for bearing in [150.0, 170.0, 180.0, -180.0, -170.0, -150.0, 190.0] {
+            assert!(filter.is_masked(bearing), "{}", bearing);
+        }
+        for bearing in [0.0, 140.0, -140.0, 220.0] {
+            assert!(!filter.is_masked(bearing), "{}", bearing);
+        }
+        // drawn over the same 60°
+        let wedges = sector_wedges(1.0, 150.0, -150.0);
+        assert_eq!(wedges.len(), 1);
+        assert_eq!(sector_span(150.0, -150.0), 60.0);
+    }
+
+    #[test]
+    fn empty_mask_hides_nothing() {
+        let filter = masks(&[[30.0, 30.0]]);
+
+        for bearing in [30.0, 29.0, 31.0, -150.0] {
+            assert!(!filter.is_masked(bearing), "{}", bearing);
+        }
+        assert!(sector_wedges(1.0, 30.0, 30.0).is_empty());
+    }
+
+    #[test]
+    fn full_mask_hides_everything() {
+        let filter = masks(&[[-180.0, 180.0]]);
+
+        for bearing in [-180.0, -90.0, 0.0, 90.0, 180.0] {
+            assert!(filter.is_masked(bearing), "{}", bearing);
+        }
+        assert_eq!(sector_wedges(1.0, -180.0, 180.0).len(), 4);
+    }
+}
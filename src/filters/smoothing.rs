@@ -0,0 +1,164 @@
+use std::collections::VecDeque;
+
+use eframe::egui::{self, Slider};
+
+use crate::pipeline::ScanFilter;
+use crate::scan::Scan;
+
+/// Replaces each range by the median of its angular neighbours.
+pub struct MedianFilter {
+    /// number of neighbours on each side
+    half_window: usize,
+}
+
+impl Default for MedianFilter {
+    fn default() -> Self {
+        Self { half_window: 1 }
+    }
+}
+
+impl ScanFilter for MedianFilter {
+    fn name(&self) -> &'static str {
+        "Median"
+    }
+
+    fn apply(&mut self, scan: &mut Scan) {
+        let n = scan.points.len();
+        if n == 0 {
+            return;
+        }
+
+        let distances: Vec<_> = scan.points.iter().map(|p| p.distance).collect();
+        let k = self.half_window.min(n / 2);
+        let mut window = Vec::with_capacity(2 * k + 1);
+
+        for (i, p) in scan.points.iter_mut().enumerate() {
+            if p.distance <= 0.0 {
+                continue; // no return
+            }
+
+            // the scan covers the full circle, hence wrap around
+            window.clear();
+            window.extend(
+                (0..=2 * k)
+                    .map(|j| distances[(i + n + j - k) % n])
+                    .filter(|d| *d > 0.0),
+            );
+            p.distance = median(&mut window);
+        }
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.add(Slider::new(&mut self.half_window, 1..=5).text("Neighbours"))
+            .on_hover_text("Number of points on each side included in the median");
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TemporalMode {
+    Exponential,
+    Median,
+}
+
+/// Smooths the range of each angular bin over consecutive scans.
+pub struct TemporalFilter {
+    mode: TemporalMode,
+    bin_size_deg: f32,
+    /// weight of the newest reading for the exponential mode
+    alpha: f32,
+    /// number of scans for the median mode
+    depth: usize,
+    /// readings jumping further than this reset the bin, to keep up with motion
+    jump_threshold: f32,
+    bins: Vec<VecDeque<f32>>,
+}
+
+impl Default for TemporalFilter {
+    fn default() -> Self {
+        Self {
+            mode: TemporalMode::Exponential,
+            bin_size_deg: 0.5,
+            alpha: 0.3,
+            depth: 5,
+            jump_threshold: 0.2,
+            bins: vec![],
+        }
+    }
+}
+
+impl ScanFilter for TemporalFilter {
+    fn name(&self) -> &'static str {
+        "Temporal"
+    }
+
+    fn apply(&mut self, scan: &mut Scan) {
+        let bin_count = (360.0 / self.bin_size_deg).ceil() as usize;
+        if self.bins.len() != bin_count {
+            self.bins = vec![VecDeque::new(); bin_count];
+        }
+
+        for p in scan.points.iter_mut().filter(|p| p.distance > 0.0) {
+            let bin = &mut self.bins[(p.angle / self.bin_size_deg) as usize % bin_count];
+
+            if bin
+                .front()
+                .map(|d| (d - p.distance).abs() > self.jump_threshold)
+                .unwrap_or(false)
+            {
+                bin.clear();
+            }
+
+            match self.mode {
+                TemporalMode::Exponential => {
+                    // a single filtered value per bin
+                    let filtered = bin
+                        .front()
+                        .map(|d| d + self.alpha * (p.distance - d))
+                        .unwrap_or(p.distance);
+                    bin.clear();
+                    bin.push_front(filtered);
+                    p.distance = filtered;
+                }
+                TemporalMode::Median => {
+                    bin.push_front(p.distance);
+                    bin.truncate(self.depth);
+                    p.distance = median(&mut bin.iter().copied().collect::<Vec<_>>());
+                }
+            }
+        }
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.mode, TemporalMode::Exponential, "Exponential");
+            ui.selectable_value(&mut self.mode, TemporalMode::Median, "Median");
+        });
+        match self.mode {
+            TemporalMode::Exponential => {
+                ui.add(Slider::new(&mut self.alpha, 0.05..=1.0).text("Alpha"))
+                    .on_hover_text("Weight of the newest reading");
+            }
+            TemporalMode::Median => {
+                ui.add(Slider::new(&mut self.depth, 2..=20).text("Depth (scans)"));
+            }
+        }
+        ui.add(Slider::new(&mut self.bin_size_deg, 0.25..=5.0).text("Bin size (°)"));
+        ui.add(Slider::new(&mut self.jump_threshold, 0.01..=1.0).text("Jump threshold (m)"))
+            .on_hover_text("Larger changes are taken as is to follow moving objects");
+    }
+
+    fn reset(&mut self) {
+        self.bins.clear();
+    }
+}
+
+fn median(values: &mut [f32]) -> f32 {
+    if values.is_empty() {
+        return 0.0;
+    }
+
+    let mid = values.len() / 2;
+    let (_, m, _) = values.select_nth_unstable_by(mid, f32::total_cmp);
+
+    *m
+}
@@ -0,0 +1,514 @@
+//! Small 2D geometry helpers shared by the tools and processing stages.
+
+pub fn distance(a: [f64; 2], b: [f64; 2]) -> f64 {
+    (a[0] - b[0]).hypot(a[1] - b[1])
+}
+
+/// Angle between `a` and `b` seen from `vertex` in degrees.
+pub fn angle_deg(a: [f64; 2], vertex: [f64; 2], b: [f64; 2]) -> f64 {
+    let u = [a[0] - vertex[0], a[1] - vertex[1]];
+    let v = [b[0] - vertex[0], b[1] - vertex[1]];
+    let cross = u[0] * v[1] - u[1] * v[0];
+    let dot = u[0] * v[0] + u[1] * v[1];
+
+    cross.abs().atan2(dot).to_degrees()
+}
+
+/// Area of a simple polygon (shoelace formula).
+pub fn polygon_area(poly: &[[f64; 2]]) -> f64 {
+    let sum: f64 = poly
+        .iter()
+        .zip(poly.iter().cycle().skip(1))
+        .map(|(a, b)| a[0] * b[1] - b[0] * a[1])
+        .sum();
+
+    sum.abs() * 0.5
+}
+
+pub fn polygon_perimeter(poly: &[[f64; 2]]) -> f64 {
+    poly.iter()
+        .zip(poly.iter().cycle().skip(1))
+        .map(|(a, b)| distance(*a, *b))
+        .sum()
+}
+
+/// Distance of `p` to the segment from `a` to `b`.
+pub fn segment_distance(p: [f64; 2], a: [f64; 2], b: [f64; 2]) -> f64 {
+    let ab = [b[0] - a[0], b[1] - a[1]];
+    let len2 = ab[0] * ab[0] + ab[1] * ab[1];
+    let t = if len2 > 0.0 {
+        (((p[0] - a[0]) * ab[0] + (p[1] - a[1]) * ab[1]) / len2).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    (p[0] - a[0] - t * ab[0]).hypot(p[1] - a[1] - t * ab[1])
+}
+
+/// Ray casting test, `poly` is implicitly closed.
+pub fn point_in_polygon(p: [f64; 2], poly: &[[f64; 2]]) -> bool {
+    let mut inside = false;
+
+    for (a, b) in poly.iter().zip(poly.iter().cycle().skip(1)) {
+        if (a[1] > p[1]) != (b[1] > p[1]) {
+            let x = a[0] + (p[1] - a[1]) / (b[1] - a[1]) * (b[0] - a[0]);
+            if p[0] < x {
+                inside = !inside;
+            }
+        }
+    }
+
+    inside
+}
+
+/// The polygon has at least three corners and turns in one direction only.
+pub fn is_convex(poly: &[[f64; 2]]) -> bool {
+    let n = poly.len();
+    if n < 3 {
+        return false;
+    }
+
+    let turns = (0..n).map(|i| {
+        let [a, b, c] = [poly[i], poly[(i + 1) % n], poly[(i + 2) % n]];
+        (b[0] - a[0]) * (c[1] - b[1]) - (b[1] - a[1]) * (c[0] - b[0])
+    });
+    let (mut left, mut right) = (false, false);
+    for turn in turns {
+        left |= turn > 0.0;
+        right |= turn < 0.0;
+    }
+
+    !(left && right)
+}
+
+/// Wraps an angle into `-180°..180°`.
+pub fn normalize_deg(deg: f64) -> f64 {
+    (deg + 180.0).rem_euclid(360.0) - 180.0
+}
+
+/// A rigid 2D transform, `theta` is counterclockwise in radians.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Pose2D {
+    pub x: f64,
+    pub y: f64,
+    pub theta: f64,
+}
+
+impl Pose2D {
+    pub fn new(x: f64, y: f64, theta: f64) -> Self {
+        Self { x, y, theta }
+    }
+
+    pub fn transform(&self, p: [f64; 2]) -> [f64; 2] {
+        let (sin, cos) = self.theta.sin_cos();
+        [
+            cos * p[0] - sin * p[1] + self.x,
+            sin * p[0] + cos * p[1] + self.y,
+        ]
+    }
+
+    pub fn inverse(&self) -> Pose2D {
+        let (sin, cos) = self.theta.sin_cos();
+        Pose2D::new(
+            -cos * self.x - sin * self.y,
+            sin * self.x - cos * self.y,
+            -self.theta,
+        )
+    }
+
+    /// The transform applying `other` first, then `self`.
+    pub fn compose(&self, other: &Pose2D) -> Pose2D {
+        let [x, y] = self.transform([other.x, other.y]);
+        Pose2D::new(x, y, self.theta + other.theta)
+    }
+}
+
+/// Total least squares line fit.
+#[derive(Debug, Clone, Copy)]
+pub struct LineFit {
+    pub centroid: [f64; 2],
+    /// unit direction
+    pub direction: [f64; 2],
+    /// root mean square of the perpendicular distances
+    pub rms: f64,
+}
+
+impl LineFit {
+    pub fn normal(&self) -> [f64; 2] {
+        [-self.direction[1], self.direction[0]]
+    }
+
+    /// Signed perpendicular distance of `p` to the line.
+    pub fn distance_to(&self, p: [f64; 2]) -> f64 {
+        let n = self.normal();
+        (p[0] - self.centroid[0]) * n[0] + (p[1] - self.centroid[1]) * n[1]
+    }
+
+    /// Position of the projection of `p` along the line, relative to the centroid.
+    pub fn project(&self, p: [f64; 2]) -> f64 {
+        (p[0] - self.centroid[0]) * self.direction[0]
+            + (p[1] - self.centroid[1]) * self.direction[1]
+    }
+
+    pub fn point_at(&self, t: f64) -> [f64; 2] {
+        [
+            self.centroid[0] + t * self.direction[0],
+            self.centroid[1] + t * self.direction[1],
+        ]
+    }
+}
+
+pub fn fit_line(points: &[[f64; 2]]) -> Option<LineFit> {
+    if points.len() < 2 {
+        return None;
+    }
+
+    let n = points.len() as f64;
+    let cx = points.iter().map(|p| p[0]).sum::<f64>() / n;
+    let cy = points.iter().map(|p| p[1]).sum::<f64>() / n;
+
+    let (mut sxx, mut syy, mut sxy) = (0.0, 0.0, 0.0);
+    for p in points {
+        let (dx, dy) = (p[0] - cx, p[1] - cy);
+        sxx += dx * dx;
+        syy += dy * dy;
+        sxy += dx * dy;
+    }
+
+    // principal axis of the covariance
+    let angle = 0.5 * (2.0 * sxy).atan2(sxx - syy);
+    let direction = [angle.cos(), angle.sin()];
+
+    let mut fit = LineFit {
+        centroid: [cx, cy],
+        direction,
+        rms: 0.0,
+    };
+    fit.rms = (points
+        .iter()
+        .map(|p| fit.distance_to(*p).powi(2))
+        .sum::<f64>()
+        / n)
+        .sqrt();
+
+    Some(fit)
+}
+
+/// Algebraic (Kåsa) circle fit.
+#[derive(Debug, Clone, Copy)]
+pub struct CircleFit {
+    pub center: [f64; 2],
+    pub radius: f64,
+    /// root mean square of the radial distances
+    pub rms: f64,
+}
+
+pub fn fit_circle(points: &[[f64; 2]]) -> Option<CircleFit> {
+    if points.len() < 3 {
+        return None;
+    }
+
+    // relative to the centroid for numerical stability
+    let n = points.len() as f64;
+    let cx = points.iter().map(|p| p[0]).sum::<f64>() / n;
+    let cy = points.iter().map(|p| p[1]).sum::<f64>() / n;
+
+    // x² + y² + a·x + b·y + c = 0
+    let mut m = vec![vec![0.0; 4]; 3];
+    for p in points {
+        let (x, y) = (p[0] - cx, p[1] - cy);
+        let row = [x, y, 1.0];
+        let z = -(x * x + y * y);
+        for (r, ri) in m.iter_mut().zip(row) {
+            for (c, rc) in row.iter().enumerate() {
+                r[c] += ri * rc;
+            }
+            r[3] += ri * z;
+        }
+    }
+    let [a, b, c] = solve_linear(m)?[..] else {
+        return None;
+    };
+
+    let center = [-a * 0.5, -b * 0.5];
+    let radius_squared = center[0] * center[0] + center[1] * center[1] - c;
+    if radius_squared <= 0.0 {
+        return None;
+    }
+
+    let radius = radius_squared.sqrt();
+    let center = [center[0] + cx, center[1] + cy];
+    let rms = (points
+        .iter()
+        .map(|p| (distance(*p, center) - radius).powi(2))
+        .sum::<f64>()
+        / n)
+        .sqrt();
+
+    Some(CircleFit {
+        center,
+        radius,
+        rms,
+    })
+}
+
+/// Rectangle aligned with the principal axis of a point set.
+#[derive(Debug, Clone, Copy)]
+pub struct OrientedBox {
+    pub center: [f64; 2],
+    /// unit direction of the long side
+    pub axis: [f64; 2],
+    /// half of the length and width
+    pub half_extents: [f64; 2],
+}
+
+impl OrientedBox {
+    pub fn from_points(points: &[[f64; 2]]) -> Option<Self> {
+        let axis = match points {
+            [] => return None,
+            [_] => [1.0, 0.0],
+            _ => fit_line(points)?.direction,
+        };
+        let normal = [-axis[1], axis[0]];
+        let dot = |p: &[f64; 2], d: [f64; 2]| p[0] * d[0] + p[1] * d[1];
+
+        let mut lo = [f64::INFINITY; 2];
+        let mut hi = [f64::NEG_INFINITY; 2];
+        for p in points {
+            for (k, d) in [axis, normal].into_iter().enumerate() {
+                lo[k] = lo[k].min(dot(p, d));
+                hi[k] = hi[k].max(dot(p, d));
+            }
+        }
+
+        let mid = [(lo[0] + hi[0]) * 0.5, (lo[1] + hi[1]) * 0.5];
+        Some(Self {
+            center: [
+                mid[0] * axis[0] + mid[1] * normal[0],
+                mid[0] * axis[1] + mid[1] * normal[1],
+            ],
+            axis,
+            half_extents: [(hi[0] - lo[0]) * 0.5, (hi[1] - lo[1]) * 0.5],
+        })
+    }
+
+    pub fn corners(&self) -> [[f64; 2]; 4] {
+        let [a, n] = [self.axis, [-self.axis[1], self.axis[0]]];
+        let [l, w] = self.half_extents;
+        let corner = |s: f64, t: f64| {
+            [
+                self.center[0] + s * l * a[0] + t * w * n[0],
+                self.center[1] + s * l * a[1] + t * w * n[1],
+            ]
+        };
+
+        [
+            corner(-1.0, -1.0),
+            corner(1.0, -1.0),
+            corner(1.0, 1.0),
+            corner(-1.0, 1.0),
+        ]
+    }
+}
+
+/// Solves the linear system given as augmented `n × (n + 1)` matrix.
+pub fn solve_linear(mut a: Vec<Vec<f64>>) -> Option<Vec<f64>> {
+    let n = a.len();
+
+    // gaussian elimination with partial pivoting
+    for col in 0..n {
+        let pivot = (col..n).max_by(|i, j| a[*i][col].abs().total_cmp(&a[*j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+
+        let pivot_row = a[col].clone();
+        for (row, r) in a.iter_mut().enumerate() {
+            if row != col {
+                let factor = r[col] / pivot_row[col];
+                for (v, p) in r.iter_mut().zip(&pivot_row).skip(col) {
+                    *v -= factor * p;
+                }
+            }
+        }
+    }
+
+    Some(a.iter().enumerate().map(|(i, r)| r[n] / r[i]).collect())
+}
+
+/// Symmetric positive definite matrix storing the lower triangle of each row
+/// from its first non-zero column, the Cholesky factor fills in within these
+/// profiles only. Sparse systems with short rows are solved in about linear time.
+pub struct SkylineMatrix {
+    /// first stored column of each row
+    first: Vec<usize>,
+    rows: Vec<Vec<f64>>,
+}
+
+impl SkylineMatrix {
+    /// Zero matrix, `first[i] <= i` is the first non-zero column of row `i`.
+    pub fn new(first: Vec<usize>) -> Self {
+        let rows = first
+            .iter()
+            .enumerate()
+            .map(|(i, f)| vec![0.0; i + 1 - f])
+            .collect();
+
+        Self { first, rows }
+    }
+
+    /// Adds to the element `(i, j)` and its mirror, which must lie within the profile.
+    pub fn add(&mut self, i: usize, j: usize, value: f64) {
+        let (i, j) = (i.max(j), i.min(j));
+        self.rows[i][j - self.first[i]] += value;
+    }
+
+    /// Solves `A x = b` by the Cholesky factorization, none if not positive definite.
+    pub fn solve(mut self, b: &[f64]) -> Option<Vec<f64>> {
+        let first = &self.first;
+
+        // A = L Lᵀ, L replaces the rows
+        for i in 0..self.rows.len() {
+            let (done, rest) = self.rows.split_at_mut(i);
+            let row = &mut rest[0];
+            for j in first[i]..=i {
+                let start = first[i].max(first[j]);
+                let other = if j < i { &done[j] } else { &*row };
+                let sum = row[j - first[i]]
+                    - (start..j)
+                        .map(|k| row[k - first[i]] * other[k - first[j]])
+                        .sum::<f64>();
+
+                if j < i {
+                    row[j - first[i]] = sum / done[j][j - first[j]];
+                } else if sum > 1e-12 {
+                    row[i - first[i]] = sum.sqrt();
+                } else {
+                    return None;
+                }
+            }
+        }
+
+        // L y = b, then Lᵀ x = y
+        let mut x = b.to_vec();
+        for (i, row) in self.rows.iter().enumerate() {
+            let sum: f64 = (first[i]..i).map(|k| row[k - first[i]] * x[k]).sum();
+            x[i] = (x[i] - sum) / row[i - first[i]];
+        }
+        for (i, row) in self.rows.iter().enumerate().rev() {
+            x[i] /= row[i - first[i]];
+            for k in first[i]..i {
+                x[k] -= row[k - first[i]] * x[i];
+            }
+        }
+
+        Some(x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_fit_follows_the_points() {
+        let points: Vec<_> = (0..10)
+            .map(|i| {
+                let x = i as f64 * 0.1;
+                [x, 0.5 * x + 1.0]
+            })
+            .collect();
+        let fit = fit_line(&points).expect("enough points");
+
+        // parallel to (2, 1) either way round
+        let cross = fit.direction[0] * 1.0 - fit.direction[1] * 2.0;
+        assert!(cross.abs() < 1e-9, "{:?}", fit.direction);
+        assert!(fit.rms < 1e-9);
+        // signed, the side depends on the direction
+        assert!(fit.distance_to([0.0, 1.0]).abs() < 1e-9);
+        assert!((fit.distance_to([0.0, 0.0]).abs() - 2.0 / 5f64.sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn line_fit_of_a_vertical_wall() {
+        let points = [[2.0, -1.0], [2.02, 0.0], [1.98, 1.0], [2.0, 2.0]];
+        let fit = fit_line(&points).expect("enough points");
+
+        assert!(fit.direction[0].abs() < 0.02, "{:?}", fit.direction);
+        assert!((fit.centroid[0] - 2.0).abs() < 1e-9);
+        assert!(fit.rms > 0.005 && fit.rms < 0.02, "{}", fit.rms);
+        assert!(fit_line(&points[..1]).is_none());
+    }
+
+    #[test]
+    fn circle_fit_of_a_visible_arc() {
+        // the side of a post facing the sensor
+        let points: Vec<_> = (0..12)
+            .map(|i| {
+                let angle = (200.0 + i as f64 * 10.0).to_radians();
+                [3.0 + 0.4 * angle.cos(), -2.0 + 0.4 * angle.sin()]
+            })
+            .collect();
+        let fit = fit_circle(&points).expect("an arc");
+
+        assert!(distance(fit.center, [3.0, -2.0]) < 1e-9, "{:?}", fit.center);
+        assert!((fit.radius - 0.4).abs() < 1e-9, "{}", fit.radius);
+        assert!(fit.rms < 1e-9);
+    }
+
+    #[test]
+    fn circle_fit_rejects_degenerate_points() {
+        assert!(fit_circle(&[[0.0, 0.0], [1.0, 1.0]]).is_none());
+
+        let collinear: Vec<_> = (0..5).map(|i| [i as f64, 2.0 * i as f64]).collect();
+        assert!(fit_circle(&collinear).is_none());
+    }
+
+    #[test]
+    fn skyline_matches_dense_solve() {
+        // a chain with one long link, like a pose graph with a loop closure
+        let n = 6;
+        let mut dense = vec![vec![0.0; n + 1]; n];
+        let mut first: Vec<_> = (0..n).map(|i| i.saturating_sub(1)).collect();
+        first[5] = 0;
+        let mut skyline = SkylineMatrix::new(first);
+
+        let mut link = |i: usize, j: usize, w: f64| {
+            for (a, b, v) in [(i, i, w), (j, j, w), (i, j, -w)] {
+                skyline.add(a, b, v);
+                dense[a][b] += v;
+                if a != b {
+                    dense[b][a] += v;
+                }
+            }
+        };
+        for i in 0..n - 1 {
+            link(i, i + 1, 1.0 + i as f64);
+        }
+        link(0, 5, 0.5);
+        skyline.add(0, 0, 10.0);
+        dense[0][0] += 10.0;
+
+        let b = [1.0, -2.0, 0.5, 3.0, 0.0, -1.0];
+        for (row, v) in dense.iter_mut().zip(b) {
+            row[n] = v;
+        }
+
+        let expected = solve_linear(dense).expect("regular");
+        let x = skyline.solve(&b).expect("positive definite");
+        for (a, b) in x.iter().zip(expected) {
+            assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+        }
+    }
+
+    #[test]
+    fn skyline_rejects_singular() {
+        let mut skyline = SkylineMatrix::new(vec![0, 0]);
+        skyline.add(0, 0, 1.0);
+        skyline.add(1, 0, -1.0);
+        skyline.add(1, 1, 1.0);
+
+        assert!(skyline.solve(&[1.0, 1.0]).is_none());
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+
+use crate::geometry::{distance, fit_line, solve_linear, Pose2D};
+
+/// Neighbours on each side used to estimate the normal of a point.
+const NORMAL_NEIGHBOURS: usize = 2;
+
+/// Normals of curved or noisy surfaces are not reliable.
+const MAX_NORMAL_RMS: f64 = 0.02;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IcpParams {
+    /// pairs farther apart are not matched
+    pub max_correspondence: f64,
+    pub max_iterations: usize,
+    pub min_matches: usize,
+}
+
+impl Default for IcpParams {
+    fn default() -> Self {
+        Self {
+            max_correspondence: 0.3,
+            max_iterations: 30,
+            min_matches: 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Alignment {
+    pub pose: Pose2D,
+    /// root mean square of the point to line distances
+    pub rms: f64,
+    pub matches: usize,
+    pub iterations: usize,
+}
+
+/// Points with normals to align other scans to, indexed by a hash grid.
+pub struct Reference {
+    points: Vec<[f64; 2]>,
+    normals: Vec<[f64; 2]>,
+    cell_size: f64,
+    cells: HashMap<[i64; 2], Vec<usize>>,
+}
+
+impl Reference {
+    /// `points` are ordered by angle, as in a scan.
+    pub fn new(points: &[[f64; 2]], cell_size: f64) -> Self {
+        Self::from_runs([points], cell_size)
+    }
+
+    /// Each run is ordered by angle, e.g. the points of one sensor,
+    /// the normals are estimated within the runs.
+    pub fn from_runs<'a>(runs: impl IntoIterator<Item = &'a [[f64; 2]]>, cell_size: f64) -> Self {
+        let mut reference = Self {
+            points: vec![],
+            normals: vec![],
+            cell_size,
+            cells: HashMap::new(),
+        };
+
+        for points in runs {
+            reference.add_run(points);
+        }
+
+        reference
+    }
+
+    fn add_run(&mut self, points: &[[f64; 2]]) {
+        let cell_size = self.cell_size;
+        for (i, p) in points.iter().enumerate() {
+            let from = i.saturating_sub(NORMAL_NEIGHBOURS);
+            let to = (i + NORMAL_NEIGHBOURS + 1).min(points.len());
+            let neighbours: Vec<_> = points[from..to]
+                .iter()
+                .filter(|q| distance(*p, **q) <= cell_size)
+                .copied()
+                .collect();
+
+            if let Some(fit) =
+                fit_line(&neighbours).filter(|f| neighbours.len() >= 3 && f.rms <= MAX_NORMAL_RMS)
+            {
+                let index = self.points.len();
+                self.points.push(*p);
+                self.normals.push(fit.normal());
+                let cell = self.cell(*p);
+                self.cells.entry(cell).or_default().push(index);
+            }
+        }
+    }
+
+    fn cell(&self, p: [f64; 2]) -> [i64; 2] {
+        [
+            (p[0] / self.cell_size).floor() as i64,
+            (p[1] / self.cell_size).floor() as i64,
+        ]
+    }
+
+    fn nearest(&self, p: [f64; 2], max_distance: f64) -> Option<usize> {
+        let [cx, cy] = self.cell(p);
+        let mut best = None;
+        let mut best_distance = max_distance;
+
+        for x in cx - 1..=cx + 1 {
+            for y in cy - 1..=cy + 1 {
+                for i in self.cells.get(&[x, y]).into_iter().flatten() {
+                    let d = distance(p, self.points[*i]);
+                    if d <= best_distance {
+                        best_distance = d;
+                        best = Some(*i);
+                    }
+                }
+            }
+        }
+
+        best
+    }
+
+    /// Point-to-line ICP, finds the pose that maps `points` onto the reference
+    /// starting from the guess `initial`.
+    pub fn align(
+        &self,
+        points: &[[f64; 2]],
+        initial: Pose2D,
+        params: &IcpParams,
+    ) -> Option<Alignment> {
+        let mut pose = initial;
+        let max_distance = params.max_correspondence.min(self.cell_size);
+
+        for iteration in 1..=params.max_iterations {
+            // normal equations of the linearized problem in (x, y, θ)
+            let mut a = vec![vec![0.0; 4]; 3];
+            let mut squared = 0.0;
+            let mut matches = 0;
+
+            for p in points {
+                let p = pose.transform(*p);
+                let Some(i) = self.nearest(p, max_distance) else {
+                    continue;
+                };
+
+                let (q, n) = (self.points[i], self.normals[i]);
+                let r = n[0] * (p[0] - q[0]) + n[1] * (p[1] - q[1]);
+                let j = [n[0], n[1], n[1] * p[0] - n[0] * p[1]];
+                for (row, jr) in a.iter_mut().zip(j) {
+                    for (col, jc) in j.iter().enumerate() {
+                        row[col] += jr * jc;
+                    }
+                    row[3] -= jr * r;
+                }
+
+                squared += r * r;
+                matches += 1;
+            }
+
+            if matches < params.min_matches {
+                return None;
+            }
+
+            let delta = solve_linear(a)?;
+            pose = Pose2D::new(delta[0], delta[1], delta[2]).compose(&pose);
+
+            let converged = delta[0].hypot(delta[1]) < 1e-4 && delta[2].abs() < 1e-4;
+            if converged || iteration == params.max_iterations {
+                return Some(Alignment {
+                    pose,
+                    rms: (squared / matches as f64).sqrt(),
+                    matches,
+                    iterations: iteration,
+                });
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Walls of a 4m × 3m room around the origin, 2cm apart and in order.
+    fn room() -> Vec<[f64; 2]> {
+        let corners = [
+            [-2.0, -1.0],
+            [2.0, -1.0],
+            [2.0, 2.0],
+            [-2.0, 2.0],
+            [-2.0, -1.0],
+        ];
+        corners
+            .windows(2)
+            .flat_map(|w| {
+                let steps = (distance(w[0], w[1]) / 0.02).round() as usize;
+                (0..steps).map(move |i| {
+                    let t = i as f64 / steps as f64;
+                    [
+                        w[0][0] + t * (w[1][0] - w[0][0]),
+                        w[0][1] + t * (w[1][1] - w[0][1]),
+                    ]
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn align_recovers_a_rigid_offset() {
+        let room = room();
+        let reference = Reference::new(&room, 0.3);
+        let offset = Pose2D::new(0.12, -0.07, 4f64.to_radians());
+        // the room as seen from the offset pose
+        let view = offset.inverse();
+        let points: Vec<_> = room.iter().map(|p| view.transform(*p)).collect();
+
+        let alignment = reference
+            .align(&points, Pose2D::default(), &IcpParams::default())
+            .expect("overlapping scans");
+
+        assert!(
+            (alignment.pose.x - offset.x).abs() < 1e-3,
+            "{:?}",
+            alignment.pose
+        );
+        assert!(
+            (alignment.pose.y - offset.y).abs() < 1e-3,
+            "{:?}",
+            alignment.pose
+        );
+        assert!(
+            (alignment.pose.theta - offset.theta).abs() < 1e-3,
+            "{:?}",
+            alignment.pose
+        );
+        assert!(alignment.rms < 1e-3, "{}", alignment.rms);
+        assert!(alignment.iterations < IcpParams::default().max_iterations);
+    }
+
+    #[test]
+    fn align_fails_without_overlap() {
+        let room = room();
+        let reference = Reference::new(&room, 0.3);
+        let far: Vec<_> = room.iter().map(|p| [p[0] + 20.0, p[1]]).collect();
+
+        assert!(reference
+            .align(&far, Pose2D::default(), &IcpParams::default())
+            .is_none());
+    }
+}
@@ -0,0 +1,516 @@
+use std::time::Instant;
+
+use eframe::egui::{self, Align2, Color32, Slider};
+use egui_plot::{Line, MarkerShape, PlotPoint, PlotPoints, PlotUi, Points, Text};
+
+use crate::clustering::Cluster;
+use crate::export::Svg;
+use crate::geometry::{angle_deg, distance, fit_circle};
+use crate::lines::Segment;
+use crate::overlay::arc;
+use crate::scan::Scan;
+
+const CORNER_COLOR: Color32 = Color32::from_rgb(255, 255, 255);
+const CIRCLE_COLOR: Color32 = Color32::from_rgb(120, 255, 120);
+const REFLECTOR_COLOR: Color32 = Color32::from_rgb(255, 255, 0);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Corner {
+    pub position: [f64; 2],
+    /// angle between both walls
+    pub angle_deg: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Circle {
+    pub center: [f64; 2],
+    pub radius: f64,
+    pub rms: f64,
+    pub points: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Reflector {
+    pub center: [f64; 2],
+    /// distance between the outermost points
+    pub width: f64,
+    /// mean normalized intensity
+    pub intensity: f64,
+    pub points: usize,
+}
+
+/// Intersection of the lines through both segments, if it is close to an end of each.
+fn corner(a: &Segment, b: &Segment, max_gap: f64, min_angle_deg: f64) -> Option<Corner> {
+    let (p, d) = (a.fit.centroid, a.fit.direction);
+    let (q, e) = (b.fit.centroid, b.fit.direction);
+    let cross = d[0] * e[1] - d[1] * e[0];
+    if cross.abs() < 1e-6 {
+        return None;
+    }
+
+    let t = ((q[0] - p[0]) * e[1] - (q[1] - p[1]) * e[0]) / cross;
+    let position = a.fit.point_at(t);
+
+    // the far ends span the angle
+    let near = |s: &Segment| {
+        let (near, far) = if distance(s.start, position) < distance(s.end, position) {
+            (s.start, s.end)
+        } else {
+            (s.end, s.start)
+        };
+        (distance(near, position) <= max_gap).then_some(far)
+    };
+    let angle = angle_deg(near(a)?, position, near(b)?);
+
+    (angle >= min_angle_deg && angle <= 180.0 - min_angle_deg).then_some(Corner {
+        position,
+        angle_deg: angle,
+    })
+}
+
+/// Runs of consecutive high intensity points.
+pub fn find_reflectors(
+    scan: &Scan,
+    min_intensity: f32,
+    max_gap: f64,
+    min_points: usize,
+) -> Vec<Reflector> {
+    let mut runs: Vec<Vec<([f64; 2], f32)>> = vec![];
+
+    // the points of different sensors are never consecutive
+    for sensor_points in scan.sensor_runs() {
+        let first_run = runs.len();
+        let mut previous: Option<[f64; 2]> = None;
+        // the first point of the rotation starts a run, it may continue the last one
+        let mut first: Option<[f64; 2]> = None;
+
+        for (i, p) in sensor_points.iter().enumerate() {
+            let intensity = p.point.normalized_intensity();
+            if p.distance <= 0.0 || intensity < min_intensity {
+                previous = None;
+                continue;
+            }
+
+            let position = scan.position(p);
+            if i == 0 {
+                first = Some(position);
+            }
+            match (runs.last_mut(), previous) {
+                (Some(run), Some(previous)) if distance(previous, position) <= max_gap => {
+                    run.push((position, intensity))
+                }
+                _ => runs.push(vec![(position, intensity)]),
+            }
+            previous = Some(position);
+        }
+
+        // join the runs meeting at the 0°/360° seam, the last one comes first in order
+        if let (Some(first), Some(last)) = (first, previous) {
+            if runs.len() - first_run >= 2 && distance(last, first) <= max_gap {
+                let mut run = runs.pop().expect("two runs");
+                run.append(&mut runs[first_run]);
+                runs[first_run] = run;
+            }
+        }
+    }
+
+    runs.into_iter()
+        .filter(|r| r.len() >= min_points)
+        .map(|run| {
+            let n = run.len() as f64;
+            Reflector {
+                center: [
+                    run.iter().map(|(p, _)| p[0]).sum::<f64>() / n,
+                    run.iter().map(|(p, _)| p[1]).sum::<f64>() / n,
+                ],
+                width: distance(run[0].0, run[run.len() - 1].0),
+                intensity: run.iter().map(|(_, i)| *i as f64).sum::<f64>() / n,
+                points: run.len(),
+            }
+        })
+        .collect()
+}
+
+/// Finds corners between the lines, cylinders among the clusters and retro-reflectors.
+pub struct Landmarks {
+    pub enabled: bool,
+    detect_corners: bool,
+    /// the segment ends may be this far from the intersection
+    max_corner_gap: f64,
+    /// corners sharper or flatter than this are ignored
+    min_corner_angle_deg: f64,
+    detect_circles: bool,
+    min_radius: f64,
+    max_radius: f64,
+    max_circle_rms: f64,
+    min_circle_points: usize,
+    detect_reflectors: bool,
+    min_intensity: f32,
+    max_reflector_gap: f64,
+    min_reflector_points: usize,
+    corners: Vec<Corner>,
+    circles: Vec<Circle>,
+    reflectors: Vec<Reflector>,
+    /// the scan the detections belong to
+    source: Option<Instant>,
+}
+
+impl Default for Landmarks {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            detect_corners: true,
+            max_corner_gap: 0.15,
+            min_corner_angle_deg: 45.0,
+            detect_circles: true,
+            min_radius: 0.02,
+            max_radius: 0.5,
+            max_circle_rms: 0.01,
+            min_circle_points: 5,
+            detect_reflectors: true,
+            min_intensity: 0.8,
+            max_reflector_gap: 0.05,
+            min_reflector_points: 2,
+            corners: vec![],
+            circles: vec![],
+            reflectors: vec![],
+            source: None,
+        }
+    }
+}
+
+impl Landmarks {
+    pub fn reset(&mut self) {
+        self.corners.clear();
+        self.circles.clear();
+        self.reflectors.clear();
+        self.source = None;
+    }
+
+    /// Reflectors of the last processed scan, empty while disabled.
+    pub fn reflectors(&self) -> &[Reflector] {
+        if self.enabled {
+            &self.reflectors
+        } else {
+            &[]
+        }
+    }
+
+    /// Detects the landmarks in the scan, its line segments and clusters, unless already done.
+    pub fn update(&mut self, scan: &Scan, segments: &[Segment], clusters: &[Cluster]) {
+        if !self.enabled || self.source == Some(scan.completed) {
+            return;
+        }
+        self.source = Some(scan.completed);
+
+        self.corners.clear();
+        if self.detect_corners {
+            for (i, a) in segments.iter().enumerate() {
+                for b in &segments[i + 1..] {
+                    self.corners.extend(corner(
+                        a,
+                        b,
+                        self.max_corner_gap,
+                        self.min_corner_angle_deg,
+                    ));
+                }
+            }
+        }
+
+        self.circles.clear();
+        if self.detect_circles {
+            let sensor = scan.mounting.transform([0.0, 0.0]);
+            for cluster in clusters
+                .iter()
+                .filter(|c| c.points.len() >= self.min_circle_points)
+            {
+                let Some(fit) = fit_circle(&cluster.points) else {
+                    continue;
+                };
+
+                // the sensor sees the near side of a cylinder
+                let convex = distance(fit.center, sensor) > distance(cluster.centroid, sensor);
+                if convex
+                    && (self.min_radius..=self.max_radius).contains(&fit.radius)
+                    && fit.rms <= self.max_circle_rms
+                {
+                    self.circles.push(Circle {
+                        center: fit.center,
+                        radius: fit.radius,
+                        rms: fit.rms,
+                        points: cluster.points.len(),
+                    });
+                }
+            }
+        }
+
+        self.reflectors = if self.detect_reflectors {
+            find_reflectors(
+                scan,
+                self.min_intensity,
+                self.max_reflector_gap,
+                self.min_reflector_points,
+            )
+        } else {
+            vec![]
+        };
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        let before = (
+            self.enabled,
+            self.detect_corners,
+            self.max_corner_gap,
+            self.min_corner_angle_deg,
+            self.detect_circles,
+            (self.min_radius, self.max_radius, self.max_circle_rms),
+            self.min_circle_points,
+            self.detect_reflectors,
+            self.min_intensity,
+            self.max_reflector_gap,
+            self.min_reflector_points,
+        );
+
+        ui.checkbox(&mut self.enabled, "Detect landmarks");
+
+        ui.checkbox(&mut self.detect_corners, "Corners")
+            .on_hover_text("Intersections of the extracted lines");
+        if self.detect_corners {
+            ui.add(
+                Slider::new(&mut self.max_corner_gap, 0.01..=0.5).text("Max. gap to corner (m)"),
+            );
+            ui.add(
+                Slider::new(&mut self.min_corner_angle_deg, 5.0..=89.0)
+                    .text("Min. corner angle (°)"),
+            );
+        }
+
+        ui.checkbox(&mut self.detect_circles, "Circles")
+            .on_hover_text("Cylinders fitted to the clusters");
+        if self.detect_circles {
+            ui.add(
+                Slider::new(&mut self.min_radius, 0.01..=1.0)
+                    .text("Min. radius (m)")
+                    .logarithmic(true),
+            );
+            ui.add(
+                Slider::new(&mut self.max_radius, 0.01..=2.0)
+                    .text("Max. radius (m)")
+                    .logarithmic(true),
+            );
+            ui.add(
+                Slider::new(&mut self.max_circle_rms, 0.001..=0.05)
+                    .text("Max. fit error (m)")
+                    .logarithmic(true),
+            );
+            ui.add(Slider::new(&mut self.min_circle_points, 3..=50).text("Min. points"));
+        }
+
+        ui.checkbox(&mut self.detect_reflectors, "Reflectors")
+            .on_hover_text("Runs of high intensity points");
+        if self.detect_reflectors {
+            ui.add(Slider::new(&mut self.min_intensity, 0.0..=1.0).text("Min. intensity"));
+            ui.add(Slider::new(&mut self.max_reflector_gap, 0.01..=0.3).text("Max. point gap (m)"));
+            ui.add(Slider::new(&mut self.min_reflector_points, 1..=20).text("Min. points"));
+        }
+
+        // detect again with the new parameters
+        if before
+            != (
+                self.enabled,
+                self.detect_corners,
+                self.max_corner_gap,
+                self.min_corner_angle_deg,
+                self.detect_circles,
+                (self.min_radius, self.max_radius, self.max_circle_rms),
+                self.min_circle_points,
+                self.detect_reflectors,
+                self.min_intensity,
+                self.max_reflector_gap,
+                self.min_reflector_points,
+            )
+        {
+            self.source = None;
+        }
+
+        if !self.enabled {
+            return;
+        }
+
+        egui::ScrollArea::vertical()
+            .id_source("landmarks")
+            .max_height(200.0)
+            .show(ui, |ui| {
+                egui::Grid::new("landmarks")
+                    .num_columns(3)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.label("Landmark");
+                        ui.label("Position");
+                        ui.label("Parameters");
+                        ui.end_row();
+                        for (i, c) in self.corners.iter().enumerate() {
+                            ui.label(format!("Corner {}", i + 1));
+                            ui.label(format!("({:.2}, {:.2})", c.position[0], c.position[1]));
+                            ui.label(format!("{:.1}°", c.angle_deg));
+                            ui.end_row();
+                        }
+                        for (i, c) in self.circles.iter().enumerate() {
+                            ui.label(format!("Circle {}", i + 1));
+                            ui.label(format!("({:.2}, {:.2})", c.center[0], c.center[1]));
+                            ui.label(format!(
+                                "r {:.3}m, {:.1}mm rms, {} points",
+                                c.radius,
+                                c.rms * 1e3,
+                                c.points
+                            ));
+                            ui.end_row();
+                        }
+                        for (i, r) in self.reflectors.iter().enumerate() {
+                            ui.label(format!("Reflector {}", i + 1));
+                            ui.label(format!("({:.2}, {:.2})", r.center[0], r.center[1]));
+                            ui.label(format!(
+                                "{:.3}m wide, intensity {:.2}, {} points",
+                                r.width, r.intensity, r.points
+                            ));
+                            ui.end_row();
+                        }
+                    });
+            });
+    }
+
+    fn circle_outline(circle: &Circle) -> Vec<[f64; 2]> {
+        let [x, y] = circle.center;
+        arc(circle.radius, -180.0, 180.0)
+            .into_iter()
+            .map(|[dx, dy]| [x + dx, y + dy])
+            .collect()
+    }
+
+    pub fn show(&self, plot_ui: &mut PlotUi) {
+        if !self.enabled {
+            return;
+        }
+
+        for (i, c) in self.corners.iter().enumerate() {
+            plot_ui.points(
+                Points::new(vec![c.position])
+                    .radius(5.0)
+                    .shape(MarkerShape::Square)
+                    .filled(false)
+                    .color(CORNER_COLOR)
+                    .name(format!("Corner {}", i + 1)),
+            );
+            plot_ui.text(
+                Text::new(
+                    PlotPoint::new(c.position[0], c.position[1]),
+                    format!("{:.0}°", c.angle_deg),
+                )
+                .color(CORNER_COLOR)
+                .anchor(Align2::LEFT_BOTTOM),
+            );
+        }
+
+        for (i, c) in self.circles.iter().enumerate() {
+            plot_ui.line(
+                Line::new(PlotPoints::new(Self::circle_outline(c)))
+                    .color(CIRCLE_COLOR)
+                    .width(2.0)
+                    .name(format!("Circle {}", i + 1)),
+            );
+            plot_ui.points(
+                Points::new(vec![c.center])
+                    .radius(3.0)
+                    .shape(MarkerShape::Cross)
+                    .color(CIRCLE_COLOR)
+                    .allow_hover(false),
+            );
+            plot_ui.text(
+                Text::new(
+                    PlotPoint::new(c.center[0], c.center[1] + c.radius),
+                    format!("r {:.3}m", c.radius),
+                )
+                .color(CIRCLE_COLOR)
+                .anchor(Align2::CENTER_BOTTOM),
+            );
+        }
+
+        for (i, r) in self.reflectors.iter().enumerate() {
+            plot_ui.points(
+                Points::new(vec![r.center])
+                    .radius(6.0)
+                    .shape(MarkerShape::Asterisk)
+                    .color(REFLECTOR_COLOR)
+                    .name(format!("Reflector {}", i + 1)),
+            );
+            plot_ui.text(
+                Text::new(
+                    PlotPoint::new(r.center[0], r.center[1]),
+                    format!("R{} {:.2}", i + 1, r.intensity),
+                )
+                .color(REFLECTOR_COLOR)
+                .anchor(Align2::LEFT_TOP),
+            );
+        }
+    }
+
+    pub fn write_svg(&self, svg: &mut Svg) {
+        if !self.enabled {
+            return;
+        }
+
+        for c in &self.corners {
+            svg.circle(c.position, 4.0, CORNER_COLOR);
+            svg.text(c.position, &format!("{:.0}°", c.angle_deg), CORNER_COLOR);
+        }
+        for c in &self.circles {
+            svg.polyline(&Self::circle_outline(c), 2.0, CIRCLE_COLOR, false);
+        }
+        for (i, r) in self.reflectors.iter().enumerate() {
+            svg.circle(r.center, 5.0, REFLECTOR_COLOR);
+            svg.text(r.center, &format!("R{}", i + 1), REFLECTOR_COLOR);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A rotation at 2m with one degree steps, bright where `bright` holds.
+    fn ring(bright: impl Fn(u16) -> bool) -> Scan {
+        Scan::from_readings((0..360u16).map(|angle| {
+            let intensity = if bright(angle) { 250 } else { 20 };
+            (angle as f32, 2.0, intensity)
+        }))
+    }
+
+    #[test]
+    fn reflector_across_the_seam_is_one() {
+        let scan = ring(|angle| !(5..355).contains(&angle));
+        let reflectors = find_reflectors(&scan, 0.5, 0.1, 3);
+
+        assert_eq!(reflectors.len(), 1);
+        let reflector = &reflectors[0];
+        assert_eq!(reflector.points, 10);
+        // straight ahead, slightly inside the ring
+        assert!(reflector.center[0].abs() < 0.05, "{:?}", reflector.center);
+        assert!(
+            (reflector.center[1] - 2.0).abs() < 0.05,
+            "{:?}",
+            reflector.center
+        );
+        assert!((reflector.width - 0.31).abs() < 0.01, "{}", reflector.width);
+    }
+
+    #[test]
+    fn separate_reflectors_stay_apart() {
+        let scan = ring(|angle| angle < 3 || (90..94).contains(&angle) || angle >= 350);
+        let mut reflectors = find_reflectors(&scan, 0.5, 0.1, 3);
+        reflectors.sort_by_key(|r| r.points);
+
+        assert_eq!(reflectors.len(), 2);
+        assert_eq!(reflectors[0].points, 4);
+        assert_eq!(reflectors[1].points, 13);
+    }
+}
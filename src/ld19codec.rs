@@ -6,7 +6,7 @@ use std::{
 use tokio::io;
 use tokio_util::{bytes::BytesMut, codec::Decoder};
 
-#[repr(packed)]
+#[repr(C, packed)]
 #[derive(Clone, Copy)]
 pub struct Ld19Point {
     distance: u16,
@@ -30,12 +30,22 @@ impl Ld19Point {
     }
 }
 
+#[cfg(test)]
+impl Ld19Point {
+    pub fn new(distance_mm: u16, intensity: u8) -> Self {
+        Ld19Point {
+            distance: distance_mm,
+            intensity,
+        }
+    }
+}
+
 pub enum Ld19Frame {
     Packet(Ld19Packet),
     CRCError,
 }
 
-#[repr(packed)]
+#[repr(C, packed)]
 #[allow(unused)]
 #[derive(Copy, Clone)]
 pub struct Ld19Packet {
@@ -92,7 +102,7 @@ impl Ld19Packet {
         std::time::Duration::from_millis(self.timestamp as u64)
     }
 
-    pub fn iter_points(&self) -> Ld19PointIter {
+    pub fn iter_points(&self) -> Ld19PointIter<'_> {
         Ld19PointIter {
             packet: self,
             index: 0,
@@ -0,0 +1,448 @@
+use std::fmt::Write;
+use std::time::Instant;
+
+use eframe::egui::{self, Align2, Color32, Slider};
+use egui_plot::{Line, PlotPoint, PlotPoints, PlotUi, Text};
+
+use crate::export::Svg;
+use crate::geometry::{distance, fit_line, LineFit};
+use crate::overlay::bearing_deg;
+use crate::scan::Scan;
+
+const LINE_COLOR: Color32 = Color32::from_rgb(255, 128, 255);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Segment {
+    pub start: [f64; 2],
+    pub end: [f64; 2],
+    pub fit: LineFit,
+    pub points: usize,
+}
+
+impl Segment {
+    /// Fits a segment to the points, the ends are the projections of the outermost points.
+    fn from_points(points: &[[f64; 2]]) -> Option<Self> {
+        let fit = fit_line(points)?;
+        let (min, max) = points
+            .iter()
+            .map(|p| fit.project(*p))
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), t| {
+                (lo.min(t), hi.max(t))
+            });
+
+        Some(Segment {
+            start: fit.point_at(min),
+            end: fit.point_at(max),
+            fit,
+            points: points.len(),
+        })
+    }
+
+    pub fn length(&self) -> f64 {
+        distance(self.start, self.end)
+    }
+
+    /// Orientation using the θ convention, in `0°..180°`.
+    pub fn angle_deg(&self) -> f64 {
+        let d = self.fit.direction;
+        bearing_deg(d[0], d[1]).rem_euclid(180.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Method {
+    SplitAndMerge,
+    Ransac,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineParams {
+    /// maximum distance of a point to its line
+    pub tolerance: f64,
+    /// larger gaps between neighbouring points break a line
+    pub max_gap: f64,
+    pub min_points: usize,
+    pub min_length: f64,
+}
+
+impl Default for LineParams {
+    fn default() -> Self {
+        Self {
+            tolerance: 0.03,
+            max_gap: 0.2,
+            min_points: 8,
+            min_length: 0.2,
+        }
+    }
+}
+
+impl LineParams {
+    fn accepts(&self, segment: &Segment) -> bool {
+        segment.points >= self.min_points && segment.length() >= self.min_length
+    }
+}
+
+/// Extracts segments from points ordered by angle by recursively splitting
+/// at the farthest point and merging collinear neighbours afterwards.
+pub fn split_and_merge(points: &[[f64; 2]], params: &LineParams) -> Vec<Segment> {
+    let mut runs = vec![];
+
+    for group in split_at_gaps(points, params.max_gap) {
+        split(group, params, &mut runs);
+    }
+
+    // merge neighbouring runs that form a single line
+    let mut merged: Vec<Vec<[f64; 2]>> = vec![];
+    for run in runs {
+        if let Some(last) = merged.last_mut() {
+            let gap = distance(*last.last().unwrap(), run[0]);
+            let joined: Vec<_> = last.iter().chain(run.iter()).copied().collect();
+            let fits = fit_line(&joined)
+                .map(|f| {
+                    joined
+                        .iter()
+                        .all(|p| f.distance_to(*p).abs() <= params.tolerance)
+                })
+                .unwrap_or(false);
+
+            if gap <= params.max_gap && fits {
+                *last = joined;
+                continue;
+            }
+        }
+        merged.push(run);
+    }
+
+    merged
+        .iter()
+        .filter_map(|run| Segment::from_points(run))
+        .filter(|s| params.accepts(s))
+        .collect()
+}
+
+fn split(points: &[[f64; 2]], params: &LineParams, runs: &mut Vec<Vec<[f64; 2]>>) {
+    if points.len() < 3 {
+        runs.push(points.to_vec());
+        return;
+    }
+
+    // farthest point from the chord between the ends
+    let chord = fit_line(&[points[0], points[points.len() - 1]]).expect("two points");
+    let (index, dist) = points
+        .iter()
+        .enumerate()
+        .map(|(i, p)| (i, chord.distance_to(*p).abs()))
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .expect("not empty");
+
+    if dist > params.tolerance && index > 0 && index < points.len() - 1 {
+        split(&points[..=index], params, runs);
+        split(&points[index..], params, runs);
+    } else {
+        runs.push(points.to_vec());
+    }
+}
+
+fn split_at_gaps(points: &[[f64; 2]], max_gap: f64) -> impl Iterator<Item = &[[f64; 2]]> {
+    points
+        .chunk_by(move |a, b| distance(*a, *b) <= max_gap)
+        .filter(|g| g.len() >= 2)
+}
+
+/// Extracts segments by repeatedly fitting the line with the most inliers
+/// to random pairs of points.
+pub fn ransac(points: &[[f64; 2]], params: &LineParams, iterations: usize) -> Vec<Segment> {
+    let mut rng = fastrand::Rng::with_seed(0x1d19);
+    let mut remaining = points.to_vec();
+    let mut segments = vec![];
+
+    while remaining.len() >= params.min_points && segments.len() < 100 {
+        let mut best: Option<Vec<usize>> = None;
+        for _ in 0..iterations {
+            let a = remaining[rng.usize(..remaining.len())];
+            let b = remaining[rng.usize(..remaining.len())];
+            if distance(a, b) < params.tolerance {
+                continue;
+            }
+
+            let model = fit_line(&[a, b]).expect("two points");
+            let inliers: Vec<_> = (0..remaining.len())
+                .filter(|i| model.distance_to(remaining[*i]).abs() <= params.tolerance)
+                .collect();
+            if best
+                .as_ref()
+                .map(|b| inliers.len() > b.len())
+                .unwrap_or(true)
+            {
+                best = Some(inliers);
+            }
+        }
+
+        let Some(inliers) = best.filter(|b| b.len() >= params.min_points) else {
+            break;
+        };
+
+        // refine and split the inliers into contiguous pieces along the line
+        let inlier_points: Vec<_> = inliers.iter().map(|i| remaining[*i]).collect();
+        let fit = fit_line(&inlier_points).expect("enough points");
+        let mut along = inlier_points.clone();
+        along.sort_by(|a, b| fit.project(*a).total_cmp(&fit.project(*b)));
+
+        let mut found = false;
+        for piece in split_at_gaps(&along, params.max_gap) {
+            if let Some(segment) = Segment::from_points(piece).filter(|s| params.accepts(s)) {
+                segments.push(segment);
+                found = true;
+            }
+        }
+
+        // drop the inliers even if they did not form a segment, to make progress
+        let mut keep = (0..remaining.len()).map(|i| inliers.binary_search(&i).is_err());
+        remaining.retain(|_| keep.next().unwrap_or(true));
+
+        if !found && inliers.len() < params.min_points * 2 {
+            break;
+        }
+    }
+
+    segments
+}
+
+pub struct LineExtractor {
+    pub enabled: bool,
+    method: Method,
+    params: LineParams,
+    ransac_iterations: usize,
+    segments: Vec<Segment>,
+    /// the scan the segments belong to
+    source: Option<Instant>,
+}
+
+impl Default for LineExtractor {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            method: Method::SplitAndMerge,
+            params: Default::default(),
+            ransac_iterations: 200,
+            segments: vec![],
+            source: None,
+        }
+    }
+}
+
+impl LineExtractor {
+    /// Forgets the segments, e.g. when the device changes.
+    pub fn reset(&mut self) {
+        self.segments.clear();
+        self.source = None;
+    }
+
+    /// Segments of the last processed scan, empty while disabled.
+    pub fn segments(&self) -> &[Segment] {
+        if self.enabled {
+            &self.segments
+        } else {
+            &[]
+        }
+    }
+
+    /// Extracts the segments of the scan unless already done.
+    pub fn update(&mut self, scan: &Scan) {
+        if !self.enabled || self.source == Some(scan.completed) {
+            return;
+        }
+
+        self.segments = match self.method {
+            // the points are split in the angular order of each sensor
+            Method::SplitAndMerge => scan
+                .sensor_positions()
+                .iter()
+                .flat_map(|points| split_and_merge(points, &self.params))
+                .collect(),
+            Method::Ransac => {
+                let points: Vec<_> = scan.positions().collect();
+                ransac(&points, &self.params, self.ransac_iterations)
+            }
+        };
+        self.source = Some(scan.completed);
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        let before = (
+            self.enabled,
+            self.method,
+            self.params,
+            self.ransac_iterations,
+        );
+
+        ui.checkbox(&mut self.enabled, "Extract lines");
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.method, Method::SplitAndMerge, "Split and merge");
+            ui.selectable_value(&mut self.method, Method::Ransac, "RANSAC");
+        });
+        ui.add(
+            Slider::new(&mut self.params.tolerance, 0.005..=0.2)
+                .text("Tolerance (m)")
+                .logarithmic(true),
+        );
+        ui.add(Slider::new(&mut self.params.max_gap, 0.02..=1.0).text("Max. gap (m)"));
+        ui.add(Slider::new(&mut self.params.min_points, 3..=50).text("Min. points"));
+        ui.add(Slider::new(&mut self.params.min_length, 0.0..=2.0).text("Min. length (m)"));
+        if self.method == Method::Ransac {
+            ui.add(Slider::new(&mut self.ransac_iterations, 10..=1000).text("Iterations"));
+        }
+
+        // extract again with the new parameters
+        if before
+            != (
+                self.enabled,
+                self.method,
+                self.params,
+                self.ransac_iterations,
+            )
+        {
+            self.source = None;
+        }
+
+        if !self.enabled {
+            return;
+        }
+
+        egui::ScrollArea::vertical()
+            .id_source("segments")
+            .max_height(200.0)
+            .show(ui, |ui| {
+                egui::Grid::new("segments")
+                    .num_columns(5)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.label("#");
+                        ui.label("Ends");
+                        ui.label("Length");
+                        ui.label("Angle");
+                        ui.label("RMS");
+                        ui.end_row();
+                        for (i, s) in self.segments.iter().enumerate() {
+                            ui.label(format!("{}", i + 1));
+                            ui.label(format!(
+                                "({:.2}, {:.2}) → ({:.2}, {:.2})",
+                                s.start[0], s.start[1], s.end[0], s.end[1]
+                            ));
+                            ui.label(format!("{:.2}m", s.length()));
+                            ui.label(format!("{:.1}°", s.angle_deg()));
+                            ui.label(format!("{:.1}mm", s.fit.rms * 1e3));
+                            ui.end_row();
+                        }
+                    });
+            });
+    }
+
+    pub fn show(&self, plot_ui: &mut PlotUi) {
+        if !self.enabled {
+            return;
+        }
+
+        for (i, s) in self.segments.iter().enumerate() {
+            plot_ui.line(
+                Line::new(PlotPoints::new(vec![s.start, s.end]))
+                    .color(LINE_COLOR)
+                    .width(2.0)
+                    .name(format!("Line {}", i + 1)),
+            );
+            let mid = s
+                .fit
+                .point_at((s.fit.project(s.start) + s.fit.project(s.end)) * 0.5);
+            plot_ui.text(
+                Text::new(PlotPoint::new(mid[0], mid[1]), format!("{}", i + 1))
+                    .color(LINE_COLOR)
+                    .anchor(Align2::LEFT_BOTTOM),
+            );
+        }
+    }
+
+    pub fn write_svg(&self, svg: &mut Svg) {
+        if !self.enabled {
+            return;
+        }
+
+        for s in &self.segments {
+            svg.polyline(&[s.start, s.end], 2.0, LINE_COLOR, false);
+        }
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = "x1,y1,x2,y2,length_m,angle_deg,rms_m,points\n".to_owned();
+        for s in &self.segments {
+            let _ = writeln!(
+                csv,
+                "{:.4},{:.4},{:.4},{:.4},{:.4},{:.2},{:.5},{}",
+                s.start[0],
+                s.start[1],
+                s.end[0],
+                s.end[1],
+                s.length(),
+                s.angle_deg(),
+                s.fit.rms,
+                s.points
+            );
+        }
+
+        csv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two walls meeting at (1, 2) in scan order, 2cm between the points.
+    fn corner() -> Vec<[f64; 2]> {
+        let back = (0..100).map(|i| [-1.0 + i as f64 * 0.02, 2.0]);
+        let side = (0..=75).map(|i| [1.0, 2.0 - i as f64 * 0.02]);
+        back.chain(side).collect()
+    }
+
+    /// The segment with the orientation, its ends in either order within `tolerance`.
+    fn assert_segment(segments: &[Segment], angle_deg: f64, ends: [[f64; 2]; 2], tolerance: f64) {
+        let segment = segments
+            .iter()
+            .find(|s| (s.angle_deg() - angle_deg).abs() < 1.0)
+            .unwrap_or_else(|| panic!("no segment at {}° in {:?}", angle_deg, segments));
+        let (a, b) = (segment.start, segment.end);
+        let error = (distance(a, ends[0]) + distance(b, ends[1]))
+            .min(distance(a, ends[1]) + distance(b, ends[0]));
+        assert!(error < tolerance, "{:?} != {:?}", [a, b], ends);
+    }
+
+    #[test]
+    fn split_and_merge_finds_both_walls() {
+        let segments = split_and_merge(&corner(), &LineParams::default());
+
+        assert_eq!(segments.len(), 2);
+        // along +x is 90° in the θ convention
+        assert_segment(&segments, 90.0, [[-1.0, 2.0], [1.0, 2.0]], 0.02);
+        assert_segment(&segments, 0.0, [[1.0, 2.0], [1.0, 0.5]], 0.02);
+    }
+
+    #[test]
+    fn split_and_merge_breaks_at_gaps() {
+        let mut points = corner();
+        // a doorway in the back wall
+        points.retain(|p| !(p[1] == 2.0 && (-0.3..0.3).contains(&p[0])));
+        let segments = split_and_merge(&points, &LineParams::default());
+
+        assert_eq!(segments.len(), 3);
+    }
+
+    #[test]
+    fn ransac_finds_both_walls() {
+        let segments = ransac(&corner(), &LineParams::default(), 100);
+
+        assert_eq!(segments.len(), 2);
+        assert_segment(&segments, 90.0, [[-1.0, 2.0], [1.0, 2.0]], 0.02);
+        // the points near the corner are inliers of the first wall found
+        let tolerance = LineParams::default().tolerance * 3.0;
+        assert_segment(&segments, 0.0, [[1.0, 2.0], [1.0, 0.5]], tolerance);
+    }
+}
@@ -0,0 +1,593 @@
+use std::f64::consts::{PI, TAU};
+use std::path::Path;
+use std::time::Instant;
+
+use eframe::egui::{self, Color32, Slider};
+use egui_plot::{Arrows, PlotPoint, PlotPoints, PlotUi, Points};
+
+use crate::export::Svg;
+use crate::geometry::Pose2D;
+use crate::occupancy::{beams, GridMap, MapLayer};
+use crate::overlay::MAX_RANGE;
+use crate::scan::Scan;
+
+const PARTICLE_COLOR: Color32 = Color32::from_rgb(255, 80, 200);
+const ESTIMATE_COLOR: Color32 = Color32::from_rgb(0, 255, 120);
+
+/// Distances to obstacles beyond this are not told apart.
+const MAX_FIELD_DISTANCE: f32 = 2.0;
+
+/// Share of the measurements explained by random readings.
+const RANDOM_WEIGHT: f64 = 0.05;
+
+fn wrap(angle: f64) -> f64 {
+    (angle + PI).rem_euclid(TAU) - PI
+}
+
+fn gaussian(rng: &mut fastrand::Rng, sigma: f64) -> f64 {
+    // Box-Muller
+    let u = 1.0 - rng.f64();
+    (-2.0 * u.ln()).sqrt() * (TAU * rng.f64()).cos() * sigma
+}
+
+/// Loaded map with the distance of each cell to the closest obstacle.
+struct LikelihoodField {
+    grid: GridMap,
+    distances: Vec<f32>,
+}
+
+impl LikelihoodField {
+    fn new(grid: GridMap) -> Self {
+        let (w, h) = (grid.width, grid.height);
+        let r = grid.resolution as f32;
+        let mut distances: Vec<f32> = (0..grid.cells.len())
+            .map(|i| {
+                if grid.occupied(i) {
+                    0.0
+                } else {
+                    MAX_FIELD_DISTANCE
+                }
+            })
+            .collect();
+
+        // two pass chamfer distance transform
+        let neighbours = [
+            (-1, 0, r),
+            (0, -1, r),
+            (-1, -1, r * 2f32.sqrt()),
+            (1, -1, r * 2f32.sqrt()),
+        ];
+        let mut relax = |x: usize, y: usize, sign: i64| {
+            for (dx, dy, cost) in neighbours {
+                let (nx, ny) = (x as i64 + dx * sign, y as i64 + dy * sign);
+                if (0..w as i64).contains(&nx) && (0..h as i64).contains(&ny) {
+                    let d = distances[ny as usize * w + nx as usize] + cost;
+                    let current = &mut distances[y * w + x];
+                    *current = current.min(d);
+                }
+            }
+        };
+        for y in 0..h {
+            for x in 0..w {
+                relax(x, y, 1);
+            }
+        }
+        for y in (0..h).rev() {
+            for x in (0..w).rev() {
+                relax(x, y, -1);
+            }
+        }
+
+        Self { grid, distances }
+    }
+
+    fn distance(&self, p: [f64; 2]) -> f32 {
+        self.grid
+            .index(self.grid.cell(p))
+            .map_or(MAX_FIELD_DISTANCE, |i| self.distances[i])
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Particle {
+    pose: Pose2D,
+    weight: f64,
+}
+
+/// Steps of the "set initial pose" tool.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PoseTool {
+    Position,
+    /// position in the map frame, waiting for the heading
+    Heading([f64; 2]),
+}
+
+/// Estimates the pose of the robot in a loaded map with a particle filter (Monte Carlo localization).
+pub struct Localization {
+    pub enabled: bool,
+    field: Option<LikelihoodField>,
+    layer: MapLayer,
+    path: String,
+    particle_count: usize,
+    /// beams of each scan weighed against the map
+    beam_count: usize,
+    /// expected range noise
+    sigma_hit: f64,
+    /// motion noise per scan on top of the odometry
+    noise_distance: f64,
+    noise_angle_deg: f64,
+    opacity: f32,
+    show_particles: bool,
+    particles: Vec<Particle>,
+    estimate: Option<Pose2D>,
+    /// standard deviation of the particle positions
+    spread: f64,
+    /// odometry pose of the previous scan
+    last_odometry: Option<Pose2D>,
+    tool: Option<PoseTool>,
+    rng: fastrand::Rng,
+    status: Option<String>,
+    source: Option<Instant>,
+}
+
+impl Default for Localization {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            field: None,
+            layer: MapLayer::new("localization"),
+            path: "map.yaml".to_owned(),
+            particle_count: 500,
+            beam_count: 60,
+            sigma_hit: 0.1,
+            noise_distance: 0.02,
+            noise_angle_deg: 1.0,
+            opacity: 1.0,
+            show_particles: true,
+            particles: vec![],
+            estimate: None,
+            spread: 0.0,
+            last_odometry: None,
+            tool: None,
+            rng: fastrand::Rng::with_seed(0x1d19),
+            status: None,
+            source: None,
+        }
+    }
+}
+
+impl Localization {
+    pub fn reset(&mut self) {
+        self.particles.clear();
+        self.estimate = None;
+        self.spread = 0.0;
+        self.last_odometry = None;
+        self.tool = None;
+        self.source = None;
+    }
+
+    fn load(&mut self, path: &Path) -> Result<(), String> {
+        let grid = GridMap::load(path)?;
+        self.reset();
+        self.field = Some(LikelihoodField::new(grid));
+        self.layer.dirty = true;
+        Ok(())
+    }
+
+    /// Particles spread around `pose`.
+    fn initialize(&mut self, pose: Pose2D, sigma_distance: f64, sigma_angle: f64) {
+        let weight = 1.0 / self.particle_count as f64;
+        self.particles = (0..self.particle_count)
+            .map(|_| Particle {
+                pose: Pose2D::new(
+                    pose.x + gaussian(&mut self.rng, sigma_distance),
+                    pose.y + gaussian(&mut self.rng, sigma_distance),
+                    wrap(pose.theta + gaussian(&mut self.rng, sigma_angle)),
+                ),
+                weight,
+            })
+            .collect();
+        self.estimate = Some(pose);
+        self.source = None;
+    }
+
+    /// Particles spread over the free space, when the pose is unknown.
+    fn initialize_global(&mut self) {
+        let Some(field) = &self.field else {
+            return;
+        };
+        let free: Vec<_> = (0..field.grid.cells.len())
+            .filter(|i| field.grid.free(*i))
+            .collect();
+        if free.is_empty() {
+            return;
+        }
+
+        let weight = 1.0 / self.particle_count as f64;
+        self.particles = (0..self.particle_count)
+            .map(|_| {
+                let [x, y] = field.grid.position(free[self.rng.usize(..free.len())]);
+                Particle {
+                    pose: Pose2D::new(x, y, self.rng.f64() * TAU - PI),
+                    weight,
+                }
+            })
+            .collect();
+        self.estimate = None;
+        self.source = None;
+    }
+
+    /// Moves, weighs and resamples the particles unless the scan was already used.
+    /// `odometry` is the pose from the scan matching, if available.
+    pub fn update(&mut self, scan: &Scan, odometry: Option<Pose2D>) {
+        if !self.enabled || self.source == Some(scan.completed) {
+            return;
+        }
+        self.source = Some(scan.completed);
+
+        let motion = match (self.last_odometry, odometry) {
+            (Some(last), Some(current)) => last.inverse().compose(&current),
+            _ => Pose2D::default(),
+        };
+        self.last_odometry = odometry;
+
+        let Some(field) = &self.field else {
+            return;
+        };
+        if self.particles.is_empty() {
+            return;
+        }
+
+        // motion model, the noise grows with the distance moved
+        let step = motion.x.hypot(motion.y);
+        let sigma_distance = self.noise_distance + 0.1 * step;
+        let sigma_angle = self.noise_angle_deg.to_radians() + 0.1 * motion.theta.abs();
+        for particle in &mut self.particles {
+            let noisy = Pose2D::new(
+                motion.x + gaussian(&mut self.rng, sigma_distance),
+                motion.y + gaussian(&mut self.rng, sigma_distance),
+                motion.theta + gaussian(&mut self.rng, sigma_angle),
+            );
+            particle.pose = particle.pose.compose(&noisy);
+            particle.pose.theta = wrap(particle.pose.theta);
+        }
+
+        // measurement model, a likelihood field of the obstacles
+        let hits: Vec<_> = beams(scan, MAX_RANGE as f32)
+            .into_iter()
+            .filter(|b| b.hit)
+            .map(|b| b.end)
+            .collect();
+        if hits.is_empty() {
+            return;
+        }
+        let stride = hits.len().div_ceil(self.beam_count.max(1));
+        let used: Vec<_> = hits.iter().step_by(stride).copied().collect();
+
+        let variance = 2.0 * self.sigma_hit * self.sigma_hit;
+        let random = RANDOM_WEIGHT / MAX_RANGE;
+        let log_weights: Vec<f64> = self
+            .particles
+            .iter()
+            .map(|particle| {
+                used.iter()
+                    .map(|p| {
+                        let d = field.distance(particle.pose.transform(*p)) as f64;
+                        ((1.0 - RANDOM_WEIGHT) * (-d * d / variance).exp() + random).ln()
+                    })
+                    .sum()
+            })
+            .collect();
+
+        let max = log_weights.iter().copied().fold(f64::MIN, f64::max);
+        for (particle, log_weight) in self.particles.iter_mut().zip(&log_weights) {
+            particle.weight *= (log_weight - max).exp();
+        }
+        let total: f64 = self.particles.iter().map(|p| p.weight).sum();
+        if total <= 0.0 || !total.is_finite() {
+            for particle in &mut self.particles {
+                particle.weight = 1.0 / self.particle_count as f64;
+            }
+        } else {
+            for particle in &mut self.particles {
+                particle.weight /= total;
+            }
+        }
+
+        self.update_estimate();
+        let effective = 1.0
+            / self
+                .particles
+                .iter()
+                .map(|p| p.weight * p.weight)
+                .sum::<f64>();
+        if effective < self.particle_count as f64 * 0.5 {
+            self.resample();
+        }
+    }
+
+    /// Weighted mean of the particles.
+    fn update_estimate(&mut self) {
+        let (mut x, mut y, mut sin, mut cos) = (0.0, 0.0, 0.0, 0.0);
+        for p in &self.particles {
+            x += p.weight * p.pose.x;
+            y += p.weight * p.pose.y;
+            sin += p.weight * p.pose.theta.sin();
+            cos += p.weight * p.pose.theta.cos();
+        }
+        let variance: f64 = self
+            .particles
+            .iter()
+            .map(|p| p.weight * ((p.pose.x - x).powi(2) + (p.pose.y - y).powi(2)))
+            .sum();
+
+        self.estimate = Some(Pose2D::new(x, y, sin.atan2(cos)));
+        self.spread = variance.sqrt();
+    }
+
+    /// Low variance resampling, also adjusts the number of particles.
+    fn resample(&mut self) {
+        let count = self.particle_count;
+        let step = 1.0 / count as f64;
+        let mut target = self.rng.f64() * step;
+        let mut cumulative = 0.0;
+        let mut resampled = Vec::with_capacity(count);
+
+        for particle in &self.particles {
+            cumulative += particle.weight;
+            while target < cumulative && resampled.len() < count {
+                resampled.push(Particle {
+                    pose: particle.pose,
+                    weight: step,
+                });
+                target += step;
+            }
+        }
+        while resampled.len() < count {
+            resampled.push(Particle {
+                pose: self.particles[self.rng.usize(..self.particles.len())].pose,
+                weight: step,
+            });
+        }
+
+        self.particles = resampled;
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(&mut self.path).desired_width(120.0))
+                .on_hover_text("YAML description of a map_server map");
+            if ui.button("Load map").clicked() {
+                let path = self.path.clone();
+                self.status = Some(match self.load(Path::new(&path)) {
+                    Ok(()) => {
+                        let grid = &self.field.as_ref().expect("map loaded").grid;
+                        format!("{} × {} cells", grid.width, grid.height)
+                    }
+                    Err(err) => format!("Cannot load {}: {}", path, err),
+                });
+            }
+        });
+        if let Some(status) = &self.status {
+            ui.label(status);
+        }
+        if self.field.is_none() {
+            return;
+        }
+
+        ui.checkbox(&mut self.enabled, "Localize");
+        ui.horizontal(|ui| {
+            let label = match self.tool {
+                Some(PoseTool::Position) => "Click the position",
+                Some(PoseTool::Heading(_)) => "Click the heading",
+                None => "Set initial pose",
+            };
+            if ui
+                .selectable_label(self.tool.is_some(), label)
+                .on_hover_text("Click the position on the plot, then where the sensor faces")
+                .clicked()
+            {
+                self.tool = match self.tool {
+                    Some(_) => None,
+                    None => Some(PoseTool::Position),
+                };
+            }
+            if ui
+                .button("Global")
+                .on_hover_text("Spread the particles over the free space")
+                .clicked()
+            {
+                self.initialize_global();
+            }
+        });
+
+        ui.add(Slider::new(&mut self.particle_count, 50..=5000).text("Particles"));
+        ui.add(Slider::new(&mut self.beam_count, 10..=500).text("Beams per scan"));
+        ui.add(Slider::new(&mut self.sigma_hit, 0.01..=0.5).text("Range noise (m)"));
+        ui.add(Slider::new(&mut self.noise_distance, 0.0..=0.2).text("Motion noise (m)"));
+        ui.add(Slider::new(&mut self.noise_angle_deg, 0.0..=10.0).text("Motion noise (°)"));
+        ui.add(Slider::new(&mut self.opacity, 0.0..=1.0).text("Opacity"));
+        ui.checkbox(&mut self.show_particles, "Particles");
+
+        match self.estimate {
+            Some(pose) => ui.label(format!(
+                "Pose ({:.2}, {:.2})m {:.1}°, spread {:.2}m",
+                pose.x,
+                pose.y,
+                -pose.theta.to_degrees(),
+                self.spread
+            )),
+            None => ui.label("Pose unknown"),
+        };
+    }
+
+    /// Handles the pose tool, which takes the plot `click`, and draws the map,
+    /// the particles and the estimate as seen from the estimated pose.
+    pub fn show(&mut self, plot_ui: &mut PlotUi, click: &mut Option<PlotPoint>) {
+        if self.field.is_none() {
+            return;
+        }
+        let estimate = self.estimate.unwrap_or_default();
+        let view = estimate.inverse();
+
+        if let Some(tool) = self.tool {
+            if let Some(pointer) = plot_ui.pointer_coordinate() {
+                let pointer = [pointer.x, pointer.y];
+                let clicked = click.take().is_some();
+                match tool {
+                    PoseTool::Position if clicked => {
+                        self.tool = Some(PoseTool::Heading(estimate.transform(pointer)));
+                    }
+                    PoseTool::Heading(position) => {
+                        let from = view.transform(position);
+                        plot_ui.arrows(
+                            Arrows::new(
+                                PlotPoints::new(vec![from]),
+                                PlotPoints::new(vec![pointer]),
+                            )
+                            .color(ESTIMATE_COLOR),
+                        );
+                        if clicked {
+                            // forward is +y, θ is counter-clockwise
+                            let direction = [pointer[0] - from[0], pointer[1] - from[1]];
+                            let theta = estimate.theta + (-direction[0]).atan2(direction[1]);
+                            self.initialize(
+                                Pose2D::new(position[0], position[1], wrap(theta)),
+                                0.1,
+                                5f64.to_radians(),
+                            );
+                            self.enabled = true;
+                            self.tool = None;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let field = self.field.as_ref().expect("map loaded");
+        self.layer.show(plot_ui, &field.grid, view, self.opacity);
+
+        if self.show_particles && !self.particles.is_empty() {
+            let particles: Vec<_> = self
+                .particles
+                .iter()
+                .map(|p| view.transform([p.pose.x, p.pose.y]))
+                .collect();
+            plot_ui.points(
+                Points::new(particles)
+                    .radius(1.5)
+                    .color(PARTICLE_COLOR)
+                    .name("Particles")
+                    .allow_hover(false),
+            );
+        }
+    }
+
+    pub fn write_svg(&self, svg: &mut Svg) {
+        let Some(field) = &self.field else {
+            return;
+        };
+
+        let view = self.estimate.unwrap_or_default().inverse();
+        MapLayer::write_svg(svg, &field.grid, view, self.opacity);
+        if !self.show_particles || self.particles.is_empty() {
+            return;
+        }
+
+        for p in &self.particles {
+            svg.circle(view.transform([p.pose.x, p.pose.y]), 1.5, PARTICLE_COLOR);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Walls of a 4m × 3m room with a pillar, so that the pose is unique nearby.
+    fn room() -> GridMap {
+        let mut grid = GridMap::new(0.05, 6.0);
+        for i in 0..grid.cells.len() {
+            let [x, y] = grid.position(i);
+            let wall = (x.abs() - 2.0).abs() < 0.03 && (-1.0..=2.0).contains(&y)
+                || ((y + 1.0).abs() < 0.03 || (y - 2.0).abs() < 0.03) && x.abs() <= 2.0;
+            let pillar = (0.8..1.0).contains(&x) && (0.8..1.0).contains(&y);
+            if wall || pillar {
+                grid.cells[i] = 5.0;
+            }
+        }
+        grid
+    }
+
+    /// Range from `pose` along the bearing to the first obstacle of the map, in 1cm steps.
+    fn cast(field: &LikelihoodField, pose: Pose2D, bearing_deg: f64) -> f32 {
+        let rad = bearing_deg.to_radians();
+        (1..400)
+            .map(|i| i as f64 * 0.01)
+            .find(|range| {
+                let p = pose.transform([rad.sin() * range, rad.cos() * range]);
+                field.distance(p) == 0.0
+            })
+            .unwrap_or(0.0) as f32
+    }
+
+    #[test]
+    fn field_holds_the_distance_to_the_nearest_obstacle() {
+        let mut grid = GridMap::new(0.1, 2.0);
+        let center = grid.index([10, 10]).expect("inside");
+        grid.cells[center] = 5.0;
+        let field = LikelihoodField::new(grid);
+        let at = |x: i64, y: i64| {
+            let i = field.grid.index([x, y]).expect("inside");
+            field.distances[i]
+        };
+
+        assert_eq!(at(10, 10), 0.0);
+        assert!((at(11, 10) - 0.1).abs() < 1e-6);
+        assert!((at(10, 7) - 0.3).abs() < 1e-6);
+        assert!((at(9, 9) - 0.1 * 2f32.sqrt()).abs() < 1e-6);
+        // chamfer distance, one diagonal and one straight step
+        assert!((at(12, 11) - 0.1 * (1.0 + 2f32.sqrt())).abs() < 1e-6);
+        assert!(at(0, 0) > 1.4 && at(0, 0) <= MAX_FIELD_DISTANCE);
+        assert_eq!(field.distance([5.0, 5.0]), MAX_FIELD_DISTANCE);
+    }
+
+    #[test]
+    fn particles_converge_to_the_true_pose() {
+        let field = LikelihoodField::new(room());
+        let truth = Pose2D::new(0.3, -0.2, 0.15);
+        let readings: Vec<_> = (0..180)
+            .map(|i| {
+                let angle = i as f64 * 2.0;
+                (angle as f32, cast(&field, truth, angle), 200)
+            })
+            .collect();
+
+        let mut localization = Localization {
+            enabled: true,
+            field: Some(field),
+            ..Default::default()
+        };
+        localization.initialize(Pose2D::default(), 0.3, 0.15);
+
+        let scan = Scan::from_readings(readings);
+        for i in 0..20 {
+            let mut scan = scan.clone();
+            scan.completed += Duration::from_millis(100 * i);
+            localization.update(&scan, None);
+        }
+
+        let estimate = localization.estimate.expect("estimate");
+        let error = (estimate.x - truth.x).hypot(estimate.y - truth.y);
+        assert!(error < 0.05, "{:?}", estimate);
+        assert!(
+            wrap(estimate.theta - truth.theta).abs() < 3f64.to_radians(),
+            "{:?}",
+            estimate
+        );
+        assert!(localization.spread < 0.1, "{}", localization.spread);
+    }
+}
@@ -1,18 +1,59 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
+use std::collections::VecDeque;
+use std::path::PathBuf;
 use std::time::{Duration, Instant};
 
-use ::futures::StreamExt;
-use eframe::egui::{Color32, ComboBox, Slider, Vec2, Vec2b};
+use background::BackgroundSubtraction;
+use clustering::Clustering;
+use docking::Docking;
+use eframe::egui::{Color32, ComboBox, Rect, Slider, Vec2, Vec2b};
 use eframe::{egui, CreationContext};
 use egui_plot::{Arrows, CoordinatesFormatter, PlotPoints, Points};
-use ld19codec::{Ld19Frame, Ld19Point};
+use extrinsics::Extrinsics;
+use landmarks::Landmarks;
+use lines::LineExtractor;
+use localization::Localization;
+use measure::MeasureTools;
+use mounting::{Mounting, MountingStore, WallCalibration};
+use occupancy::OccupancyMapper;
+use odometry::Odometry;
+use overlay::PolarGrid;
+use people::PeopleDetector;
+use range_calibration::RangeCalibration;
+use scan::Scan;
+use sensor::{available_serial_ports, Sensor};
+use slam::Slam;
 use tokio::runtime;
-
-use tokio_serial::SerialPortBuilderExt;
-use tokio_util::codec::Decoder;
-
+use tracking::Tracker;
+use zones::ZoneMonitor;
+
+mod background;
+mod clustering;
+mod docking;
+mod events;
+mod export;
+mod extrinsics;
+mod filters;
+mod geometry;
+mod icp;
+mod landmarks;
 mod ld19codec;
+mod lines;
+mod localization;
+mod measure;
+mod mounting;
+mod occupancy;
+mod odometry;
+mod overlay;
+mod people;
+mod pipeline;
+mod range_calibration;
+mod scan;
+mod sensor;
+mod slam;
+mod tracking;
+mod zones;
 
 fn main() -> eframe::Result {
     let options = eframe::NativeOptions {
@@ -28,52 +69,102 @@ fn main() -> eframe::Result {
     )
 }
 
-#[derive(Clone, Copy)]
-struct LidarPoint {
-    point: Ld19Point,
-    angle: f32,
-    instant: Instant,
-}
+/// Number of discrete opacity levels used to draw fading points.
+const FADE_LEVELS: usize = 8;
 
-#[derive(Debug, Default)]
-struct LidarStats {
-    angular_resolution: RollingAverage,
-    angular_rate: RollingAverage,
-    sample_rate: RollingAverage,
-    max_dist: RollingAverage,
-    min_dist: RollingAverage,
-    crc_errors: u32,
-    last_start_angle: f32,
-    last_completed_rotation: Option<Instant>,
+/// Scans of the other sensors older than this are not merged.
+const MERGE_WINDOW: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FadeMode {
+    /// points fade out gradually over the fade duration
+    Age,
+    /// the last N scans are kept with decaying opacity
+    Persistence,
 }
 
-#[derive(Debug, Default)]
-struct RollingAverage {
-    index: usize,
-    hist: [f32; 8],
+/// Maps an opacity in `0..=1` to one of the discrete fade levels.
+fn fade_level(alpha: f32) -> usize {
+    ((alpha * FADE_LEVELS as f32).ceil() as usize).clamp(1, FADE_LEVELS) - 1
 }
 
-impl RollingAverage {
-    fn push(&mut self, val: f32) {
-        self.hist[self.index] = val;
-        self.index = (self.index + 1) % self.hist.len();
-    }
+/// Plot of the scans in the robot frame, the views of the processed
+/// and the raw points pan and zoom together.
+fn scan_plot(id: &str) -> egui_plot::Plot<'static> {
+    egui_plot::Plot::new(id)
+        .allow_zoom(true)
+        .allow_drag(true)
+        .allow_scroll(false)
+        .auto_bounds(Vec2b::new(false, false))
+        .x_axis_label("m")
+        .y_axis_label("m")
+        .data_aspect(1.0)
+        .link_axis("scan_plots", true, true)
+        .link_cursor("scan_plots", true, true)
+        .coordinates_formatter(
+            egui_plot::Corner::LeftBottom,
+            CoordinatesFormatter::new(|p, _| {
+                let d = (p.x * p.x + p.y * p.y).sqrt();
+                let ang = overlay::bearing_deg(p.x, p.y);
+                format!("d={:.2}m θ={:.2}°", d, ang)
+            }),
+        )
+}
 
-    fn get(&self) -> f32 {
-        self.hist.iter().sum::<f32>() / self.hist.len() as f32
+/// Adds the first `count` scans to the fade levels of their sensors with linearly decaying opacity.
+fn push_trail<'a>(
+    levels: &mut [Vec<Vec<[f64; 2]>>],
+    scans: impl Iterator<Item = &'a Scan>,
+    count: usize,
+) {
+    for (i, scan) in scans.take(count).enumerate() {
+        let alpha = 1.0 - i as f32 / count as f32;
+        for p in &scan.points {
+            if let Some(sensor) = levels.get_mut(p.sensor) {
+                sensor[fade_level(alpha)].push(scan.position(p));
+            }
+        }
     }
 }
 
 struct ViewerApp {
     rt: runtime::Runtime,
-    lidar_rx: Option<std::sync::mpsc::Receiver<Ld19Frame>>,
-    lidar_points: Vec<LidarPoint>,
-    intensity_threshold: f32,
+    sensors: Vec<Sensor>,
+    /// the sensor whose mounting and range calibration are edited
+    selected: usize,
     fade_duration_ms: u64,
-    serial_port: String,
-    worker_handle: Option<tokio::task::JoinHandle<()>>,
-    stop_signal: Option<tokio::sync::mpsc::Sender<()>>,
-    stats: LidarStats,
+    fade_mode: FadeMode,
+    persistence_scans: usize,
+    scan_history: VecDeque<Scan>,
+    show_raw: bool,
+    mountings: MountingStore,
+    mounting_status: Option<String>,
+    calibration: WallCalibration,
+    range_calibration: RangeCalibration,
+    polar_grid: PolarGrid,
+    measure: MeasureTools,
+    lines: LineExtractor,
+    clustering: Clustering,
+    tracker: Tracker,
+    people: PeopleDetector,
+    landmarks: Landmarks,
+    docking: Docking,
+    extrinsics: Extrinsics,
+    background: BackgroundSubtraction,
+    zones: ZoneMonitor,
+    occupancy: OccupancyMapper,
+    odometry: Odometry,
+    slam: Slam,
+    localization: Localization,
+    paused: bool,
+    /// copy of the buffer taken when pausing, the live buffer keeps filling
+    frozen: VecDeque<Scan>,
+    buffer_scans: usize,
+    replay_index: usize,
+    plot_rect: Option<Rect>,
+    png_requested: bool,
+    svg_requested: bool,
+    export_status: Option<String>,
 }
 
 impl ViewerApp {
@@ -83,278 +174,844 @@ impl ViewerApp {
                 .enable_all()
                 .build()
                 .unwrap(),
-            lidar_rx: None,
-            lidar_points: vec![],
-            intensity_threshold: 0.1,
+            sensors: vec![],
+            selected: 0,
             fade_duration_ms: 100, // 10Hz
-            serial_port: "".to_owned(),
-            worker_handle: None,
-            stop_signal: None,
-            stats: Default::default(),
+            fade_mode: FadeMode::Age,
+            persistence_scans: 5,
+            scan_history: VecDeque::new(),
+            show_raw: false,
+            mountings: MountingStore::load(),
+            mounting_status: None,
+            calibration: Default::default(),
+            range_calibration: RangeCalibration::load(),
+            polar_grid: Default::default(),
+            measure: Default::default(),
+            lines: Default::default(),
+            clustering: Default::default(),
+            tracker: Default::default(),
+            people: Default::default(),
+            landmarks: Default::default(),
+            docking: Default::default(),
+            extrinsics: Default::default(),
+            background: Default::default(),
+            zones: Default::default(),
+            occupancy: Default::default(),
+            odometry: Default::default(),
+            slam: Default::default(),
+            localization: Default::default(),
+            paused: false,
+            frozen: VecDeque::new(),
+            buffer_scans: 50,
+            replay_index: 0,
+            plot_rect: None,
+            png_requested: false,
+            svg_requested: false,
+            export_status: None,
+        }
+    }
+
+    /// The live buffer, or the frozen copy while paused.
+    fn shown_history(&self) -> &VecDeque<Scan> {
+        if self.paused {
+            &self.frozen
+        } else {
+            &self.scan_history
+        }
+    }
+
+    /// The newest scan, or the selected one while paused.
+    fn displayed_scan(&self) -> Option<&Scan> {
+        let index = if self.paused { self.replay_index } else { 0 };
+        self.shown_history().get(index)
+    }
+
+    /// The first enabled sensor, the others are merged into its scans.
+    fn primary(&self) -> Option<&Sensor> {
+        self.sensors.iter().find(|s| s.enabled)
+    }
+
+    /// Mounting of the sensor being edited, the overlays are drawn around it.
+    fn selected_mounting(&self) -> Mounting {
+        self.sensors
+            .get(self.selected)
+            .map(|s| s.mounting)
+            .unwrap_or_default()
+    }
+
+    /// Points of the selected sensor in the shown scan, the wall calibration corrects them.
+    fn wall_points(&self) -> Vec<[f64; 2]> {
+        self.displayed_scan()
+            .map(|scan| {
+                scan.points
+                    .iter()
+                    .filter(|p| p.sensor == self.selected)
+                    .map(|p| scan.position(p))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// File name for exports carrying the time and device
+    fn export_path(&self, extension: &str) -> PathBuf {
+        let sensor = self.primary().or(self.sensors.first());
+        let port = sensor.map(|s| s.port.as_str()).unwrap_or_default();
+        let serial_number = sensor
+            .and_then(|s| s.serial_number.as_deref())
+            .unwrap_or_default();
+        let stem = export::file_stem(&[port, serial_number]);
+
+        PathBuf::from(format!("{}.{}", stem, extension))
+    }
+
+    fn export_png(&self, screenshot: &egui::ColorImage, pixels_per_point: f32) -> String {
+        let Some(rect) = self.plot_rect else {
+            return "Nothing to export".to_owned();
+        };
+        let path = self.export_path("png");
+
+        match export::save_png(&path, screenshot, rect, pixels_per_point) {
+            Ok(()) => format!("Saved {}", path.display()),
+            Err(err) => format!("PNG export failed: {}", err),
+        }
+    }
+
+    fn export_svg(
+        &self,
+        levels: &[Vec<Vec<[f64; 2]>>],
+        bounds: egui_plot::PlotBounds,
+        size: Vec2,
+        background: Color32,
+    ) -> String {
+        let (min, max) = (bounds.min(), bounds.max());
+        let mut svg = export::Svg::new(min, max, size.x, size.y);
+
+        svg.rect(background);
+        svg.axes(Color32::from_gray(128));
+        // the layers in the order of the plot
+        self.occupancy
+            .write_svg(&mut svg, self.odometry.pose().inverse());
+        self.slam.write_svg(&mut svg);
+        self.localization.write_svg(&mut svg);
+        self.odometry.write_svg(&mut svg);
+        self.polar_grid
+            .write_svg(&mut svg, min, max, &self.selected_mounting());
+        if let Some(sensor) = self.sensors.get(self.selected) {
+            sensor.pipeline.write_svg(&mut svg, &sensor.mounting);
+        }
+
+        // the raw view is a separate plot, its points go faintly underneath
+        if let (true, Some(scan)) = (self.show_raw, self.displayed_scan()) {
+            for p in &scan.raw {
+                if let Some(sensor) = self.sensors.get(p.sensor) {
+                    svg.circle(scan.position(p), 2.5, sensor.color.gamma_multiply(0.3));
+                }
+            }
+        }
+
+        for (sensor, levels) in self.sensors.iter().zip(levels) {
+            for (level, points) in levels.iter().enumerate() {
+                let alpha = (level + 1) as f32 / FADE_LEVELS as f32;
+                for p in points {
+                    svg.circle(*p, 2.5, sensor.color.gamma_multiply(alpha));
+                }
+            }
+        }
+        for sensor in self.sensors.iter().filter(|s| s.enabled) {
+            let position = sensor.mounting.transform([0.0, 0.0]);
+            let forward = sensor.mounting.transform([0.0, 1.0]);
+            svg.polyline(&[position, forward], 1.5, Color32::from_gray(200), false);
+            svg.circle(position, 10.0, Color32::GOLD);
+        }
+        self.zones.write_svg(&mut svg);
+        self.background.write_svg(&mut svg);
+        self.clustering.write_svg(&mut svg);
+        self.tracker.write_svg(&mut svg);
+        self.people.write_svg(&mut svg);
+        self.lines.write_svg(&mut svg);
+        self.landmarks.write_svg(&mut svg);
+        self.docking.write_svg(&mut svg);
+        self.extrinsics.write_svg(&mut svg);
+        self.measure.write_svg(&mut svg);
+        self.calibration.write_svg(&mut svg, &self.wall_points());
+
+        let path = self.export_path("svg");
+        let sensors: Vec<_> = self
+            .sensors
+            .iter()
+            .filter(|s| s.enabled)
+            .map(|s| {
+                format!(
+                    "{} (serial {}), {:.1}Hz, {:.2}° resolution",
+                    s.port,
+                    s.serial_number.as_deref().unwrap_or("unknown"),
+                    s.stats.angular_rate.get(),
+                    s.stats.angular_resolution.get(),
+                )
+            })
+            .collect();
+        let description = format!(
+            "LD19 scan on {} at {} UTC",
+            sensors.join(" and "),
+            export::timestamp(),
+        );
+
+        match std::fs::write(&path, svg.finish("LD19 LIDAR Viewer", &description)) {
+            Ok(()) => format!("Saved {}", path.display()),
+            Err(err) => format!("SVG export failed: {}", err),
+        }
+    }
+
+    /// Clears the plot and the state of the processing, e.g. when the sensors change.
+    fn reset_processing(&mut self) {
+        self.extrinsics.reset();
+        for sensor in &mut self.sensors {
+            sensor.pipeline.reset();
+        }
+        self.lines.reset();
+        self.clustering.reset();
+        self.tracker.reset();
+        self.people.reset();
+        self.landmarks.reset();
+        self.docking.reset();
+        self.background.reset();
+        self.zones.reset();
+        self.occupancy.clear();
+        self.odometry.reset();
+        self.slam.clear();
+        self.localization.reset();
+        self.scan_history.clear();
+        self.frozen.clear();
+        self.replay_index = 0;
+    }
+
+    fn select_sensor(&mut self, index: usize) {
+        self.selected = index;
+        if let Some(sensor) = self.sensors.get(index) {
+            self.range_calibration.select_device(&sensor.key);
+        }
+    }
+
+    fn fetch_frames(&mut self) {
+        let primary = self.sensors.iter().position(|s| s.enabled);
+        let mut completed = vec![];
+
+        for (i, sensor) in self.sensors.iter_mut().enumerate() {
+            for mut scan in sensor.fetch() {
+                for p in scan.points.iter_mut().chain(&mut scan.raw) {
+                    p.sensor = i;
+                }
+                self.range_calibration.process(&mut scan, &sensor.key);
+                sensor.process(&mut scan);
+
+                if Some(i) == primary {
+                    completed.push(scan.clone());
+                }
+                sensor.latest = Some(scan);
+            }
+        }
+
+        for scan in completed {
+            self.complete_scan(scan);
         }
     }
+
+    /// Merges the processed scans of the other sensors into the scan
+    /// of the first one and buffers the result.
+    fn complete_scan(&mut self, mut scan: Scan) {
+        let primary = self.sensors.iter().position(|s| s.enabled);
+        for (i, sensor) in self.sensors.iter().enumerate() {
+            if Some(i) == primary {
+                continue;
+            }
+            let fresh = sensor.latest.as_ref().filter(|latest| {
+                scan.completed.saturating_duration_since(latest.completed) <= MERGE_WINDOW
+            });
+            if let (true, Some(latest)) = (sensor.enabled, fresh) {
+                scan.merge(latest, i);
+            }
+        }
+
+        // keep the completed scan for display, persistence and playback
+        self.scan_history.push_front(scan);
+        self.scan_history
+            .truncate(self.persistence_scans.max(self.buffer_scans));
+    }
 }
 
 impl eframe::App for ViewerApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.fetch_frames();
+        self.extrinsics.update(&self.sensors);
+
+        // monitoring and the map always use the live scan, also during playback
+        if let Some(scan) = self.scan_history.front() {
+            self.zones.update(scan);
+            self.background.update(scan);
+            self.odometry.update(scan);
+            self.occupancy.update(scan, self.odometry.pose());
+            self.slam.update(scan);
+            self.localization
+                .update(scan, self.odometry.enabled.then(|| self.odometry.pose()));
+        }
+
+        let (history, index) = if self.paused {
+            (&self.frozen, self.replay_index)
+        } else {
+            (&self.scan_history, 0)
+        };
+        if let Some(scan) = history.get(index) {
+            self.lines.update(scan);
+            self.clustering.update(scan);
+            self.tracker
+                .update(self.clustering.clusters(), scan.completed);
+            self.people.update(
+                self.clustering.clusters(),
+                scan.mounting.transform([0.0, 0.0]),
+                scan.completed,
+            );
+            self.landmarks
+                .update(scan, self.lines.segments(), self.clustering.clusters());
+            self.docking
+                .update(self.landmarks.reflectors(), &scan.mounting, scan.completed);
+        }
+
+        // a requested screenshot arrives as an event a few frames later
+        let screenshot = ctx.input(|i| {
+            i.events.iter().find_map(|e| match e {
+                egui::Event::Screenshot { image, .. } => Some(image.clone()),
+                _ => None,
+            })
+        });
+        if let Some(screenshot) = screenshot {
+            if std::mem::take(&mut self.png_requested) {
+                self.export_status = Some(self.export_png(&screenshot, ctx.pixels_per_point()));
+            }
+        }
+
         egui::SidePanel::left("options").show(ctx, |ui| {
-            // device disconnected?
-            if self
-                .stop_signal
-                .as_ref()
-                .map(|p| p.is_closed())
-                .unwrap_or(false)
-            {
-                self.worker_handle = None;
-                self.stop_signal = None;
-                self.serial_port = String::new();
+            // devices disconnected?
+            if self.sensors.iter().any(|s| s.is_disconnected()) {
+                self.sensors.retain(|s| !s.is_disconnected());
+                self.select_sensor(0);
+                self.reset_processing();
             }
 
             ui.style_mut().spacing.item_spacing = Vec2::new(16.0, 16.0);
             ui.style_mut().spacing.indent = 16.0;
 
-            ui.add_space(ui.spacing().item_spacing.y);
-            ui.spacing();
-            ui.heading("Settings");
-            ComboBox::from_label("Serial port")
-                .selected_text(self.serial_port.to_string())
-                .show_ui(ui, |ui| {
-                    for port in available_serial_ports() {
-                        let resp =
-                            ui.selectable_value(&mut self.serial_port, port.clone(), port.clone());
-
-                        if resp.changed() {
-                            // exit the worker task
-                            if self.worker_handle.as_ref().is_some() {
-                                self.rt
-                                    .block_on(self.stop_signal.as_ref().unwrap().send(()))
-                                    .ok();
-                                self.worker_handle = None;
-                                self.stop_signal = None;
-                            }
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                ui.add_space(ui.spacing().item_spacing.y);
+                ui.spacing();
+                ui.heading("Settings");
+                let mut remove = None;
+                let mut select = None;
+                for (i, sensor) in self.sensors.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut sensor.enabled, "")
+                            .on_hover_text("Merge the points into the view");
+                        ui.color_edit_button_srgba(&mut sensor.color);
+                        if ui
+                            .selectable_label(self.selected == i, &sensor.port)
+                            .on_hover_text("Edit the processing, mounting and range calibration")
+                            .clicked()
+                        {
+                            select = Some(i);
+                        }
+                        if ui.small_button("🗑").on_hover_text("Disconnect").clicked() {
+                            remove = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = select {
+                    self.select_sensor(i);
+                }
+                if let Some(i) = remove {
+                    self.sensors.remove(i).disconnect(&self.rt);
+                    self.select_sensor(0);
+                    self.reset_processing();
+                }
 
-                            // create a new worker
-                            let (tx, rx) = std::sync::mpsc::channel();
-                            self.lidar_rx = Some(rx);
-
-                            let egui_ctx = ctx.clone();
-
-                            let (tx_stop, mut rx_stop) = tokio::sync::mpsc::channel(1);
-                            self.stop_signal = Some(tx_stop);
-
-                            self.worker_handle = Some(self.rt.spawn(async move {
-                                let port = tokio_serial::new(port, 230400)
-                                    .stop_bits(tokio_serial::StopBits::One)
-                                    .parity(tokio_serial::Parity::None)
-                                    .flow_control(tokio_serial::FlowControl::None)
-                                    .open_native_async()
-                                    .expect("Cannot open port");
-
-                                let codec = ld19codec::Ld19Codec {};
-                                let mut reader = codec.framed(port);
-
-                                loop {
-                                    tokio::select! {
-                                        Some(frame) = reader.next() => {
-                                            if let Ok(result) = frame {
-                                                tx.send(result).unwrap();
-                                                egui_ctx.request_repaint();
-                                            } else {
-                                                break;
-                                            }
-                                        },
-
-                                        Some(_) = rx_stop.recv() => {
-                                            break;
-                                        }
-                                    }
-                                }
+                let mut connect = None;
+                ComboBox::from_label("Add sensor")
+                    .selected_text("Serial port")
+                    .show_ui(ui, |ui| {
+                        for port in available_serial_ports() {
+                            let connected = self.sensors.iter().any(|s| s.port == port);
+                            if ui
+                                .add_enabled(!connected, egui::SelectableLabel::new(false, &port))
+                                .clicked()
+                            {
+                                connect = Some(port);
+                            }
+                        }
+                    });
+                if let Some(port) = connect {
+                    let index = self.sensors.len();
+                    let mut sensor = Sensor::connect(&self.rt, ctx, port, index);
+                    sensor.mounting = self.mountings.get(&sensor.key);
+                    self.sensors.push(sensor);
+                    self.select_sensor(index);
+                    self.reset_processing();
+                }
 
-                                println!("exit worker");
-                            }));
+                ComboBox::from_label("Fade mode")
+                    .selected_text(format!("{:?}", self.fade_mode))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.fade_mode, FadeMode::Age, "Age");
+                        ui.selectable_value(
+                            &mut self.fade_mode,
+                            FadeMode::Persistence,
+                            "Persistence",
+                        );
+                    });
+                match self.fade_mode {
+                    FadeMode::Age => {
+                        ui.add(
+                            Slider::new(&mut self.fade_duration_ms, 0..=500)
+                                .text("Fade duration (ms)"),
+                        )
+                        .on_hover_ui(|ui| {
+                            ui.label(
+                                "This is typically the angular frequency (100ms for the LD19)",
+                            );
+                        });
+                    }
+                    FadeMode::Persistence => {
+                        ui.add(
+                            Slider::new(&mut self.persistence_scans, 1..=20)
+                                .text("Persistence (scans)"),
+                        )
+                        .on_hover_ui(|ui| {
+                            ui.label("Number of past scans drawn with decaying opacity");
+                        });
+                    }
+                }
 
-                            // clear plot and reset stats
-                            self.lidar_points.clear();
-                            self.stats = Default::default();
+                // playback ui
+                ui.separator();
+                ui.heading("Playback");
+                ui.horizontal(|ui| {
+                    if self.paused {
+                        if ui.button("▶ Resume").clicked() {
+                            self.paused = false;
+                            self.frozen.clear();
+                        }
+                        let oldest = self.frozen.len().saturating_sub(1);
+                        if ui
+                            .add_enabled(self.replay_index < oldest, egui::Button::new("⏴"))
+                            .on_hover_text("Previous scan")
+                            .clicked()
+                        {
+                            self.replay_index += 1;
                         }
+                        if ui
+                            .add_enabled(self.replay_index > 0, egui::Button::new("⏵"))
+                            .on_hover_text("Next scan")
+                            .clicked()
+                        {
+                            self.replay_index -= 1;
+                        }
+                        ui.label(format!("-{} / {}", self.replay_index, self.frozen.len()));
+                    } else if ui.button("⏸ Pause").clicked() {
+                        self.paused = true;
+                        self.frozen = self.scan_history.clone();
+                        self.replay_index = 0;
                     }
                 });
+                ui.add(Slider::new(&mut self.buffer_scans, 1..=200).text("Buffer (scans)"))
+                    .on_hover_ui(|ui| {
+                        ui.label("Number of past scans kept for stepping through while paused");
+                    });
 
-            ui.add(
-                Slider::new(&mut self.intensity_threshold, 0.0..=1.0).text("Intensity threshold"),
-            );
-            ui.add(Slider::new(&mut self.fade_duration_ms, 0..=500).text("Fade duration (ms)"))
-                .on_hover_ui(|ui| {
-                    ui.label("This is typically the angular frequency (100ms for the LD19)");
-                });
+                // processing ui
+                ui.separator();
+                ui.heading("Processing");
+                if let Some(sensor) = self.sensors.get_mut(self.selected) {
+                    sensor.pipeline.ui(ui);
+                } else {
+                    ui.label("No sensor connected");
+                }
+                ui.checkbox(&mut self.show_raw, "Show raw scan")
+                    .on_hover_text(
+                        "Shows the unprocessed points in a second view next to the processed ones",
+                    );
+
+                // mounting ui
+                ui.separator();
+                ui.heading("Mounting");
+                if let Some(sensor) = self.sensors.get_mut(self.selected) {
+                    sensor.mounting.ui(ui);
+                    ui.horizontal(|ui| {
+                        if ui.button("Save").clicked() {
+                            let saved = self.mountings.save(&sensor.key, sensor.mounting);
+                            self.mounting_status = Some(match saved {
+                                Ok(()) => format!("Saved the mounting of {}", sensor.port),
+                                Err(err) => format!("Cannot save the mounting: {}", err),
+                            });
+                        }
+                        if ui.button("Reset").clicked() {
+                            sensor.mounting = Mounting::default();
+                        }
+                    });
+                } else {
+                    ui.label("No sensor connected");
+                }
+                if let Some(status) = &self.mounting_status {
+                    ui.label(status);
+                }
+                self.calibration.ui(ui);
+
+                // extrinsic calibration ui
+                ui.separator();
+                ui.heading("Extrinsic calibration");
+                self.extrinsics
+                    .ui(ui, &mut self.sensors, &mut self.mountings);
+
+                // range calibration ui
+                ui.separator();
+                ui.heading("Range calibration");
+                let device = self
+                    .sensors
+                    .get(self.selected)
+                    .map(|s| s.key.clone())
+                    .unwrap_or_default();
+                self.range_calibration.ui(ui, &device);
+
+                // overlays ui
+                ui.separator();
+                ui.heading("Overlays");
+                self.polar_grid.ui(ui);
+
+                // measurement ui
+                ui.separator();
+                ui.heading("Measure");
+                self.measure.ui(ui);
+
+                // odometry ui
+                ui.separator();
+                ui.heading("Odometry");
+                self.odometry.ui(ui);
+
+                // occupancy grid ui
+                ui.separator();
+                ui.heading("Map");
+                self.occupancy.ui(ui);
+                if ui.button("Export PGM + YAML").clicked() {
+                    let path = self.export_path("pgm");
+                    self.export_status = Some(self.occupancy.export(&path));
+                }
 
-            // stats ui
-            ui.separator();
-            ui.heading("Stats");
-            egui::Grid::new("stats")
-                .num_columns(2)
-                .striped(true)
-                .show(ui, |ui| {
-                    ui.label("CRC errors");
-                    ui.label(format!("{}", self.stats.crc_errors));
-                    ui.end_row();
-                    ui.label("Sample rate");
-                    ui.label(format!("{:.1}kHz", self.stats.sample_rate.get() * 1e-3));
-                    ui.end_row();
-                    ui.label("Angular rate");
-                    ui.label(format!("{:.1}Hz", self.stats.angular_rate.get()));
-                    ui.end_row();
-                    ui.label("Angular resolution");
-                    ui.label(format!("{:.2}°", self.stats.angular_resolution.get()));
-                    ui.end_row();
-                    ui.label("Min distance");
-                    ui.label(format!("{:.2}m", self.stats.min_dist.get()));
-                    ui.end_row();
-                    ui.label("Max distance");
-                    ui.label(format!("{:.2}m", self.stats.max_dist.get()));
-                });
+                // slam ui
+                ui.separator();
+                ui.heading("SLAM");
+                self.slam.ui(ui);
+
+                // localization ui
+                ui.separator();
+                ui.heading("Localization");
+                self.localization.ui(ui);
+
+                // zone ui
+                ui.separator();
+                ui.heading("Zones");
+                self.zones.ui(ui);
+                if !self.zones.events.is_empty() && ui.button("Save log").clicked() {
+                    let path = self.export_path("zones.log");
+                    let log = self.zones.events.to_text();
+                    self.export_status = Some(match std::fs::write(&path, log) {
+                        Ok(()) => format!("Saved {}", path.display()),
+                        Err(err) => format!("Log export failed: {}", err),
+                    });
+                }
 
-            // fetch new datapoints
-            if let Some(lidar_rx) = self.lidar_rx.as_ref() {
-                while let Ok(frame) = lidar_rx.try_recv() {
-                    match frame {
-                        Ld19Frame::Packet(packet) => {
-                            let fade_dur = Duration::from_millis(self.fade_duration_ms);
+                // background subtraction ui
+                ui.separator();
+                ui.heading("Motion");
+                self.background.ui(ui);
+                if !self.background.events.is_empty() && ui.button("Save log").clicked() {
+                    let path = self.export_path("motion.log");
+                    let log = self.background.events.to_text();
+                    self.export_status = Some(match std::fs::write(&path, log) {
+                        Ok(()) => format!("Saved {}", path.display()),
+                        Err(err) => format!("Log export failed: {}", err),
+                    });
+                }
 
-                            for point_angle in packet.iter_points() {
-                                self.lidar_points.push(LidarPoint {
-                                    point: *point_angle.1,
-                                    angle: point_angle.0,
-                                    instant: Instant::now(),
-                                });
-                            }
+                // line extraction ui
+                ui.separator();
+                ui.heading("Lines");
+                self.lines.ui(ui);
+                if self.lines.enabled && ui.button("Export CSV").clicked() {
+                    let path = self.export_path("lines.csv");
+                    self.export_status = Some(match std::fs::write(&path, self.lines.to_csv()) {
+                        Ok(()) => format!("Saved {}", path.display()),
+                        Err(err) => format!("CSV export failed: {}", err),
+                    });
+                }
 
-                            // filter datapoints
-                            let points: Vec<_> = self
-                                .lidar_points
-                                .iter()
-                                .filter(|p| {
-                                    Instant::now().duration_since(p.instant) < fade_dur
-                                        && p.point.normalized_intensity() > self.intensity_threshold
-                                })
-                                .copied()
-                                .collect();
-
-                            self.lidar_points = points;
-
-                            // calculate stats
-                            self.stats
-                                .angular_resolution
-                                .push(packet.delta_angle_per_point_deg());
-                            if self.stats.last_start_angle > packet.start_angle_deg() {
-                                let dt = Instant::now().duration_since(
-                                    self.stats.last_completed_rotation.unwrap_or(Instant::now()),
-                                );
-                                self.stats.last_completed_rotation = Some(Instant::now());
-                                self.stats.angular_rate.push(dt.as_secs_f32().recip());
-                                self.stats.sample_rate.push(
-                                    dt.as_secs_f32().recip()
-                                        * (360.0 / packet.delta_angle_per_point_deg()),
-                                );
-                            }
-                            self.stats.last_start_angle = packet.start_angle_deg();
-                            self.stats.max_dist.push(
-                                self.lidar_points
-                                    .iter()
-                                    .max_by(|a, b| {
-                                        a.point
-                                            .distance_in_meters()
-                                            .total_cmp(&b.point.distance_in_meters())
-                                    })
-                                    .map(|p| p.point.distance_in_meters())
-                                    .unwrap_or_default(),
-                            );
-                            self.stats.min_dist.push(
-                                self.lidar_points
-                                    .iter()
-                                    .min_by(|a, b| {
-                                        a.point
-                                            .distance_in_meters()
-                                            .total_cmp(&b.point.distance_in_meters())
-                                    })
-                                    .map(|p| p.point.distance_in_meters())
-                                    .unwrap_or_default(),
-                            );
-                        }
-                        Ld19Frame::CRCError => self.stats.crc_errors += 1,
+                // clustering ui
+                ui.separator();
+                ui.heading("Clusters");
+                self.clustering.ui(ui);
+
+                // tracking ui
+                ui.separator();
+                ui.heading("Tracking");
+                if !self.clustering.enabled {
+                    ui.label("Tracks are built from the clusters, enable clustering first");
+                }
+                self.tracker.ui(ui);
+
+                // people detection ui
+                ui.separator();
+                ui.heading("People");
+                if !self.clustering.enabled {
+                    ui.label("Legs are found among the clusters, enable clustering first");
+                }
+                self.people.ui(ui);
+
+                // landmark ui
+                ui.separator();
+                ui.heading("Landmarks");
+                if !self.lines.enabled || !self.clustering.enabled {
+                    ui.label("Corners need the lines, circles the clusters");
+                }
+                self.landmarks.ui(ui);
+
+                // docking ui
+                ui.separator();
+                ui.heading("Docking");
+                if !self.landmarks.enabled {
+                    ui.label(
+                        "The strips are found among the reflector landmarks, enable them first",
+                    );
+                }
+                self.docking.ui(ui);
+                if self.docking.has_log() && ui.button("Save log").clicked() {
+                    let path = self.export_path("docking.csv");
+                    self.export_status = Some(match std::fs::write(&path, self.docking.to_csv()) {
+                        Ok(()) => format!("Saved {}", path.display()),
+                        Err(err) => format!("Log export failed: {}", err),
+                    });
+                }
+
+                // export ui
+                ui.separator();
+                ui.heading("Export");
+                ui.horizontal(|ui| {
+                    let connected = !self.sensors.is_empty();
+                    if ui
+                        .add_enabled(connected, egui::Button::new("PNG"))
+                        .clicked()
+                    {
+                        ctx.send_viewport_cmd(egui::ViewportCommand::Screenshot);
+                        self.png_requested = true;
+                    }
+                    if ui
+                        .add_enabled(connected, egui::Button::new("SVG"))
+                        .clicked()
+                    {
+                        self.svg_requested = true;
                     }
+                });
+                if let Some(status) = &self.export_status {
+                    ui.label(status);
                 }
-            }
+
+                // stats ui
+                ui.separator();
+                ui.heading("Stats");
+                let several = self.sensors.len() > 1;
+                for sensor in &self.sensors {
+                    if several {
+                        ui.colored_label(sensor.color, &sensor.port);
+                    }
+                    sensor.stats_ui(ui);
+                }
+                egui::Grid::new("stats")
+                    .num_columns(2)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        if self.people.enabled {
+                            ui.label("People");
+                            ui.label(format!("{}", self.people.count()));
+                            ui.end_row();
+                        }
+                    });
+            });
         });
 
         egui::CentralPanel::default().show(ctx, |ui| {
-            if self.serial_port.is_empty() {
+            if self.sensors.is_empty() {
                 ui.vertical_centered(|ui| {
                     ui.add_space(ui.available_height() * 0.5);
                     ui.heading("LIDAR not connected");
                     ui.label("Connect your LIDAR device and select a serial port")
                 });
             } else {
-                egui_plot::Plot::new("plot")
-                    .allow_zoom(true)
-                    .allow_drag(true)
-                    .allow_scroll(false)
-                    .auto_bounds(Vec2b::new(false, false))
-                    .x_axis_label("m")
-                    .y_axis_label("m")
-                    .data_aspect(1.0)
-                    .coordinates_formatter(
-                        egui_plot::Corner::LeftBottom,
-                        CoordinatesFormatter::new(|p, _| {
-                            let d = (p.x * p.x + p.y * p.y).sqrt();
-                            let ang = p.x.atan2(p.y).to_degrees();
-                            format!("d={:.2}m θ={:.2}°", d, ang)
-                        }),
-                    )
-                    .show(ui, |plot_ui| {
-                        let points: Vec<_> = self
-                            .lidar_points
-                            .iter()
-                            .map(|p| {
-                                let rad = p.angle.to_radians();
-
-                                // align +y with the forward direction of the sensor
-                                let x = rad.sin() * p.point.distance_in_meters();
-                                let y = rad.cos() * p.point.distance_in_meters();
-
-                                [x as f64, y as f64]
-                            })
-                            .collect();
-
-                        let plot_points = Points::new(points).radius(2.5).color(Color32::GREEN);
-                        plot_ui.points(plot_points);
+                // unprocessed points of the shown scan next to the processed ones
+                let mut raw_rect = None;
+                if self.show_raw {
+                    egui::SidePanel::right("raw")
+                        .resizable(false)
+                        .exact_width(ui.available_width() * 0.5)
+                        .show_inside(ui, |ui| {
+                            let response = scan_plot("raw_plot").show(ui, |plot_ui| {
+                                self.polar_grid.show(plot_ui, &self.selected_mounting());
+                                let Some(scan) = self.displayed_scan() else {
+                                    return;
+                                };
+                                for (i, sensor) in self.sensors.iter().enumerate() {
+                                    let raw: Vec<_> = scan
+                                        .raw
+                                        .iter()
+                                        .filter(|p| p.sensor == i)
+                                        .map(|p| scan.position(p))
+                                        .collect();
+                                    plot_ui.points(
+                                        Points::new(raw)
+                                            .radius(2.5)
+                                            .color(sensor.color)
+                                            .name(format!("{} (raw)", sensor.port)),
+                                    );
+                                }
+                            });
+                            raw_rect = Some(response.response.rect);
+                        });
+                }
+
+                let response = scan_plot("plot").show(ui, |plot_ui| {
+                    // a click goes to the first tool taking it, never to several
+                    let mut click = plot_ui
+                        .response()
+                        .clicked()
+                        .then(|| plot_ui.pointer_coordinate())
+                        .flatten();
+
+                    self.occupancy.show(plot_ui, self.odometry.pose().inverse());
+                    self.slam.show(plot_ui);
+                    self.localization.show(plot_ui, &mut click);
+                    self.odometry.show(plot_ui);
+                    let mounting = self.selected_mounting();
+                    self.polar_grid.show(plot_ui, &mounting);
+                    if let Some(sensor) = self.sensors.get_mut(self.selected) {
+                        sensor.pipeline.show(plot_ui, &sensor.mounting, &mut click);
+                    }
+
+                    // group the points by opacity, one plot item per level
+                    let mut levels = vec![vec![vec![]; FADE_LEVELS]; self.sensors.len()];
+
+                    match self.fade_mode {
+                        // frozen view of the buffered scans
+                        FadeMode::Age if self.paused => {
+                            let scans = self.frozen.iter().skip(self.replay_index);
+                            push_trail(&mut levels, scans, 1);
+                        }
+                        FadeMode::Persistence if self.paused => {
+                            let scans = self.frozen.iter().skip(self.replay_index);
+                            push_trail(&mut levels, scans, self.persistence_scans);
+                        }
+                        FadeMode::Age => {
+                            // scans are shown once assembled, i.e. delayed by one rotation
+                            let now = Instant::now();
+                            let fade_dur = Duration::from_millis(self.fade_duration_ms);
+                            let delay = self
+                                .scan_history
+                                .front()
+                                .map(|s| s.completed.duration_since(s.started))
+                                .unwrap_or_default();
+
+                            for scan in &self.scan_history {
+                                if now.duration_since(scan.completed).saturating_sub(delay)
+                                    >= fade_dur
+                                {
+                                    break;
+                                }
+
+                                for p in &scan.points {
+                                    let age = now.duration_since(p.instant).saturating_sub(delay);
+                                    let sensor_levels = levels.get_mut(p.sensor);
+                                    if let (true, Some(sensor_levels)) =
+                                        (age < fade_dur, sensor_levels)
+                                    {
+                                        let alpha =
+                                            1.0 - age.as_secs_f32() / fade_dur.as_secs_f32();
+                                        sensor_levels[fade_level(alpha)].push(scan.position(p));
+                                    }
+                                }
+                            }
+                        }
+                        FadeMode::Persistence => {
+                            let scans = self.scan_history.iter();
+                            push_trail(&mut levels, scans, self.persistence_scans);
+                        }
+                    }
+
+                    let snap_points: Vec<_> = levels.iter().flatten().flatten().copied().collect();
+
+                    if std::mem::take(&mut self.svg_requested) {
+                        let status = self.export_svg(
+                            &levels,
+                            plot_ui.plot_bounds(),
+                            plot_ui.response().rect.size(),
+                            plot_ui.ctx().style().visuals.extreme_bg_color,
+                        );
+                        self.export_status = Some(status);
+                    }
+
+                    for (sensor, levels) in self.sensors.iter().zip(levels) {
+                        for (level, points) in levels.into_iter().enumerate() {
+                            let alpha = (level + 1) as f32 / FADE_LEVELS as f32;
+                            let plot_points = Points::new(points)
+                                .radius(2.5)
+                                .color(sensor.color.gamma_multiply(alpha))
+                                .name(&sensor.port);
+                            plot_ui.points(plot_points);
+                        }
+                    }
+                    // the sensors and their forward directions in the robot frame
+                    for sensor in self.sensors.iter().filter(|s| s.enabled) {
+                        let position = sensor.mounting.transform([0.0, 0.0]);
+                        let forward = sensor.mounting.transform([0.0, 1.0]);
                         plot_ui.arrows(
                             Arrows::new(
-                                PlotPoints::new(vec![[0.0, 0.0]]),
-                                PlotPoints::new(vec![[0.0, 1.0]]),
+                                PlotPoints::new(vec![position]),
+                                PlotPoints::new(vec![forward]),
                             )
                             .allow_hover(false),
                         );
 
-                        let plot_points = Points::new(vec![[0.0, 0.0]])
+                        let plot_points = Points::new(vec![position])
                             .radius(10.0)
                             .color(Color32::GOLD);
                         plot_ui.points(plot_points);
-                    });
+                    }
+
+                    self.zones.show(plot_ui, &mut click);
+                    self.background.show(plot_ui);
+                    self.clustering.show(plot_ui);
+                    self.tracker.show(plot_ui);
+                    self.people.show(plot_ui);
+                    self.lines.show(plot_ui);
+                    self.landmarks.show(plot_ui);
+                    self.docking.show(plot_ui);
+                    self.extrinsics.show(plot_ui);
+                    self.measure.show(plot_ui, &snap_points, &mut click);
+
+                    let wall_points = self.wall_points();
+                    if let Some(sensor) = self.sensors.get_mut(self.selected) {
+                        self.calibration.show(
+                            plot_ui,
+                            &wall_points,
+                            &mut sensor.mounting,
+                            &mut click,
+                        );
+                    }
+                });
+                let rect = response.response.rect;
+                self.plot_rect = Some(raw_rect.map_or(rect, |raw| rect.union(raw)));
             }
         });
     }
 }
-
-fn available_serial_ports() -> Vec<String> {
-    if let Ok(ports) = tokio_serial::available_ports() {
-        // Note: filtering on port type does not work / is unreliable
-        return ports
-            .iter()
-            .map(|p| p.port_name.clone())
-            .filter(|p| !p.contains("ttyS"))
-            .collect();
-    }
-
-    vec![]
-}
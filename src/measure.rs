@@ -0,0 +1,283 @@
+use eframe::egui::{self, Align2, Color32};
+use egui_plot::{Line, PlotPoint, PlotPoints, PlotUi, Points, Polygon, Text};
+
+use crate::export::Svg;
+use crate::geometry::{angle_deg, distance, polygon_area, polygon_perimeter};
+
+const MEASURE_COLOR: Color32 = Color32::YELLOW;
+
+/// Distance in screen pixels within which clicks snap to a lidar point.
+const SNAP_RADIUS_PX: f32 = 12.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tool {
+    None,
+    Ruler,
+    Angle,
+    Area,
+}
+
+#[derive(Debug, Clone)]
+pub enum Measurement {
+    Distance([f64; 2], [f64; 2]),
+    /// angle at the vertex (second point) between the other two
+    Angle([f64; 2], [f64; 2], [f64; 2]),
+    Area(Vec<[f64; 2]>),
+}
+
+impl Measurement {
+    pub fn label(&self) -> String {
+        match self {
+            Measurement::Distance(a, b) => format!("{:.3}m", distance(*a, *b)),
+            Measurement::Angle(a, v, b) => format!("{:.1}°", angle_deg(*a, *v, *b)),
+            Measurement::Area(poly) => format!(
+                "{:.3}m² (perimeter {:.2}m)",
+                polygon_area(poly),
+                polygon_perimeter(poly)
+            ),
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Measurement::Distance(..) => "Distance",
+            Measurement::Angle(..) => "Angle",
+            Measurement::Area(..) => "Area",
+        }
+    }
+
+    fn show(&self, plot_ui: &mut PlotUi, color: Color32) {
+        match self {
+            Measurement::Distance(a, b) => {
+                plot_ui.line(Line::new(PlotPoints::new(vec![*a, *b])).color(color));
+                plot_ui.text(
+                    Text::new(midpoint(&[*a, *b]), self.label())
+                        .color(color)
+                        .anchor(Align2::LEFT_BOTTOM),
+                );
+            }
+            Measurement::Angle(a, v, b) => {
+                plot_ui.line(Line::new(PlotPoints::new(vec![*a, *v, *b])).color(color));
+                plot_ui.text(
+                    Text::new(PlotPoint::new(v[0], v[1]), self.label())
+                        .color(color)
+                        .anchor(Align2::LEFT_BOTTOM),
+                );
+            }
+            Measurement::Area(poly) => {
+                plot_ui.polygon(
+                    Polygon::new(PlotPoints::new(poly.clone()))
+                        .stroke((1.0, color))
+                        .fill_color(color.gamma_multiply(0.15)),
+                );
+                plot_ui.text(Text::new(midpoint(poly), self.label()).color(color));
+            }
+        }
+    }
+
+    fn write_svg(&self, svg: &mut Svg, color: Color32) {
+        match self {
+            Measurement::Distance(a, b) => {
+                svg.polyline(&[*a, *b], 1.5, color, false);
+                let mid = midpoint(&[*a, *b]);
+                svg.text([mid.x, mid.y], &self.label(), color);
+            }
+            Measurement::Angle(a, v, b) => {
+                svg.polyline(&[*a, *v, *b], 1.5, color, false);
+                svg.text(*v, &self.label(), color);
+            }
+            Measurement::Area(poly) => {
+                svg.polygon(poly, color.gamma_multiply(0.15), color);
+                let mid = midpoint(poly);
+                svg.text([mid.x, mid.y], &self.label(), color);
+            }
+        }
+    }
+}
+
+pub struct MeasureTools {
+    tool: Tool,
+    pending: Vec<[f64; 2]>,
+    measurements: Vec<Measurement>,
+}
+
+impl Default for MeasureTools {
+    fn default() -> Self {
+        Self {
+            tool: Tool::None,
+            pending: vec![],
+            measurements: vec![],
+        }
+    }
+}
+
+impl MeasureTools {
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let before = self.tool;
+            ui.selectable_value(&mut self.tool, Tool::None, "Off");
+            ui.selectable_value(&mut self.tool, Tool::Ruler, "Ruler");
+            ui.selectable_value(&mut self.tool, Tool::Angle, "Angle");
+            ui.selectable_value(&mut self.tool, Tool::Area, "Area");
+            if before != self.tool {
+                self.pending.clear();
+            }
+        });
+
+        match self.tool {
+            Tool::None => {}
+            Tool::Ruler => {
+                ui.label("Click two points");
+            }
+            Tool::Angle => {
+                ui.label("Click a point, the vertex and another point");
+            }
+            Tool::Area => {
+                ui.label("Click the corners, click the first corner again to close");
+                if ui.button("Finish polygon").clicked() {
+                    self.finish_polygon();
+                }
+            }
+        }
+
+        if !self.measurements.is_empty() {
+            let mut remove = None;
+            egui::Grid::new("measurements")
+                .num_columns(3)
+                .striped(true)
+                .show(ui, |ui| {
+                    for (i, m) in self.measurements.iter().enumerate() {
+                        ui.label(format!("{} #{}", m.kind(), i + 1));
+                        ui.label(m.label());
+                        if ui.small_button("🗑").clicked() {
+                            remove = Some(i);
+                        }
+                        ui.end_row();
+                    }
+                });
+
+            if let Some(i) = remove {
+                self.measurements.remove(i);
+            }
+
+            if ui.button("Clear measurements").clicked() {
+                self.measurements.clear();
+                self.pending.clear();
+            }
+        }
+    }
+
+    /// Takes the plot `click` while a tool is selected and draws all measurements.
+    /// `points` are the currently displayed lidar points used for snapping.
+    pub fn show(
+        &mut self,
+        plot_ui: &mut PlotUi,
+        points: &[[f64; 2]],
+        click: &mut Option<PlotPoint>,
+    ) {
+        let color = MEASURE_COLOR;
+
+        if self.tool != Tool::None {
+            if let Some(pointer) = click.take() {
+                let pos = snap(plot_ui, [pointer.x, pointer.y], points);
+                self.add_point(plot_ui, pos);
+            }
+        }
+
+        for m in &self.measurements {
+            m.show(plot_ui, color);
+        }
+
+        // preview of the measurement in progress
+        if !self.pending.is_empty() {
+            let mut preview = self.pending.clone();
+            if let Some(pointer) = plot_ui.pointer_coordinate() {
+                preview.push(snap(plot_ui, [pointer.x, pointer.y], points));
+            }
+
+            plot_ui.points(
+                Points::new(self.pending.clone())
+                    .radius(4.0)
+                    .color(color)
+                    .allow_hover(false),
+            );
+            plot_ui.line(
+                Line::new(PlotPoints::new(preview))
+                    .color(color.gamma_multiply(0.5))
+                    .allow_hover(false),
+            );
+        }
+    }
+
+    pub fn write_svg(&self, svg: &mut Svg) {
+        for m in &self.measurements {
+            m.write_svg(svg, MEASURE_COLOR);
+        }
+    }
+
+    fn add_point(&mut self, plot_ui: &PlotUi, pos: [f64; 2]) {
+        match self.tool {
+            Tool::None => {}
+            Tool::Ruler => {
+                self.pending.push(pos);
+                if let [a, b] = self.pending[..] {
+                    self.measurements.push(Measurement::Distance(a, b));
+                    self.pending.clear();
+                }
+            }
+            Tool::Angle => {
+                self.pending.push(pos);
+                if let [a, v, b] = self.pending[..] {
+                    self.measurements.push(Measurement::Angle(a, v, b));
+                    self.pending.clear();
+                }
+            }
+            Tool::Area => {
+                // clicking the first corner closes the polygon
+                if let Some(first) = self.pending.first() {
+                    let first = plot_ui.screen_from_plot(PlotPoint::new(first[0], first[1]));
+                    let pos = plot_ui.screen_from_plot(PlotPoint::new(pos[0], pos[1]));
+                    if self.pending.len() >= 3 && first.distance(pos) < SNAP_RADIUS_PX {
+                        self.finish_polygon();
+                        return;
+                    }
+                }
+                self.pending.push(pos);
+            }
+        }
+    }
+
+    fn finish_polygon(&mut self) {
+        if self.pending.len() >= 3 {
+            self.measurements
+                .push(Measurement::Area(std::mem::take(&mut self.pending)));
+        }
+    }
+}
+
+/// Returns the lidar point closest to `pos` if it lies within the snap radius.
+fn snap(plot_ui: &PlotUi, pos: [f64; 2], points: &[[f64; 2]]) -> [f64; 2] {
+    let screen_pos = plot_ui.screen_from_plot(PlotPoint::new(pos[0], pos[1]));
+
+    points
+        .iter()
+        .map(|p| {
+            let d = plot_ui
+                .screen_from_plot(PlotPoint::new(p[0], p[1]))
+                .distance(screen_pos);
+            (d, *p)
+        })
+        .filter(|(d, _)| *d < SNAP_RADIUS_PX)
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, p)| p)
+        .unwrap_or(pos)
+}
+
+fn midpoint(points: &[[f64; 2]]) -> PlotPoint {
+    let n = points.len().max(1) as f64;
+    let (x, y) = points
+        .iter()
+        .fold((0.0, 0.0), |acc, p| (acc.0 + p[0], acc.1 + p[1]));
+
+    PlotPoint::new(x / n, y / n)
+}
@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use eframe::egui::{self, Color32, DragValue};
+use egui_plot::{Line, PlotPoint, PlotPoints, PlotUi, Points};
+
+use crate::export::Svg;
+use crate::geometry::{fit_line, segment_distance, LineFit, Pose2D};
+
+const MOUNTINGS_FILE: &str = "mountings.txt";
+
+/// Points within this distance of the picked segment belong to the wall.
+const WALL_TOLERANCE: f64 = 0.05;
+
+/// Pose of a sensor on the robot.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mounting {
+    pub x: f64,
+    pub y: f64,
+    /// bearing of the sensor's forward axis, clockwise like the θ of the coordinates
+    pub yaw_deg: f64,
+    /// the sensor is mounted upside down
+    pub mirror: bool,
+}
+
+impl Default for Mounting {
+    fn default() -> Self {
+        Self {
+            x: 0.0,
+            y: 0.0,
+            yaw_deg: 0.0,
+            mirror: false,
+        }
+    }
+}
+
+impl Mounting {
+    pub fn pose(&self) -> Pose2D {
+        Pose2D::new(self.x, self.y, -self.yaw_deg.to_radians())
+    }
+
+    pub fn set_pose(&mut self, pose: Pose2D) {
+        self.x = pose.x;
+        self.y = pose.y;
+        self.yaw_deg = -pose.theta.to_degrees();
+        self.yaw_deg = (self.yaw_deg + 180.0).rem_euclid(360.0) - 180.0;
+    }
+
+    /// Transforms a point from the sensor into the robot frame.
+    pub fn transform(&self, p: [f64; 2]) -> [f64; 2] {
+        let p = if self.mirror { [-p[0], p[1]] } else { p };
+        self.pose().transform(p)
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        egui::Grid::new("mounting").num_columns(2).show(ui, |ui| {
+            ui.label("x");
+            ui.add(DragValue::new(&mut self.x).speed(0.005).suffix("m"));
+            ui.end_row();
+            ui.label("y");
+            ui.add(DragValue::new(&mut self.y).speed(0.005).suffix("m"));
+            ui.end_row();
+            ui.label("Yaw");
+            ui.add(
+                DragValue::new(&mut self.yaw_deg)
+                    .speed(0.1)
+                    .range(-180.0..=180.0)
+                    .suffix("°"),
+            );
+            ui.end_row();
+            ui.label("Mirror");
+            ui.checkbox(&mut self.mirror, "upside down");
+        });
+    }
+
+    fn to_line(self, key: &str) -> String {
+        format!(
+            "{} {} {} {} {}",
+            key, self.x, self.y, self.yaw_deg, self.mirror
+        )
+    }
+
+    fn from_line(line: &str) -> Option<(String, Self)> {
+        let mut parts = line.split_whitespace();
+        let key = parts.next()?.to_owned();
+        let mounting = Mounting {
+            x: parts.next()?.parse().ok()?,
+            y: parts.next()?.parse().ok()?,
+            yaw_deg: parts.next()?.parse().ok()?,
+            mirror: parts.next()?.parse().ok()?,
+        };
+
+        Some((key, mounting))
+    }
+}
+
+/// Mountings keyed by device, persisted in a plain text file.
+pub struct MountingStore {
+    path: PathBuf,
+    mountings: HashMap<String, Mounting>,
+}
+
+impl MountingStore {
+    pub fn load() -> Self {
+        let path = PathBuf::from(MOUNTINGS_FILE);
+        let mountings = std::fs::read_to_string(&path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(Mounting::from_line)
+            .collect();
+
+        Self { path, mountings }
+    }
+
+    pub fn get(&self, device: &str) -> Mounting {
+        self.mountings.get(device).copied().unwrap_or_default()
+    }
+
+    pub fn save(&mut self, device: &str, mounting: Mounting) -> std::io::Result<()> {
+        self.mountings.insert(device.to_owned(), mounting);
+
+        let mut lines: Vec<_> = self
+            .mountings
+            .iter()
+            .map(|(key, m)| m.to_line(key))
+            .collect();
+        lines.sort();
+
+        std::fs::write(&self.path, lines.join("\n") + "\n")
+    }
+}
+
+enum CalibrationStep {
+    Idle,
+    /// two clicks around a straight wall
+    PickWall(Vec<[f64; 2]>),
+    /// two clicks where the wall is supposed to be
+    PickTarget(LineFit, Vec<[f64; 2]>),
+}
+
+/// Corrects the mounting by aligning a wall seen in the scan with a target line.
+pub struct WallCalibration {
+    step: CalibrationStep,
+    status: Option<String>,
+}
+
+impl Default for WallCalibration {
+    fn default() -> Self {
+        Self {
+            step: CalibrationStep::Idle,
+            status: None,
+        }
+    }
+}
+
+impl WallCalibration {
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        match self.step {
+            CalibrationStep::Idle => {
+                if ui
+                    .button("Calibrate to wall")
+                    .on_hover_text("Align a straight wall in the scan with a target line")
+                    .clicked()
+                {
+                    self.step = CalibrationStep::PickWall(vec![]);
+                    self.status = None;
+                }
+            }
+            CalibrationStep::PickWall(_) => {
+                ui.label("Click both ends of a straight wall");
+            }
+            CalibrationStep::PickTarget(fit, _) => {
+                ui.label(format!(
+                    "Wall found (rms {:.1}mm), click two points on the target line",
+                    fit.rms * 1e3
+                ));
+            }
+        }
+
+        if !matches!(self.step, CalibrationStep::Idle) && ui.button("Cancel").clicked() {
+            self.step = CalibrationStep::Idle;
+        }
+        if let Some(status) = &self.status {
+            ui.label(status);
+        }
+    }
+
+    /// `points` are the scan points in the robot frame using the current `mounting`,
+    /// the plot `click` is taken while picking.
+    pub fn show(
+        &mut self,
+        plot_ui: &mut PlotUi,
+        points: &[[f64; 2]],
+        mounting: &mut Mounting,
+        click: &mut Option<PlotPoint>,
+    ) {
+        let color = Color32::LIGHT_BLUE;
+        let click = match self.step {
+            CalibrationStep::Idle => None,
+            _ => click.take().map(|p| [p.x, p.y]),
+        };
+
+        match &mut self.step {
+            CalibrationStep::Idle => {}
+            CalibrationStep::PickWall(clicks) => {
+                clicks.extend(click);
+                if let [a, b] = clicks[..] {
+                    let wall: Vec<_> = points
+                        .iter()
+                        .filter(|p| segment_distance(**p, a, b) < WALL_TOLERANCE)
+                        .copied()
+                        .collect();
+
+                    match fit_line(&wall).filter(|_| wall.len() >= 5) {
+                        Some(fit) => self.step = CalibrationStep::PickTarget(fit, vec![]),
+                        None => {
+                            self.status = Some("Not enough points near the wall".to_owned());
+                            clicks.clear();
+                        }
+                    }
+                }
+            }
+            CalibrationStep::PickTarget(fit, clicks) => {
+                clicks.extend(click);
+                if let [a, b] = clicks[..] {
+                    let target = fit_line(&[a, b]).expect("two points");
+                    let correction = line_alignment(fit, &target);
+                    mounting.set_pose(correction.compose(&mounting.pose()));
+
+                    self.status = Some(format!(
+                        "Corrected by {:.1}° and {:.3}m",
+                        -correction.theta.to_degrees(),
+                        correction.x.hypot(correction.y)
+                    ));
+                    self.step = CalibrationStep::Idle;
+                }
+            }
+        }
+
+        // visualize the progress
+        let (fit, clicks) = match &self.step {
+            CalibrationStep::Idle => return,
+            CalibrationStep::PickWall(clicks) => (None, clicks),
+            CalibrationStep::PickTarget(fit, clicks) => (Some(fit), clicks),
+        };
+
+        if let Some(fit) = fit {
+            plot_ui.line(
+                Line::new(PlotPoints::new(wall_segment(fit, points).to_vec()))
+                    .color(color)
+                    .width(3.0)
+                    .name("Wall"),
+            );
+        }
+
+        let mut preview = clicks.clone();
+        if let Some(pointer) = plot_ui.pointer_coordinate() {
+            preview.push([pointer.x, pointer.y]);
+        }
+        plot_ui.points(Points::new(clicks.clone()).radius(4.0).color(color));
+        plot_ui.line(Line::new(PlotPoints::new(preview)).color(color.gamma_multiply(0.5)));
+    }
+
+    /// Draws the progress without the pointer, `points` as passed to `show`.
+    pub fn write_svg(&self, svg: &mut Svg, points: &[[f64; 2]]) {
+        let color = Color32::LIGHT_BLUE;
+        let (fit, clicks) = match &self.step {
+            CalibrationStep::Idle => return,
+            CalibrationStep::PickWall(clicks) => (None, clicks),
+            CalibrationStep::PickTarget(fit, clicks) => (Some(fit), clicks),
+        };
+
+        if let Some(fit) = fit {
+            svg.polyline(&wall_segment(fit, points), 3.0, color, false);
+        }
+        for p in clicks {
+            svg.circle(*p, 4.0, color);
+        }
+    }
+}
+
+/// Part of the fitted wall covered by the points near it.
+fn wall_segment(fit: &LineFit, points: &[[f64; 2]]) -> [[f64; 2]; 2] {
+    let (min, max) = points
+        .iter()
+        .filter(|p| fit.distance_to(**p).abs() < WALL_TOLERANCE)
+        .map(|p| fit.project(*p))
+        .fold((0.0f64, 0.0f64), |(lo, hi), t| (lo.min(t), hi.max(t)));
+
+    [fit.point_at(min), fit.point_at(max)]
+}
+
+/// Transform that moves the `detected` line onto the `target` line.
+/// The offset along the line cannot be observed and is left as is.
+fn line_alignment(detected: &LineFit, target: &LineFit) -> Pose2D {
+    let angle = |d: [f64; 2]| d[1].atan2(d[0]);
+    let mut theta = angle(target.direction) - angle(detected.direction);
+
+    // lines have no direction, take the smaller rotation
+    theta = (theta + std::f64::consts::FRAC_PI_2).rem_euclid(std::f64::consts::PI)
+        - std::f64::consts::FRAC_PI_2;
+
+    let rotation = Pose2D::new(0.0, 0.0, theta);
+    let centroid = rotation.transform(detected.centroid);
+    let offset = target.distance_to(centroid);
+    let n = target.normal();
+
+    Pose2D::new(-offset * n[0], -offset * n[1], theta)
+}
@@ -0,0 +1,654 @@
+use std::path::Path;
+use std::time::Instant;
+
+use eframe::egui::{self, Color32, ColorImage, Slider, TextureHandle, TextureOptions};
+use egui_plot::{PlotImage, PlotPoint, PlotUi};
+
+use crate::export::Svg;
+use crate::geometry::Pose2D;
+use crate::overlay::MAX_RANGE;
+use crate::scan::Scan;
+
+/// Log-odds added for a hit and a pass-through.
+const LOG_ODDS_OCCUPIED: f32 = 0.85;
+const LOG_ODDS_FREE: f32 = -0.4;
+const LOG_ODDS_LIMIT: f32 = 5.0;
+
+/// Probability thresholds of the map_server format.
+const OCCUPIED_THRESHOLD: f32 = 0.65;
+const FREE_THRESHOLD: f32 = 0.196;
+
+/// Cells along a side of a new grid, larger maps need a coarser resolution.
+pub const MAX_GRID_CELLS: usize = 2048;
+
+/// Square grid of log-odds, cell `(0, 0)` is the lower left corner at `origin`.
+pub struct GridMap {
+    pub resolution: f64,
+    pub width: usize,
+    pub height: usize,
+    pub origin: [f64; 2],
+    pub cells: Vec<f32>,
+}
+
+impl GridMap {
+    /// Empty grid of `size` meters centered at the origin,
+    /// cropped to `MAX_GRID_CELLS` along a side.
+    pub fn new(resolution: f64, size: f64) -> Self {
+        let cells = ((size / resolution).ceil() as usize).min(MAX_GRID_CELLS);
+        let half = cells as f64 * resolution * 0.5;
+
+        Self {
+            resolution,
+            width: cells,
+            height: cells,
+            origin: [-half, -half],
+            cells: vec![0.0; cells * cells],
+        }
+    }
+
+    /// Finest resolution a new grid of `size` meters can have.
+    pub fn min_resolution(size: f64) -> f64 {
+        size / MAX_GRID_CELLS as f64
+    }
+
+    /// Cell containing `p`, may be outside of the grid.
+    pub fn cell(&self, p: [f64; 2]) -> [i64; 2] {
+        [
+            ((p[0] - self.origin[0]) / self.resolution).floor() as i64,
+            ((p[1] - self.origin[1]) / self.resolution).floor() as i64,
+        ]
+    }
+
+    pub fn index(&self, [x, y]: [i64; 2]) -> Option<usize> {
+        let inside = (0..self.width as i64).contains(&x) && (0..self.height as i64).contains(&y);
+        inside.then(|| y as usize * self.width + x as usize)
+    }
+
+    pub fn size(&self) -> [f64; 2] {
+        [
+            self.width as f64 * self.resolution,
+            self.height as f64 * self.resolution,
+        ]
+    }
+
+    pub fn center(&self) -> [f64; 2] {
+        let [w, h] = self.size();
+        [self.origin[0] + w * 0.5, self.origin[1] + h * 0.5]
+    }
+
+    fn add(&mut self, cell: [i64; 2], log_odds: f32) {
+        if let Some(i) = self.index(cell) {
+            self.cells[i] = (self.cells[i] + log_odds).clamp(-LOG_ODDS_LIMIT, LOG_ODDS_LIMIT);
+        }
+    }
+
+    /// Traces the beam from `from` to `to`, `hit` marks the end as occupied.
+    pub fn integrate_ray(&mut self, from: [f64; 2], to: [f64; 2], hit: bool) {
+        let end = self.cell(to);
+        for cell in bresenham(self.cell(from), end) {
+            if cell != end {
+                self.add(cell, LOG_ODDS_FREE);
+            }
+        }
+        self.add(
+            end,
+            if hit {
+                LOG_ODDS_OCCUPIED
+            } else {
+                LOG_ODDS_FREE
+            },
+        );
+    }
+
+    /// Traces `beams` from `origin`, both in the robot frame placed at `pose`.
+    pub fn integrate_beams(&mut self, pose: Pose2D, beams: &[Beam]) {
+        for beam in beams {
+            let origin = pose.transform(beam.origin);
+            self.integrate_ray(origin, pose.transform(beam.end), beam.hit);
+        }
+    }
+
+    /// Greyscale image, the first row is the top of the map. Blocks of cells are
+    /// merged into one pixel to keep the sides within `max_side` pixels,
+    /// occupied cells take precedence over free ones.
+    pub fn to_image(&self, max_side: usize) -> ColorImage {
+        let step = self.width.max(self.height).div_ceil(max_side.max(1)).max(1);
+        let (width, height) = (self.width.div_ceil(step), self.height.div_ceil(step));
+
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in (0..height).rev() {
+            for x in 0..width {
+                let block = (y * step..((y + 1) * step).min(self.height)).flat_map(|cy| {
+                    let row = cy * self.width;
+                    self.cells[row + x * step..row + ((x + 1) * step).min(self.width)].iter()
+                });
+                let (min, max) =
+                    block.fold((0.0f32, 0.0f32), |(min, max), l| (min.min(*l), max.max(*l)));
+                let l = if max > 0.0 { max } else { min };
+                let pixel = if l == 0.0 {
+                    Color32::TRANSPARENT
+                } else {
+                    let p = probability(l);
+                    let v = (255.0 * p) as u8;
+                    Color32::from_rgba_unmultiplied(v, v, v, (80.0 + 175.0 * p) as u8)
+                };
+                pixels.push(pixel);
+            }
+        }
+
+        ColorImage {
+            size: [width, height],
+            pixels,
+        }
+    }
+
+    /// Writes the map in the ROS map_server format, a PGM image and a YAML description.
+    pub fn save(&self, pgm_path: &Path) -> std::io::Result<()> {
+        let mut pgm = format!("P5\n{} {}\n255\n", self.width, self.height).into_bytes();
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let l = self.cells[y * self.width + x];
+                let p = probability(l);
+                pgm.push(if l == 0.0 {
+                    205
+                } else if p > OCCUPIED_THRESHOLD {
+                    0
+                } else if p < FREE_THRESHOLD {
+                    254
+                } else {
+                    205
+                });
+            }
+        }
+        std::fs::write(pgm_path, pgm)?;
+
+        let image = pgm_path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let yaml = format!(
+            "image: {}\nresolution: {}\norigin: [{}, {}, 0.0]\nnegate: 0\noccupied_thresh: {}\nfree_thresh: {}\n",
+            image, self.resolution, self.origin[0], self.origin[1], OCCUPIED_THRESHOLD, FREE_THRESHOLD
+        );
+        std::fs::write(pgm_path.with_extension("yaml"), yaml)
+    }
+
+    /// Reads a map in the ROS map_server format, unknown cells stay at zero.
+    /// The yaw of the origin is ignored, as by most of the ROS tools.
+    pub fn load(yaml_path: &Path) -> Result<Self, String> {
+        let yaml = std::fs::read_to_string(yaml_path).map_err(|e| e.to_string())?;
+        let mut image = None;
+        let mut resolution = None;
+        let mut origin = None;
+        let mut negate = false;
+        let mut occupied_threshold = OCCUPIED_THRESHOLD;
+        let mut free_threshold = FREE_THRESHOLD;
+
+        for line in yaml.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            let number = |v: &str| {
+                v.trim()
+                    .parse::<f64>()
+                    .map_err(|_| format!("invalid {}: {}", key.trim(), v))
+            };
+
+            match key.trim() {
+                "image" => image = Some(value.trim_matches(['"', '\'']).to_owned()),
+                "resolution" => resolution = Some(number(value)?),
+                "origin" => {
+                    let values = value
+                        .trim_matches(['[', ']'])
+                        .split(',')
+                        .map(number)
+                        .collect::<Result<Vec<_>, _>>()?;
+                    origin = Some([values[0], *values.get(1).ok_or("invalid origin")?]);
+                }
+                "negate" => negate = value == "1" || value == "true",
+                "occupied_thresh" => occupied_threshold = number(value)? as f32,
+                "free_thresh" => free_threshold = number(value)? as f32,
+                _ => {}
+            }
+        }
+
+        let image = image.ok_or("no image")?;
+        let resolution = resolution.ok_or("no resolution")?;
+        let origin = origin.ok_or("no origin")?;
+
+        // the image is relative to the description
+        let pgm_path = yaml_path.with_file_name(image);
+        let pgm = std::fs::read(&pgm_path).map_err(|e| format!("{}: {}", pgm_path.display(), e))?;
+        let (width, height, max, pixels) =
+            parse_pgm(&pgm).ok_or("only binary PGM images are supported")?;
+
+        let mut cells = vec![0.0; width * height];
+        for (i, value) in pixels.iter().enumerate() {
+            // the first row is the top of the map
+            let (x, y) = (i % width, height - 1 - i / width);
+            let value = *value as f32 / max as f32;
+            let p = if negate { value } else { 1.0 - value };
+            cells[y * width + x] = if p > occupied_threshold {
+                LOG_ODDS_LIMIT
+            } else if p < free_threshold {
+                -LOG_ODDS_LIMIT
+            } else {
+                0.0
+            };
+        }
+
+        Ok(Self {
+            resolution,
+            width,
+            height,
+            origin,
+            cells,
+        })
+    }
+
+    /// The cell is likely occupied.
+    pub fn occupied(&self, i: usize) -> bool {
+        probability(self.cells[i]) > OCCUPIED_THRESHOLD
+    }
+
+    /// The cell is likely free.
+    pub fn free(&self, i: usize) -> bool {
+        self.cells[i] != 0.0 && probability(self.cells[i]) < FREE_THRESHOLD
+    }
+
+    /// Center of the cell with index `i`.
+    pub fn position(&self, i: usize) -> [f64; 2] {
+        [
+            self.origin[0] + ((i % self.width) as f64 + 0.5) * self.resolution,
+            self.origin[1] + ((i / self.width) as f64 + 0.5) * self.resolution,
+        ]
+    }
+}
+
+/// Width, height, max. value and pixels of a binary (P5) 8-bit PGM image.
+fn parse_pgm(data: &[u8]) -> Option<(usize, usize, u8, &[u8])> {
+    let mut fields = vec![];
+    let mut i = 0;
+    while fields.len() < 4 {
+        match data.get(i)? {
+            b'#' => {
+                while *data.get(i)? != b'\n' {
+                    i += 1;
+                }
+            }
+            c if c.is_ascii_whitespace() => i += 1,
+            _ => {
+                let start = i;
+                while !data.get(i)?.is_ascii_whitespace() {
+                    i += 1;
+                }
+                fields.push(std::str::from_utf8(&data[start..i]).ok()?);
+            }
+        }
+    }
+
+    let width: usize = fields[1].parse().ok()?;
+    let height: usize = fields[2].parse().ok()?;
+    let max: u8 = fields[3].parse().ok()?;
+    // a single whitespace separates the header from the pixels
+    let pixels = data.get(i + 1..i + 1 + width * height)?;
+
+    (fields[0] == "P5" && max > 0).then_some((width, height, max, pixels))
+}
+
+/// End of a measured beam, `hit` is false where the range was clipped.
+#[derive(Debug, Clone, Copy)]
+pub struct Beam {
+    /// the sensor in the robot frame
+    pub origin: [f64; 2],
+    pub end: [f64; 2],
+    pub hit: bool,
+}
+
+/// Beams of the scan in the robot frame, clipped at `max_range`.
+pub fn beams(scan: &Scan, max_range: f32) -> Vec<Beam> {
+    scan.points
+        .iter()
+        .filter(|p| p.distance > 0.0)
+        .map(|p| {
+            let mut clipped = *p;
+            clipped.distance = p.distance.min(max_range);
+            Beam {
+                origin: scan.mounting_of(p.sensor).transform([0.0, 0.0]),
+                end: scan.position(&clipped),
+                hit: p.distance <= max_range,
+            }
+        })
+        .collect()
+}
+
+/// Texture of a grid map, uploaded again when the map changed.
+pub struct MapLayer {
+    name: &'static str,
+    texture: Option<TextureHandle>,
+    /// the texture is outdated
+    pub dirty: bool,
+}
+
+impl MapLayer {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            texture: None,
+            dirty: true,
+        }
+    }
+
+    /// `view` maps the map into the plot, e.g. the inverse of the robot pose.
+    pub fn show(&mut self, plot_ui: &mut PlotUi, grid: &GridMap, view: Pose2D, opacity: f32) {
+        if self.dirty || self.texture.is_none() {
+            let max_side = plot_ui.ctx().input(|i| i.max_texture_side);
+            let image = grid.to_image(max_side);
+            match &mut self.texture {
+                Some(texture) => texture.set(image, TextureOptions::NEAREST),
+                None => {
+                    self.texture = Some(plot_ui.ctx().load_texture(
+                        self.name,
+                        image,
+                        TextureOptions::NEAREST,
+                    ))
+                }
+            }
+            self.dirty = false;
+        }
+
+        let texture = self.texture.as_ref().expect("texture loaded");
+        let [cx, cy] = view.transform(grid.center());
+        let [w, h] = grid.size();
+        plot_ui.image(
+            PlotImage::new(texture, PlotPoint::new(cx, cy), [w as f32, h as f32])
+                .rotate(view.theta)
+                .tint(Color32::WHITE.gamma_multiply(opacity))
+                .allow_hover(false),
+        );
+    }
+
+    /// Embeds the map as `show` draws it.
+    pub fn write_svg(svg: &mut Svg, grid: &GridMap, view: Pose2D, opacity: f32) {
+        svg.image(
+            &grid.to_image(MAX_GRID_CELLS),
+            view.transform(grid.center()),
+            grid.size(),
+            view.theta,
+            opacity,
+        );
+    }
+}
+
+fn probability(log_odds: f32) -> f32 {
+    1.0 - 1.0 / (1.0 + log_odds.exp())
+}
+
+/// Cells on the line from `a` to `b`, both included.
+fn bresenham(a: [i64; 2], b: [i64; 2]) -> impl Iterator<Item = [i64; 2]> {
+    let dx = (b[0] - a[0]).abs();
+    let dy = -(b[1] - a[1]).abs();
+    let step = [(b[0] - a[0]).signum(), (b[1] - a[1]).signum()];
+    let mut cell = a;
+    let mut error = dx + dy;
+    let mut done = false;
+
+    std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        let current = cell;
+        if cell == b {
+            done = true;
+        } else {
+            let e2 = 2 * error;
+            if e2 >= dy {
+                error += dy;
+                cell[0] += step[0];
+            }
+            if e2 <= dx {
+                error += dx;
+                cell[1] += step[1];
+            }
+        }
+
+        Some(current)
+    })
+}
+
+/// Occupancy grid built from the scans, rendered under the points.
+pub struct OccupancyMapper {
+    pub enabled: bool,
+    resolution: f64,
+    size: f64,
+    opacity: f32,
+    /// ranges beyond are treated as free space up to this distance
+    max_range: f32,
+    grid: GridMap,
+    layer: MapLayer,
+    scans: usize,
+    source: Option<Instant>,
+}
+
+impl Default for OccupancyMapper {
+    fn default() -> Self {
+        let (resolution, size) = (0.05, 20.0);
+        Self {
+            enabled: false,
+            resolution,
+            size,
+            opacity: 1.0,
+            max_range: MAX_RANGE as f32,
+            grid: GridMap::new(resolution, size),
+            layer: MapLayer::new("occupancy"),
+            scans: 0,
+            source: None,
+        }
+    }
+}
+
+impl OccupancyMapper {
+    pub fn clear(&mut self) {
+        self.grid = GridMap::new(self.resolution, self.size);
+        self.layer.dirty = true;
+        self.scans = 0;
+        self.source = None;
+    }
+
+    /// Traces the scan into the grid unless already done,
+    /// `pose` places the robot frame in the map.
+    pub fn update(&mut self, scan: &Scan, pose: Pose2D) {
+        if !self.enabled || self.source == Some(scan.completed) {
+            return;
+        }
+        self.source = Some(scan.completed);
+
+        self.grid
+            .integrate_beams(pose, &beams(scan, self.max_range));
+
+        self.scans += 1;
+        self.layer.dirty = true;
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.enabled, "Build map");
+
+        let before = (self.resolution, self.size);
+        ui.add(
+            Slider::new(&mut self.resolution, 0.01..=0.5)
+                .text("Resolution (m)")
+                .logarithmic(true),
+        );
+        ui.add(Slider::new(&mut self.size, 2.0..=100.0).text("Size (m)"));
+        self.resolution = self.resolution.max(GridMap::min_resolution(self.size));
+        if before != (self.resolution, self.size) {
+            self.clear();
+        }
+
+        ui.add(Slider::new(&mut self.max_range, 0.5..=MAX_RANGE as f32).text("Max. range (m)"));
+        ui.add(Slider::new(&mut self.opacity, 0.0..=1.0).text("Opacity"));
+        ui.horizontal(|ui| {
+            ui.label(format!(
+                "{} × {} cells, {} scans",
+                self.grid.width, self.grid.height, self.scans
+            ));
+            if ui.button("Clear").clicked() {
+                self.clear();
+            }
+        });
+    }
+
+    /// `view` maps the map into the plot, e.g. the inverse of the robot pose.
+    pub fn show(&mut self, plot_ui: &mut PlotUi, view: Pose2D) {
+        if self.scans == 0 {
+            return;
+        }
+
+        self.layer.show(plot_ui, &self.grid, view, self.opacity);
+    }
+
+    pub fn write_svg(&self, svg: &mut Svg, view: Pose2D) {
+        if self.scans > 0 {
+            MapLayer::write_svg(svg, &self.grid, view, self.opacity);
+        }
+    }
+
+    pub fn export(&self, pgm_path: &Path) -> String {
+        match self.grid.save(pgm_path) {
+            Ok(()) => format!(
+                "Saved {} and {}",
+                pgm_path.display(),
+                pgm_path.with_extension("yaml").display()
+            ),
+            Err(err) => format!("Map export failed: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// Empty directory for the files of one test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ld19-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).expect("temporary directory");
+        dir
+    }
+
+    #[test]
+    fn bresenham_includes_both_ends() {
+        for (a, b) in [
+            ([0, 0], [5, 2]),
+            ([3, -1], [-4, 6]),
+            ([2, 7], [2, -3]),
+            ([-5, 0], [-1, 0]),
+        ] {
+            let cells: Vec<_> = bresenham(a, b).collect();
+
+            assert_eq!(cells.first(), Some(&a));
+            assert_eq!(cells.last(), Some(&b));
+            let steps = (b[0] - a[0]).abs().max((b[1] - a[1]).abs());
+            assert_eq!(cells.len() as i64, steps + 1, "{:?} to {:?}", a, b);
+            // neighbouring cells, diagonals included
+            for w in cells.windows(2) {
+                assert!((w[1][0] - w[0][0]).abs() <= 1 && (w[1][1] - w[0][1]).abs() <= 1);
+            }
+        }
+
+        assert_eq!(bresenham([4, 4], [4, 4]).collect::<Vec<_>>(), vec![[4, 4]]);
+    }
+
+    #[test]
+    fn ray_frees_the_cells_before_the_hit() {
+        let mut grid = GridMap::new(0.1, 2.0);
+        // a single pass is not certain enough
+        for _ in 0..5 {
+            grid.integrate_ray([0.05, 0.05], [0.55, 0.05], true);
+        }
+
+        let row = grid.cell([0.05, 0.05])[1];
+        let start = grid.cell([0.05, 0.05])[0];
+        for x in start..start + 5 {
+            assert!(grid.free(grid.index([x, row]).expect("inside")));
+        }
+        assert!(grid.occupied(grid.index([start + 5, row]).expect("inside")));
+    }
+
+    #[test]
+    fn map_survives_save_and_load() {
+        let mut grid = GridMap::new(0.05, 1.0);
+        grid.origin = [-0.3, 1.2];
+        for x in 0..grid.width {
+            grid.cells[x] = -LOG_ODDS_LIMIT;
+            grid.cells[grid.width * 7 + x] = LOG_ODDS_LIMIT;
+        }
+        // too uncertain to be saved as occupied or free
+        grid.cells[grid.width * 3] = 0.2;
+
+        let dir = temp_dir("round-trip");
+        let pgm_path = dir.join("map.pgm");
+        grid.save(&pgm_path).expect("saved");
+        let loaded = GridMap::load(&pgm_path.with_extension("yaml")).expect("loaded");
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert_eq!((loaded.width, loaded.height), (grid.width, grid.height));
+        assert_eq!(loaded.resolution, grid.resolution);
+        assert_eq!(loaded.origin, grid.origin);
+        for (i, (a, b)) in grid.cells.iter().zip(&loaded.cells).enumerate() {
+            let expected = if grid.occupied(i) {
+                LOG_ODDS_LIMIT
+            } else if grid.free(i) {
+                -LOG_ODDS_LIMIT
+            } else {
+                0.0
+            };
+            assert_eq!(*b, expected, "cell {} was {}", i, a);
+        }
+    }
+
+    #[test]
+    fn load_reads_comments_and_negate() {
+        let dir = temp_dir("negate");
+        // 3 × 2 with the top row first, negated: white is occupied
+        let mut pgm = b"P5\n# CREATOR: GIMP\n3 2\n# 8-bit\n255\n".to_vec();
+        pgm.extend([255, 0, 128, 0, 0, 255]);
+        std::fs::write(dir.join("map.pgm"), pgm).expect("written");
+        std::fs::write(
+            dir.join("map.yaml"),
+            "image: \"map.pgm\"\nresolution: 0.5\norigin: [1.0, -2.0, 0.3]\nnegate: 1\n",
+        )
+        .expect("written");
+
+        let loaded = GridMap::load(&dir.join("map.yaml"));
+        std::fs::remove_dir_all(&dir).ok();
+        let grid = loaded.expect("loaded");
+
+        assert_eq!((grid.width, grid.height), (3, 2));
+        assert_eq!(grid.resolution, 0.5);
+        assert_eq!(grid.origin, [1.0, -2.0]);
+        // the bottom row comes first in the grid
+        let l = LOG_ODDS_LIMIT;
+        assert_eq!(grid.cells, vec![-l, -l, l, l, -l, 0.0]);
+    }
+
+    #[test]
+    fn load_rejects_ascii_images() {
+        let dir = temp_dir("ascii");
+        std::fs::write(dir.join("map.pgm"), "P2\n1 1\n255\n0\n").expect("written");
+        std::fs::write(
+            dir.join("map.yaml"),
+            "image: map.pgm\nresolution: 0.1\norigin: [0, 0, 0]\n",
+        )
+        .expect("written");
+
+        let loaded = GridMap::load(&dir.join("map.yaml"));
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert!(loaded.is_err());
+    }
+}
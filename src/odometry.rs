@@ -0,0 +1,242 @@
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use eframe::egui::{self, Color32, Slider};
+use egui_plot::{Line, PlotPoints, PlotUi};
+
+use crate::export::Svg;
+use crate::geometry::{distance, Pose2D};
+use crate::icp::{Alignment, IcpParams, Reference};
+use crate::scan::Scan;
+
+const TRAJECTORY_COLOR: Color32 = Color32::from_rgb(0, 200, 255);
+
+/// The trajectory keeps poses at least this far apart.
+const TRAJECTORY_STEP: f64 = 0.05;
+
+/// Poses of the trajectory beyond this are dropped, oldest first.
+const MAX_TRAJECTORY_POINTS: usize = 10_000;
+
+/// Estimates the motion of the robot by aligning each scan with a keyframe.
+pub struct Odometry {
+    pub enabled: bool,
+    params: IcpParams,
+    /// a new keyframe is taken after moving this far
+    keyframe_distance: f64,
+    keyframe_angle_deg: f64,
+    show_trajectory: bool,
+    /// robot pose in the odometry frame
+    pose: Pose2D,
+    keyframe: Option<(Pose2D, Reference)>,
+    /// motion during the last scan, to predict the next one
+    velocity: Pose2D,
+    trajectory: VecDeque<[f64; 2]>,
+    travelled: f64,
+    last: Option<Alignment>,
+    failures: usize,
+    source: Option<Instant>,
+}
+
+impl Default for Odometry {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            params: Default::default(),
+            keyframe_distance: 0.2,
+            keyframe_angle_deg: 10.0,
+            show_trajectory: true,
+            pose: Default::default(),
+            keyframe: None,
+            velocity: Default::default(),
+            trajectory: VecDeque::new(),
+            travelled: 0.0,
+            last: None,
+            failures: 0,
+            source: None,
+        }
+    }
+}
+
+impl Odometry {
+    /// Robot pose in the odometry frame.
+    pub fn pose(&self) -> Pose2D {
+        self.pose
+    }
+
+    pub fn reset(&mut self) {
+        self.pose = Default::default();
+        self.keyframe = None;
+        self.velocity = Default::default();
+        self.trajectory.clear();
+        self.travelled = 0.0;
+        self.last = None;
+        self.failures = 0;
+        self.source = None;
+    }
+
+    /// Aligns the scan unless already done.
+    pub fn update(&mut self, scan: &Scan) {
+        if !self.enabled || self.source == Some(scan.completed) {
+            return;
+        }
+        self.source = Some(scan.completed);
+
+        let runs = scan.sensor_positions();
+        let points = runs.concat();
+        let cell_size = self.params.max_correspondence;
+        let new_reference = || Reference::from_runs(runs.iter().map(Vec::as_slice), cell_size);
+        let predicted = self.pose.compose(&self.velocity);
+
+        let Some((keyframe_pose, reference)) = &self.keyframe else {
+            self.keyframe = Some((self.pose, new_reference()));
+            self.extend_trajectory();
+            return;
+        };
+        let keyframe_pose = *keyframe_pose;
+
+        // pose relative to the keyframe
+        let guess = keyframe_pose.inverse().compose(&predicted);
+        let alignment = reference.align(&points, guess, &self.params);
+        let pose = match alignment {
+            Some(alignment) => keyframe_pose.compose(&alignment.pose),
+            None => {
+                self.failures += 1;
+                predicted
+            }
+        };
+        self.last = alignment;
+
+        let step = self.pose.inverse().compose(&pose);
+        self.velocity = step;
+        self.travelled += step.x.hypot(step.y);
+        self.pose = pose;
+        self.extend_trajectory();
+
+        let relative = keyframe_pose.inverse().compose(&pose);
+        if alignment.is_none()
+            || relative.x.hypot(relative.y) >= self.keyframe_distance
+            || relative.theta.to_degrees().abs() >= self.keyframe_angle_deg
+        {
+            self.keyframe = Some((pose, new_reference()));
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        if ui.checkbox(&mut self.enabled, "Estimate motion").changed() {
+            self.reset();
+        }
+        ui.add(
+            Slider::new(&mut self.params.max_correspondence, 0.05..=1.0)
+                .text("Max. match distance (m)"),
+        );
+        ui.add(Slider::new(&mut self.params.max_iterations, 1..=100).text("Max. iterations"));
+        ui.add(Slider::new(&mut self.params.min_matches, 3..=200).text("Min. matches"));
+        ui.add(Slider::new(&mut self.keyframe_distance, 0.0..=2.0).text("Keyframe every (m)"));
+        ui.add(Slider::new(&mut self.keyframe_angle_deg, 0.0..=90.0).text("or (°)"));
+        ui.checkbox(&mut self.show_trajectory, "Trajectory");
+
+        egui::Grid::new("odometry")
+            .num_columns(2)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("Position");
+                ui.label(format!("({:.3}, {:.3})m", self.pose.x, self.pose.y));
+                ui.end_row();
+                ui.label("Heading");
+                // clockwise like the θ of the coordinates
+                ui.label(format!("{:.1}°", -self.pose.theta.to_degrees()));
+                ui.end_row();
+                ui.label("Travelled");
+                ui.label(format!("{:.2}m", self.travelled));
+                ui.end_row();
+                ui.label("Last match");
+                ui.label(match self.last {
+                    Some(a) => format!(
+                        "{} points, {:.1}mm rms, {} iterations",
+                        a.matches,
+                        a.rms * 1e3,
+                        a.iterations
+                    ),
+                    None => "-".to_owned(),
+                });
+                ui.end_row();
+                ui.label("Failures");
+                ui.label(format!("{}", self.failures));
+            });
+
+        if ui.button("Reset").clicked() {
+            self.reset();
+        }
+    }
+
+    /// Adds the pose to the trajectory once it moved away from the last one.
+    fn extend_trajectory(&mut self) {
+        let position = [self.pose.x, self.pose.y];
+        if self
+            .trajectory
+            .back()
+            .is_some_and(|last| distance(*last, position) < TRAJECTORY_STEP)
+        {
+            return;
+        }
+
+        self.trajectory.push_back(position);
+        if self.trajectory.len() > MAX_TRAJECTORY_POINTS {
+            self.trajectory.pop_front();
+        }
+    }
+
+    /// The trajectory up to the robot as seen from the current pose, to overlay the live scan.
+    fn relative_trajectory(&self) -> Vec<[f64; 2]> {
+        let view = self.pose.inverse();
+        let mut trajectory: Vec<_> = self.trajectory.iter().map(|p| view.transform(*p)).collect();
+        trajectory.push([0.0, 0.0]);
+        trajectory
+    }
+
+    pub fn show(&self, plot_ui: &mut PlotUi) {
+        if !self.enabled || !self.show_trajectory || self.trajectory.is_empty() {
+            return;
+        }
+
+        plot_ui.line(
+            Line::new(PlotPoints::new(self.relative_trajectory()))
+                .color(TRAJECTORY_COLOR)
+                .width(2.0)
+                .name("Trajectory"),
+        );
+    }
+
+    pub fn write_svg(&self, svg: &mut Svg) {
+        if !self.enabled || !self.show_trajectory || self.trajectory.is_empty() {
+            return;
+        }
+
+        svg.polyline(&self.relative_trajectory(), 2.0, TRAJECTORY_COLOR, false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trajectory_is_decimated_and_capped() {
+        let mut odometry = Odometry::default();
+        // standing still adds a single pose
+        for _ in 0..10 {
+            odometry.extend_trajectory();
+        }
+        assert_eq!(odometry.trajectory.len(), 1);
+
+        for i in 1..=MAX_TRAJECTORY_POINTS * 2 {
+            odometry.pose = Pose2D::new(i as f64 * TRAJECTORY_STEP * 0.6, 0.0, 0.0);
+            odometry.extend_trajectory();
+        }
+        assert_eq!(odometry.trajectory.len(), MAX_TRAJECTORY_POINTS);
+        let [first, second] = [odometry.trajectory[0], odometry.trajectory[1]];
+        assert!(distance(first, second) >= TRAJECTORY_STEP);
+        // the line still reaches the robot
+        assert_eq!(odometry.relative_trajectory().last(), Some(&[0.0, 0.0]));
+    }
+}
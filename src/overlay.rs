@@ -0,0 +1,280 @@
+use eframe::egui::{self, Align2, Color32, Slider};
+use egui_plot::{Line, LineStyle, PlotPoint, PlotPoints, PlotUi, Polygon, Text};
+
+use crate::export::Svg;
+use crate::mounting::Mounting;
+
+/// Maximum range of the LD19 in meters.
+pub const MAX_RANGE: f64 = 12.0;
+
+const GRID_COLOR: Color32 = Color32::from_rgba_premultiplied(64, 64, 64, 128);
+const FOV_FILL: Color32 = Color32::from_rgba_premultiplied(14, 17, 18, 20);
+const FOV_STROKE: Color32 = Color32::from_rgba_premultiplied(52, 65, 69, 77);
+
+/// Bearing of a point in degrees, using the sensor's convention:
+/// 0° is forward (+y) and positive angles turn towards +x.
+pub fn bearing_deg(x: f64, y: f64) -> f64 {
+    x.atan2(y).to_degrees()
+}
+
+/// Inverse of [`bearing_deg`], returns the unit vector pointing at `deg`.
+pub fn bearing_dir(deg: f64) -> [f64; 2] {
+    let rad = deg.to_radians();
+    [rad.sin(), rad.cos()]
+}
+
+pub struct PolarGrid {
+    pub show_rings: bool,
+    pub ring_spacing: f64,
+    pub show_spokes: bool,
+    pub spoke_spacing_deg: f64,
+    pub show_fov: bool,
+    pub fov_deg: f64,
+}
+
+impl Default for PolarGrid {
+    fn default() -> Self {
+        Self {
+            show_rings: false,
+            ring_spacing: 1.0,
+            show_spokes: false,
+            spoke_spacing_deg: 30.0,
+            show_fov: false,
+            fov_deg: 360.0, // the LD19 covers the full circle
+        }
+    }
+}
+
+impl PolarGrid {
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.show_rings, "Range rings");
+        if self.show_rings {
+            ui.add(
+                Slider::new(&mut self.ring_spacing, 0.1..=5.0)
+                    .text("Ring spacing (m)")
+                    .step_by(0.1),
+            );
+        }
+
+        ui.checkbox(&mut self.show_spokes, "Angle spokes");
+        if self.show_spokes {
+            ui.add(
+                Slider::new(&mut self.spoke_spacing_deg, 5.0..=90.0)
+                    .text("Spoke spacing (°)")
+                    .step_by(5.0),
+            );
+        }
+
+        ui.checkbox(&mut self.show_fov, "Field of view");
+        if self.show_fov {
+            ui.add(
+                Slider::new(&mut self.fov_deg, 10.0..=360.0)
+                    .text("FOV (°)")
+                    .step_by(1.0),
+            );
+        }
+    }
+
+    /// Draws the grid around the sensor with the given mounting.
+    pub fn show(&self, plot_ui: &mut PlotUi, mounting: &Mounting) {
+        let bounds = plot_ui.plot_bounds();
+        let extent = grid_extent(bounds.min(), bounds.max(), mounting);
+        let place = |points: Vec<[f64; 2]>| -> Vec<[f64; 2]> {
+            points.into_iter().map(|p| mounting.transform(p)).collect()
+        };
+
+        if self.show_fov {
+            for wedge in self.fov_wedges() {
+                plot_ui.polygon(
+                    Polygon::new(PlotPoints::new(place(wedge)))
+                        .fill_color(FOV_FILL)
+                        .stroke((0.0, Color32::TRANSPARENT))
+                        .allow_hover(false),
+                );
+            }
+            plot_ui.line(
+                Line::new(PlotPoints::new(place(self.fov_outline())))
+                    .color(FOV_STROKE)
+                    .allow_hover(false),
+            );
+        }
+
+        for r in self.rings(extent) {
+            plot_ui.line(
+                Line::new(PlotPoints::new(place(arc(r, -180.0, 180.0))))
+                    .color(GRID_COLOR)
+                    .style(LineStyle::dashed_dense())
+                    .allow_hover(false),
+            );
+            let [x, y] = mounting.transform([0.0, r]);
+            plot_ui.text(
+                Text::new(PlotPoint::new(x, y), format!("{:.1}m", r))
+                    .color(GRID_COLOR)
+                    .anchor(Align2::LEFT_BOTTOM)
+                    .allow_hover(false),
+            );
+        }
+
+        for deg in self.spokes() {
+            let [dx, dy] = bearing_dir(deg);
+            plot_ui.line(
+                Line::new(PlotPoints::new(place(vec![
+                    [0.0, 0.0],
+                    [dx * extent, dy * extent],
+                ])))
+                .color(GRID_COLOR)
+                .allow_hover(false),
+            );
+
+            let r = self.spoke_label_radius();
+            let [x, y] = mounting.transform([dx * r, dy * r]);
+            plot_ui.text(
+                Text::new(PlotPoint::new(x, y), format!("θ={:.0}°", deg))
+                    .color(GRID_COLOR)
+                    .allow_hover(false),
+            );
+        }
+    }
+
+    pub fn write_svg(&self, svg: &mut Svg, min: [f64; 2], max: [f64; 2], mounting: &Mounting) {
+        let extent = grid_extent(min, max, mounting);
+        let place = |points: Vec<[f64; 2]>| -> Vec<[f64; 2]> {
+            points.into_iter().map(|p| mounting.transform(p)).collect()
+        };
+
+        if self.show_fov {
+            for wedge in self.fov_wedges() {
+                svg.polygon(&place(wedge), FOV_FILL, Color32::TRANSPARENT);
+            }
+            svg.polyline(&place(self.fov_outline()), 1.0, FOV_STROKE, false);
+        }
+
+        for r in self.rings(extent) {
+            svg.polyline(&place(arc(r, -180.0, 180.0)), 1.0, GRID_COLOR, true);
+            svg.text(
+                mounting.transform([0.0, r]),
+                &format!("{:.1}m", r),
+                GRID_COLOR,
+            );
+        }
+
+        for deg in self.spokes() {
+            let [dx, dy] = bearing_dir(deg);
+            svg.polyline(
+                &place(vec![[0.0, 0.0], [dx * extent, dy * extent]]),
+                1.0,
+                GRID_COLOR,
+                false,
+            );
+
+            let r = self.spoke_label_radius();
+            svg.text(
+                mounting.transform([dx * r, dy * r]),
+                &format!("θ={:.0}°", deg),
+                GRID_COLOR,
+            );
+        }
+    }
+
+    fn fov_wedges(&self) -> Vec<Vec<[f64; 2]>> {
+        let half = self.fov_deg * 0.5;
+        sector_wedges(MAX_RANGE, -half, half)
+    }
+
+    fn fov_outline(&self) -> Vec<[f64; 2]> {
+        let half = self.fov_deg * 0.5;
+        let mut outline = arc(MAX_RANGE, -half, half);
+        if self.fov_deg < 360.0 {
+            outline.insert(0, [0.0, 0.0]);
+            outline.push([0.0, 0.0]);
+        }
+
+        outline
+    }
+
+    fn rings(&self, extent: f64) -> Vec<f64> {
+        if !self.show_rings || self.ring_spacing <= 0.0 {
+            return vec![];
+        }
+
+        let count = ((extent / self.ring_spacing).ceil() as usize).min(500);
+        (1..=count).map(|i| i as f64 * self.ring_spacing).collect()
+    }
+
+    /// Spoke bearings using the same convention as the coordinates formatter, i.e. -180°..180°
+    fn spokes(&self) -> Vec<f64> {
+        if !self.show_spokes || self.spoke_spacing_deg <= 0.0 {
+            return vec![];
+        }
+
+        let count = (360.0 / self.spoke_spacing_deg).floor() as usize;
+        (0..count)
+            .map(|i| {
+                let deg = i as f64 * self.spoke_spacing_deg;
+                if deg > 180.0 {
+                    deg - 360.0
+                } else {
+                    deg
+                }
+            })
+            .collect()
+    }
+
+    fn spoke_label_radius(&self) -> f64 {
+        if self.show_rings {
+            self.ring_spacing
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Extent of the grid around the sensor, large enough to cover the visible area.
+fn grid_extent(min: [f64; 2], max: [f64; 2], mounting: &Mounting) -> f64 {
+    let [x, y] = mounting.transform([0.0, 0.0]);
+    [[min[0] - x, min[1] - y], [max[0] - x, max[1] - y]]
+        .iter()
+        .flat_map(|a| [a[0].abs(), a[1].abs()])
+        .fold(0.0f64, f64::max)
+        * std::f64::consts::SQRT_2
+}
+
+/// Clockwise span of the sector from `from_deg` to `to_deg` in degrees,
+/// a sector ending where it starts is empty.
+pub fn sector_span(from_deg: f64, to_deg: f64) -> f64 {
+    if to_deg >= from_deg {
+        to_deg - from_deg
+    } else {
+        to_deg - from_deg + 360.0
+    }
+}
+
+/// A circular sector around the origin from `from_deg` clockwise to `to_deg`,
+/// split into wedges as egui only fills convex polygons.
+pub fn sector_wedges(radius: f64, from_deg: f64, to_deg: f64) -> Vec<Vec<[f64; 2]>> {
+    let span = sector_span(from_deg, to_deg);
+    let wedges = (span / 90.0).ceil() as usize;
+    let step = span / wedges as f64;
+
+    (0..wedges)
+        .map(|i| {
+            let from = from_deg + i as f64 * step;
+            let mut wedge = vec![[0.0, 0.0]];
+            wedge.extend(arc(radius, from, from + step));
+            wedge
+        })
+        .collect()
+}
+
+/// Points along a circular arc around the origin from `from_deg` to `to_deg`.
+pub fn arc(radius: f64, from_deg: f64, to_deg: f64) -> Vec<[f64; 2]> {
+    let steps = (((to_deg - from_deg).abs() / 2.0).ceil() as usize).max(1);
+
+    (0..=steps)
+        .map(|i| {
+            let deg = from_deg + (to_deg - from_deg) * i as f64 / steps as f64;
+            let [dx, dy] = bearing_dir(deg);
+            [dx * radius, dy * radius]
+        })
+        .collect()
+}
@@ -0,0 +1,344 @@
+use std::time::Instant;
+
+use eframe::egui::{self, Align2, Color32, Slider};
+use egui_plot::{Line, MarkerShape, PlotPoint, PlotPoints, PlotUi, Points, Text};
+
+use crate::clustering::Cluster;
+use crate::export::Svg;
+use crate::geometry::{distance, fit_line, normalize_deg};
+use crate::overlay::{arc, bearing_deg};
+
+const LEG_COLOR: Color32 = Color32::from_rgb(255, 170, 60);
+const PERSON_COLOR: Color32 = Color32::from_rgb(255, 110, 40);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Person {
+    pub position: [f64; 2],
+    /// both legs were found
+    pub paired: bool,
+}
+
+/// Finds legs, small arcs facing the sensor, and pairs them to people.
+pub struct PeopleDetector {
+    pub enabled: bool,
+    min_leg_width: f64,
+    max_leg_width: f64,
+    min_points: usize,
+    /// minimum mean bulge towards the sensor relative to the width of the leg
+    min_bulge: f64,
+    /// maximum distance between the centres of both legs
+    max_leg_spacing: f64,
+    /// a lone leg is counted as person, e.g. while the other is occluded
+    count_single_legs: bool,
+    legs: Vec<[f64; 2]>,
+    people: Vec<Person>,
+    /// the scan the detections belong to
+    source: Option<Instant>,
+}
+
+impl Default for PeopleDetector {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_leg_width: 0.05,
+            max_leg_width: 0.25,
+            min_points: 3,
+            min_bulge: 0.1,
+            max_leg_spacing: 0.45,
+            count_single_legs: false,
+            legs: vec![],
+            people: vec![],
+            source: None,
+        }
+    }
+}
+
+impl PeopleDetector {
+    pub fn reset(&mut self) {
+        self.legs.clear();
+        self.people.clear();
+        self.source = None;
+    }
+
+    pub fn count(&self) -> usize {
+        self.people.len()
+    }
+
+    fn is_leg(&self, cluster: &Cluster, sensor: [f64; 2]) -> bool {
+        if cluster.points.len() < self.min_points {
+            return false;
+        }
+
+        // the ends of the arc by bearing, a cluster may cross the 0°/360° seam
+        let towards = |p: [f64; 2]| bearing_deg(p[0] - sensor[0], p[1] - sensor[1]);
+        let ahead = towards(cluster.centroid);
+        let bearing = |p: &&[f64; 2]| normalize_deg(towards(**p) - ahead);
+        let (Some(first), Some(last)) = (
+            cluster
+                .points
+                .iter()
+                .min_by(|a, b| bearing(a).total_cmp(&bearing(b))),
+            cluster
+                .points
+                .iter()
+                .max_by(|a, b| bearing(a).total_cmp(&bearing(b))),
+        ) else {
+            return false;
+        };
+        let width = distance(*first, *last);
+        if !(self.min_leg_width..=self.max_leg_width).contains(&width) {
+            return false;
+        }
+
+        // a leg bulges towards the sensor, a corner or wall edge is flat or concave
+        let Some(chord) = fit_line(&[*first, *last]) else {
+            return false;
+        };
+        let towards_sensor = chord.distance_to(sensor).signum();
+        let bulge = cluster
+            .points
+            .iter()
+            .map(|p| chord.distance_to(*p) * towards_sensor)
+            .sum::<f64>()
+            / cluster.points.len() as f64;
+
+        bulge > self.min_bulge * width
+    }
+
+    /// Detects people in the clusters of the scan completed at `time`,
+    /// `sensor` is the position of the sensor in the robot frame.
+    pub fn update(&mut self, clusters: &[Cluster], sensor: [f64; 2], time: Instant) {
+        if !self.enabled || self.source == Some(time) {
+            return;
+        }
+        self.source = Some(time);
+
+        self.legs = clusters
+            .iter()
+            .filter(|c| self.is_leg(c, sensor))
+            .map(|c| c.centroid)
+            .collect();
+
+        // closest pairs first
+        let mut pairs = vec![];
+        for (i, a) in self.legs.iter().enumerate() {
+            for (j, b) in self.legs.iter().enumerate().skip(i + 1) {
+                let d = distance(*a, *b);
+                if d <= self.max_leg_spacing {
+                    pairs.push((d, i, j));
+                }
+            }
+        }
+        pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut used = vec![false; self.legs.len()];
+        self.people.clear();
+        for (_, i, j) in pairs {
+            if used[i] || used[j] {
+                continue;
+            }
+            used[i] = true;
+            used[j] = true;
+
+            let (a, b) = (self.legs[i], self.legs[j]);
+            self.people.push(Person {
+                position: [(a[0] + b[0]) * 0.5, (a[1] + b[1]) * 0.5],
+                paired: true,
+            });
+        }
+
+        if self.count_single_legs {
+            let single = self.legs.iter().zip(used).filter(|(_, u)| !u);
+            self.people.extend(single.map(|(leg, _)| Person {
+                position: *leg,
+                paired: false,
+            }));
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        let before = (
+            self.enabled,
+            self.min_leg_width,
+            self.max_leg_width,
+            self.min_points,
+            self.min_bulge,
+            self.max_leg_spacing,
+            self.count_single_legs,
+        );
+
+        ui.checkbox(&mut self.enabled, "Detect people");
+        ui.add(
+            Slider::new(&mut self.min_leg_width, 0.01..=0.3)
+                .text("Min. leg width (m)")
+                .step_by(0.01),
+        );
+        ui.add(
+            Slider::new(&mut self.max_leg_width, 0.05..=0.5)
+                .text("Max. leg width (m)")
+                .step_by(0.01),
+        );
+        ui.add(Slider::new(&mut self.min_points, 2..=20).text("Min. points"));
+        ui.add(
+            Slider::new(&mut self.min_bulge, 0.0..=0.4)
+                .text("Min. bulge (× width)")
+                .step_by(0.01),
+        )
+        .on_hover_text("Flatter clusters, e.g. pieces of walls, are not legs");
+        ui.add(
+            Slider::new(&mut self.max_leg_spacing, 0.1..=1.0)
+                .text("Max. leg spacing (m)")
+                .step_by(0.01),
+        );
+        ui.checkbox(&mut self.count_single_legs, "Count single legs");
+
+        // detect again with the new parameters
+        if before
+            != (
+                self.enabled,
+                self.min_leg_width,
+                self.max_leg_width,
+                self.min_points,
+                self.min_bulge,
+                self.max_leg_spacing,
+                self.count_single_legs,
+            )
+        {
+            self.source = None;
+        }
+
+        if self.enabled {
+            ui.label(format!(
+                "{} people, {} legs",
+                self.people.len(),
+                self.legs.len()
+            ));
+        }
+    }
+
+    pub fn show(&self, plot_ui: &mut PlotUi) {
+        if !self.enabled {
+            return;
+        }
+
+        plot_ui.points(
+            Points::new(self.legs.clone())
+                .radius(4.0)
+                .shape(MarkerShape::Diamond)
+                .color(LEG_COLOR)
+                .name("Legs"),
+        );
+
+        for (i, person) in self.people.iter().enumerate() {
+            let [x, y] = person.position;
+            let outline: Vec<_> = arc(0.3, -180.0, 180.0)
+                .into_iter()
+                .map(|[dx, dy]| [x + dx, y + dy])
+                .collect();
+            let color = if person.paired {
+                PERSON_COLOR
+            } else {
+                PERSON_COLOR.gamma_multiply(0.5)
+            };
+
+            plot_ui.line(
+                Line::new(PlotPoints::new(outline))
+                    .color(color)
+                    .width(2.0)
+                    .name(format!("Person {}", i + 1)),
+            );
+            plot_ui.text(
+                Text::new(PlotPoint::new(x, y + 0.3), format!("Person {}", i + 1))
+                    .color(color)
+                    .anchor(Align2::CENTER_BOTTOM),
+            );
+        }
+    }
+
+    pub fn write_svg(&self, svg: &mut Svg) {
+        if !self.enabled {
+            return;
+        }
+
+        for leg in &self.legs {
+            svg.circle(*leg, 4.0, LEG_COLOR);
+        }
+        for (i, person) in self.people.iter().enumerate() {
+            let [x, y] = person.position;
+            let outline: Vec<_> = arc(0.3, -180.0, 180.0)
+                .into_iter()
+                .map(|[dx, dy]| [x + dx, y + dy])
+                .collect();
+            svg.polyline(&outline, 2.0, PERSON_COLOR, !person.paired);
+            svg.text([x, y + 0.3], &format!("Person {}", i + 1), PERSON_COLOR);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::OrientedBox;
+
+    fn cluster(points: Vec<[f64; 2]>) -> Cluster {
+        let n = points.len() as f64;
+        Cluster {
+            centroid: [
+                points.iter().map(|p| p[0]).sum::<f64>() / n,
+                points.iter().map(|p| p[1]).sum::<f64>() / n,
+            ],
+            bbox: OrientedBox::from_points(&points).expect("points"),
+            points,
+        }
+    }
+
+    /// The half of a 12cm leg facing the sensor at the origin, centered at `center`.
+    fn leg(center: [f64; 2]) -> Vec<[f64; 2]> {
+        let facing = (-center[0]).atan2(-center[1]);
+        (0..=8)
+            .map(|i| {
+                let angle = facing + (i as f64 / 8.0 - 0.5) * std::f64::consts::PI;
+                [
+                    center[0] + 0.06 * angle.sin(),
+                    center[1] + 0.06 * angle.cos(),
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn arc_is_a_leg_but_flat_segment_is_not() {
+        let detector = PeopleDetector::default();
+        let arc = leg([0.0, 1.5]);
+        // a piece of wall as wide as the leg
+        let flat: Vec<_> = (0..=8).map(|i| [-0.06 + i as f64 * 0.015, 1.5]).collect();
+        assert!((distance(flat[0], flat[8]) - distance(arc[0], arc[8])).abs() < 1e-9);
+
+        assert!(detector.is_leg(&cluster(arc), [0.0, 0.0]));
+        assert!(!detector.is_leg(&cluster(flat), [0.0, 0.0]));
+    }
+
+    #[test]
+    fn concave_segment_is_not_a_leg() {
+        let detector = PeopleDetector::default();
+        // the inside of a corner
+        let corner: Vec<_> = (0..=8)
+            .map(|i| {
+                let x = -0.06 + i as f64 * 0.015;
+                [x, 1.56 - x.abs()]
+            })
+            .collect();
+
+        assert!(!detector.is_leg(&cluster(corner), [0.0, 0.0]));
+    }
+
+    #[test]
+    fn leg_across_the_seam_keeps_its_width() {
+        let detector = PeopleDetector::default();
+        // straight ahead, the points of the right half come first in the scan
+        let mut points = leg([0.0, 1.5]);
+        points.rotate_left(5);
+
+        assert!(detector.is_leg(&cluster(points), [0.0, 0.0]));
+    }
+}
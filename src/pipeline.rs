@@ -0,0 +1,170 @@
+use eframe::egui::{self, ComboBox};
+use egui_plot::{PlotPoint, PlotUi};
+
+use crate::export::Svg;
+use crate::filters;
+use crate::mounting::Mounting;
+use crate::scan::Scan;
+
+/// A processing stage applied to every assembled scan before it is displayed.
+pub trait ScanFilter {
+    fn name(&self) -> &'static str;
+
+    /// Processes the scan in place, e.g. by removing or adjusting points.
+    fn apply(&mut self, scan: &mut Scan);
+
+    /// Parameters of the filter.
+    fn ui(&mut self, ui: &mut egui::Ui);
+
+    /// Draws on and interacts with the plot, e.g. to edit regions,
+    /// `mounting` places the sensor frame in the plot. A filter that handles
+    /// the plot `click` takes it, so no other tool reacts to it.
+    fn show(
+        &mut self,
+        _plot_ui: &mut PlotUi,
+        _mounting: &Mounting,
+        _click: &mut Option<PlotPoint>,
+    ) {
+    }
+
+    /// Draws what `show` draws into the SVG export.
+    fn write_svg(&self, _svg: &mut Svg, _mounting: &Mounting) {}
+
+    /// Clears any state accumulated over past scans.
+    fn reset(&mut self) {}
+}
+
+struct Stage {
+    enabled: bool,
+    filter: Box<dyn ScanFilter>,
+    /// number of points removed from the last scan
+    removed: usize,
+}
+
+pub struct Pipeline {
+    stages: Vec<Stage>,
+    selected_filter: usize,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        let mut pipeline = Self {
+            stages: vec![],
+            selected_filter: 0,
+        };
+        pipeline.push(Box::<filters::IntensityFilter>::default());
+
+        pipeline
+    }
+}
+
+impl Pipeline {
+    pub fn push(&mut self, filter: Box<dyn ScanFilter>) {
+        self.stages.push(Stage {
+            enabled: true,
+            filter,
+            removed: 0,
+        });
+    }
+
+    pub fn process(&mut self, scan: &mut Scan) {
+        for stage in self.stages.iter_mut() {
+            if stage.enabled {
+                let before = scan.points.len();
+                stage.filter.apply(scan);
+                stage.removed = before.saturating_sub(scan.points.len());
+            } else {
+                stage.removed = 0;
+            }
+        }
+    }
+
+    /// Points removed from the last scan by each enabled stage.
+    pub fn removed_points(&self) -> impl Iterator<Item = (&'static str, usize)> + '_ {
+        self.stages
+            .iter()
+            .filter(|s| s.enabled)
+            .map(|s| (s.filter.name(), s.removed))
+    }
+
+    pub fn show(
+        &mut self,
+        plot_ui: &mut PlotUi,
+        mounting: &Mounting,
+        click: &mut Option<PlotPoint>,
+    ) {
+        for stage in self.stages.iter_mut().filter(|s| s.enabled) {
+            stage.filter.show(plot_ui, mounting, click);
+        }
+    }
+
+    pub fn write_svg(&self, svg: &mut Svg, mounting: &Mounting) {
+        for stage in self.stages.iter().filter(|s| s.enabled) {
+            stage.filter.write_svg(svg, mounting);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for stage in &mut self.stages {
+            stage.filter.reset();
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        let mut remove = None;
+        let mut swap = None;
+        let count = self.stages.len();
+
+        for (i, stage) in self.stages.iter_mut().enumerate() {
+            ui.push_id(i, |ui| {
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut stage.enabled, "");
+                    egui::CollapsingHeader::new(format!("{}. {}", i + 1, stage.filter.name()))
+                        .show(ui, |ui| {
+                            ui.add_enabled_ui(stage.enabled, |ui| stage.filter.ui(ui));
+                        });
+                });
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(i > 0, egui::Button::new("⏶").small())
+                        .on_hover_text("Move up")
+                        .clicked()
+                    {
+                        swap = Some((i - 1, i));
+                    }
+                    if ui
+                        .add_enabled(i + 1 < count, egui::Button::new("⏷").small())
+                        .on_hover_text("Move down")
+                        .clicked()
+                    {
+                        swap = Some((i, i + 1));
+                    }
+                    if ui.small_button("🗑").on_hover_text("Remove").clicked() {
+                        remove = Some(i);
+                    }
+                });
+            });
+        }
+
+        if let Some((a, b)) = swap {
+            self.stages.swap(a, b);
+        }
+        if let Some(i) = remove {
+            self.stages.remove(i);
+        }
+
+        let available = filters::available();
+        ui.horizontal(|ui| {
+            ComboBox::from_id_source("add_filter")
+                .selected_text(available[self.selected_filter].0)
+                .show_ui(ui, |ui| {
+                    for (i, (name, _)) in available.iter().enumerate() {
+                        ui.selectable_value(&mut self.selected_filter, i, *name);
+                    }
+                });
+            if ui.button("Add").clicked() {
+                self.push((available[self.selected_filter].1)());
+            }
+        });
+    }
+}
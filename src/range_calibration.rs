@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use eframe::egui::{self, DragValue, Slider};
+
+use crate::geometry::{normalize_deg, solve_linear};
+use crate::scan::Scan;
+
+const CALIBRATIONS_FILE: &str = "range_calibrations.txt";
+
+/// Number of scans gathered per capture.
+const CAPTURE_SCANS: usize = 20;
+
+/// Correction added to the measured range, a polynomial in the raw range `d` (meters)
+/// and the normalized intensity `i`: `c0 + c1·d + c2·d² + c3·i + c4·i·d`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RangeCorrection {
+    coeffs: [f64; 5],
+    /// the correction is applied to the scans of the device
+    enabled: bool,
+}
+
+impl Default for RangeCorrection {
+    fn default() -> Self {
+        Self {
+            coeffs: [0.0; 5],
+            enabled: true,
+        }
+    }
+}
+
+impl RangeCorrection {
+    pub fn correct(&self, distance: f32, intensity: f32) -> f32 {
+        let features = features(distance as f64, intensity as f64);
+        let offset: f64 = self.coeffs.iter().zip(features).map(|(c, f)| c * f).sum();
+
+        (distance as f64 + offset).max(0.0) as f32
+    }
+
+    fn to_line(self, key: &str) -> String {
+        let coeffs: Vec<_> = self.coeffs.iter().map(|c| c.to_string()).collect();
+        format!("{} {} {}", key, coeffs.join(" "), self.enabled)
+    }
+
+    fn from_line(line: &str) -> Option<(String, Self)> {
+        let mut parts = line.split_whitespace();
+        let key = parts.next()?.to_owned();
+        let mut coeffs = [0.0; 5];
+        for c in &mut coeffs {
+            *c = parts.next()?.parse().ok()?;
+        }
+        // files written before the flag existed apply all corrections
+        let enabled = parts.next().map_or(Some(true), |e| e.parse().ok())?;
+
+        Some((key, Self { coeffs, enabled }))
+    }
+}
+
+fn features(d: f64, i: f64) -> [f64; 5] {
+    [1.0, d, d * d, i, i * d]
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    raw: f64,
+    intensity: f64,
+    truth: f64,
+    /// the known distance of the target during the capture
+    known: f64,
+}
+
+/// Captures scans of a target at known distances and fits a per-device range correction.
+pub struct RangeCalibration {
+    path: PathBuf,
+    corrections: HashMap<String, RangeCorrection>,
+    /// the device being calibrated
+    device: String,
+    known_distance: f64,
+    /// the target is expected within this sector around the bearing
+    target_bearing: f64,
+    target_width: f64,
+    samples: Vec<Sample>,
+    capture_left: usize,
+    fitted: Option<(RangeCorrection, f64)>,
+    status: Option<String>,
+}
+
+impl RangeCalibration {
+    pub fn load() -> Self {
+        let path = PathBuf::from(CALIBRATIONS_FILE);
+        let corrections = std::fs::read_to_string(&path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(RangeCorrection::from_line)
+            .collect();
+
+        Self {
+            path,
+            corrections,
+            device: String::new(),
+            known_distance: 1.0,
+            target_bearing: 0.0,
+            target_width: 5.0,
+            samples: vec![],
+            capture_left: 0,
+            fitted: None,
+            status: None,
+        }
+    }
+
+    /// Selects the device to calibrate.
+    pub fn select_device(&mut self, device: &str) {
+        self.device = device.to_owned();
+        self.samples.clear();
+        self.fitted = None;
+        self.capture_left = 0;
+    }
+
+    /// Corrects the ranges of a scan of `device`, also gathers samples
+    /// while capturing with the selected device.
+    pub fn process(&mut self, scan: &mut Scan, device: &str) {
+        if self.capture_left > 0 && device == self.device {
+            self.capture(scan);
+        }
+
+        if let Some(correction) = self.corrections.get(device).filter(|c| c.enabled) {
+            for p in &mut scan.points {
+                p.distance = correction.correct(p.distance, p.point.normalized_intensity());
+            }
+        }
+    }
+
+    fn capture(&mut self, scan: &Scan) {
+        let half = self.target_width * 0.5;
+        let samples = scan.points.iter().filter_map(|p| {
+            let offset = normalize_deg(p.angle as f64 - self.target_bearing);
+            (p.distance > 0.0 && offset.abs() <= half).then(|| Sample {
+                raw: p.distance as f64,
+                intensity: p.point.normalized_intensity() as f64,
+                // flat target facing the sensor
+                truth: self.known_distance / offset.to_radians().cos(),
+                known: self.known_distance,
+            })
+        });
+        self.samples.extend(samples);
+
+        self.capture_left -= 1;
+        if self.capture_left == 0 {
+            self.status = Some(format!("{} samples captured", self.samples.len()));
+        }
+    }
+
+    fn fit(&mut self) {
+        // fewer distinct distances only support fewer terms
+        let mut distances: Vec<_> = self.samples.iter().map(|s| s.known).collect();
+        distances.sort_by(f64::total_cmp);
+        distances.dedup_by(|a, b| (*a - *b).abs() < 1e-3);
+        let terms: &[usize] = match distances.len() {
+            0 => {
+                self.status = Some("No samples captured".to_owned());
+                return;
+            }
+            1 => &[0, 3],
+            2 => &[0, 1, 3],
+            _ => &[0, 1, 2, 3, 4],
+        };
+
+        let rows: Vec<(Vec<f64>, f64)> = self
+            .samples
+            .iter()
+            .map(|s| {
+                let f = features(s.raw, s.intensity);
+                (terms.iter().map(|t| f[*t]).collect(), s.truth - s.raw)
+            })
+            .collect();
+
+        let Some(solution) = least_squares(&rows, terms.len()) else {
+            self.status = Some("Fit failed, capture more distances".to_owned());
+            return;
+        };
+
+        let mut correction = RangeCorrection::default();
+        for (t, c) in terms.iter().zip(solution) {
+            correction.coeffs[*t] = c;
+        }
+
+        let rms = (self
+            .samples
+            .iter()
+            .map(|s| {
+                let corrected = correction.correct(s.raw as f32, s.intensity as f32) as f64;
+                (corrected - s.truth).powi(2)
+            })
+            .sum::<f64>()
+            / self.samples.len() as f64)
+            .sqrt();
+
+        self.fitted = Some((correction, rms));
+        self.status = None;
+    }
+
+    fn save(&mut self, device: &str, correction: RangeCorrection) -> std::io::Result<()> {
+        self.corrections.insert(device.to_owned(), correction);
+
+        let mut lines: Vec<_> = self
+            .corrections
+            .iter()
+            .map(|(key, c)| c.to_line(key))
+            .collect();
+        lines.sort();
+
+        std::fs::write(&self.path, lines.join("\n") + "\n")
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, device: &str) {
+        match self.corrections.get_mut(device) {
+            Some(correction) => {
+                if ui
+                    .checkbox(&mut correction.enabled, "Apply correction")
+                    .changed()
+                {
+                    let correction = *correction;
+                    if let Err(err) = self.save(device, correction) {
+                        self.status = Some(format!("Cannot save: {}", err));
+                    }
+                }
+            }
+            None => {
+                ui.label("No correction for this device");
+            }
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Target at");
+            ui.add(
+                DragValue::new(&mut self.known_distance)
+                    .speed(0.01)
+                    .range(0.02..=12.0)
+                    .suffix("m"),
+            );
+            ui.label("θ");
+            ui.add(
+                DragValue::new(&mut self.target_bearing)
+                    .range(-180.0..=180.0)
+                    .suffix("°"),
+            );
+        });
+        ui.add(Slider::new(&mut self.target_width, 1.0..=30.0).text("Target width (°)"));
+
+        ui.horizontal(|ui| {
+            if self.capture_left > 0 {
+                ui.spinner();
+                ui.label(format!("{} scans left", self.capture_left));
+            } else if ui
+                .add_enabled(!device.is_empty(), egui::Button::new("Capture"))
+                .on_hover_text("Gathers the raw ranges within the target sector")
+                .clicked()
+            {
+                self.capture_left = CAPTURE_SCANS;
+            }
+            if ui.button("Fit").clicked() {
+                self.fit();
+            }
+            if ui.button("Clear").clicked() {
+                self.samples.clear();
+                self.fitted = None;
+            }
+        });
+
+        // mean raw error per known distance
+        let mut per_distance: Vec<(f64, f64, usize)> = vec![];
+        for s in &self.samples {
+            match per_distance
+                .iter_mut()
+                .find(|(d, _, _)| (d - s.known).abs() < 1e-3)
+            {
+                Some((_, err, n)) => {
+                    *err += s.raw - s.truth;
+                    *n += 1;
+                }
+                None => per_distance.push((s.known, s.raw - s.truth, 1)),
+            }
+        }
+        if !per_distance.is_empty() {
+            egui::Grid::new("range_samples")
+                .num_columns(3)
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("Distance");
+                    ui.label("Samples");
+                    ui.label("Mean error");
+                    ui.end_row();
+                    for (d, err, n) in per_distance {
+                        ui.label(format!("{:.3}m", d));
+                        ui.label(format!("{}", n));
+                        ui.label(format!("{:+.1}mm", err / n as f64 * 1e3));
+                        ui.end_row();
+                    }
+                });
+        }
+
+        if let Some((correction, rms)) = self.fitted {
+            ui.label(format!("Residual after correction: {:.1}mm rms", rms * 1e3));
+            if ui
+                .add_enabled(!device.is_empty(), egui::Button::new("Save for device"))
+                .clicked()
+            {
+                self.status = Some(match self.save(device, correction) {
+                    Ok(()) => format!("Saved for {}", device),
+                    Err(err) => format!("Cannot save: {}", err),
+                });
+            }
+        }
+
+        if let Some(status) = &self.status {
+            ui.label(status);
+        }
+    }
+}
+
+/// Solves the normal equations of a linear least squares problem.
+fn least_squares(rows: &[(Vec<f64>, f64)], n: usize) -> Option<Vec<f64>> {
+    let mut a = vec![vec![0.0; n + 1]; n];
+    for (x, y) in rows {
+        for i in 0..n {
+            for j in 0..n {
+                a[i][j] += x[i] * x[j];
+            }
+            a[i][n] += x[i] * y;
+        }
+    }
+
+    solve_linear(a)
+}
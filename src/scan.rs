@@ -0,0 +1,117 @@
+use std::time::Instant;
+
+use crate::ld19codec::Ld19Point;
+use crate::mounting::Mounting;
+
+#[derive(Clone, Copy)]
+pub struct LidarPoint {
+    pub point: Ld19Point,
+    pub angle: f32,
+    /// range in meters, initialized from the raw point and refined by the processing stages
+    pub distance: f32,
+    pub instant: Instant,
+    /// index of the sensor that measured the point
+    pub sensor: usize,
+}
+
+impl LidarPoint {
+    pub fn new(angle: f32, point: Ld19Point, instant: Instant) -> Self {
+        Self {
+            point,
+            angle,
+            distance: point.distance_in_meters(),
+            instant,
+            sensor: 0,
+        }
+    }
+
+    pub fn position(&self) -> [f64; 2] {
+        let rad = self.angle.to_radians();
+
+        // align +y with the forward direction of the sensor
+        let x = rad.sin() * self.distance;
+        let y = rad.cos() * self.distance;
+
+        [x as f64, y as f64]
+    }
+}
+
+/// A full rotation of the sensor.
+#[derive(Clone)]
+pub struct Scan {
+    pub points: Vec<LidarPoint>,
+    /// the points as received, before processing
+    pub raw: Vec<LidarPoint>,
+    pub started: Instant,
+    pub completed: Instant,
+    /// pose of the sensor on the robot at the time of the scan
+    pub mounting: Mounting,
+    /// mountings of the other sensors whose points were merged into the scan
+    pub merged: Vec<(usize, Mounting)>,
+}
+
+impl Scan {
+    /// Appends the points of a scan of another sensor, they stay in the frame
+    /// and angular order of their sensor.
+    pub fn merge(&mut self, other: &Scan, sensor: usize) {
+        let tag = |p: &LidarPoint| LidarPoint { sensor, ..*p };
+
+        self.points.extend(other.points.iter().map(tag));
+        self.raw.extend(other.raw.iter().map(tag));
+        self.merged.push((sensor, other.mounting));
+    }
+
+    /// Mounting of the sensor that measured the points with the index.
+    pub fn mounting_of(&self, sensor: usize) -> &Mounting {
+        self.merged
+            .iter()
+            .find(|(i, _)| *i == sensor)
+            .map_or(&self.mounting, |(_, mounting)| mounting)
+    }
+
+    /// Position of a point of the scan in the robot frame.
+    pub fn position(&self, p: &LidarPoint) -> [f64; 2] {
+        self.mounting_of(p.sensor).transform(p.position())
+    }
+
+    /// Positions of the points in the robot frame.
+    pub fn positions(&self) -> impl Iterator<Item = [f64; 2]> + '_ {
+        self.points.iter().map(|p| self.position(p))
+    }
+
+    /// The points of each sensor, ordered by angle within the run.
+    pub fn sensor_runs(&self) -> impl Iterator<Item = &[LidarPoint]> {
+        self.points.chunk_by(|a, b| a.sensor == b.sensor)
+    }
+
+    /// Positions in the robot frame, one run per sensor.
+    pub fn sensor_positions(&self) -> Vec<Vec<[f64; 2]>> {
+        self.sensor_runs()
+            .map(|run| run.iter().map(|p| self.position(p)).collect())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+impl Scan {
+    /// Scan of the first sensor from readings of angle (°), range (m) and intensity.
+    pub fn from_readings(readings: impl IntoIterator<Item = (f32, f32, u8)>) -> Self {
+        let now = Instant::now();
+        let points: Vec<_> = readings
+            .into_iter()
+            .map(|(angle, range, intensity)| {
+                let point = Ld19Point::new((range * 1e3).round() as u16, intensity);
+                LidarPoint::new(angle, point, now)
+            })
+            .collect();
+
+        Scan {
+            raw: points.clone(),
+            points,
+            started: now,
+            completed: now,
+            mounting: Mounting::default(),
+            merged: vec![],
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use std::time::Instant;
+
+use ::futures::StreamExt;
+use eframe::egui::{self, Color32};
+use tokio::runtime;
+use tokio_serial::SerialPortBuilderExt;
+use tokio_util::codec::Decoder;
+
+use crate::ld19codec::{self, Ld19Frame};
+use crate::mounting::Mounting;
+use crate::pipeline::Pipeline;
+use crate::scan::{LidarPoint, Scan};
+
+/// Point colours of the sensors, in the order they are connected.
+const SENSOR_COLORS: [Color32; 4] = [
+    Color32::GREEN,
+    Color32::from_rgb(80, 160, 255),
+    Color32::from_rgb(255, 160, 40),
+    Color32::from_rgb(230, 80, 255),
+];
+
+#[derive(Debug, Default)]
+pub struct RollingAverage {
+    index: usize,
+    hist: [f32; 8],
+}
+
+impl RollingAverage {
+    pub fn push(&mut self, val: f32) {
+        self.hist[self.index] = val;
+        self.index = (self.index + 1) % self.hist.len();
+    }
+
+    pub fn get(&self) -> f32 {
+        self.hist.iter().sum::<f32>() / self.hist.len() as f32
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct LidarStats {
+    pub angular_resolution: RollingAverage,
+    pub angular_rate: RollingAverage,
+    pub sample_rate: RollingAverage,
+    pub max_dist: RollingAverage,
+    pub min_dist: RollingAverage,
+    pub crc_errors: u32,
+    last_start_angle: f32,
+    last_completed_rotation: Option<Instant>,
+}
+
+/// A connected LIDAR with its serial worker.
+pub struct Sensor {
+    pub port: String,
+    /// identifies the device, preferably by its USB serial number
+    pub key: String,
+    pub serial_number: Option<String>,
+    /// the points are part of the merged scan
+    pub enabled: bool,
+    pub color: Color32,
+    pub mounting: Mounting,
+    /// processing of the scans of this sensor before they are merged
+    pub pipeline: Pipeline,
+    pub stats: LidarStats,
+    /// newest scan, the others are merged into the scans of the first enabled sensor
+    pub latest: Option<Scan>,
+    current_scan: Vec<LidarPoint>,
+    rx: std::sync::mpsc::Receiver<Ld19Frame>,
+    worker: tokio::task::JoinHandle<()>,
+    stop_signal: tokio::sync::mpsc::Sender<()>,
+}
+
+impl Sensor {
+    /// Opens the port in a worker task, the `index`-th sensor gets the `index`-th colour.
+    pub fn connect(rt: &runtime::Runtime, ctx: &egui::Context, port: String, index: usize) -> Self {
+        let serial_number = usb_serial_number(&port);
+        let key = serial_number
+            .clone()
+            .unwrap_or_else(|| port.clone())
+            .replace(char::is_whitespace, "_");
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let (stop_signal, mut rx_stop) = tokio::sync::mpsc::channel(1);
+        let egui_ctx = ctx.clone();
+        let worker_port = port.clone();
+
+        let worker = rt.spawn(async move {
+            let port = tokio_serial::new(worker_port, 230400)
+                .stop_bits(tokio_serial::StopBits::One)
+                .parity(tokio_serial::Parity::None)
+                .flow_control(tokio_serial::FlowControl::None)
+                .open_native_async()
+                .expect("Cannot open port");
+
+            let codec = ld19codec::Ld19Codec {};
+            let mut reader = codec.framed(port);
+
+            loop {
+                tokio::select! {
+                    Some(frame) = reader.next() => {
+                        if let Ok(result) = frame {
+                            tx.send(result).unwrap();
+                            egui_ctx.request_repaint();
+                        } else {
+                            break;
+                        }
+                    },
+
+                    Some(_) = rx_stop.recv() => {
+                        break;
+                    }
+                }
+            }
+
+            println!("exit worker");
+        });
+
+        Self {
+            port,
+            key,
+            serial_number,
+            enabled: true,
+            color: SENSOR_COLORS[index % SENSOR_COLORS.len()],
+            mounting: Mounting::default(),
+            pipeline: Default::default(),
+            stats: Default::default(),
+            latest: None,
+            current_scan: vec![],
+            rx,
+            worker,
+            stop_signal,
+        }
+    }
+
+    /// Exits the worker task.
+    pub fn disconnect(self, rt: &runtime::Runtime) {
+        if !self.worker.is_finished() {
+            rt.block_on(self.stop_signal.send(())).ok();
+        }
+    }
+
+    /// The worker exited, e.g. because the device was unplugged.
+    pub fn is_disconnected(&self) -> bool {
+        self.stop_signal.is_closed()
+    }
+
+    /// Assembles the received packets, returns the completed rotations.
+    pub fn fetch(&mut self) -> Vec<Scan> {
+        let frames: Vec<_> = self.rx.try_iter().collect();
+        let mut scans = vec![];
+
+        for frame in frames {
+            match frame {
+                Ld19Frame::Packet(packet) => {
+                    let now = Instant::now();
+                    for (angle, point) in packet.iter_points() {
+                        self.current_scan.push(LidarPoint::new(angle, *point, now));
+                    }
+
+                    // calculate stats
+                    self.stats
+                        .angular_resolution
+                        .push(packet.delta_angle_per_point_deg());
+                    if self.stats.last_start_angle > packet.start_angle_deg() {
+                        let started = self.stats.last_completed_rotation.unwrap_or(now);
+                        let dt = now.duration_since(started);
+                        self.stats.last_completed_rotation = Some(now);
+                        self.stats.angular_rate.push(dt.as_secs_f32().recip());
+                        self.stats.sample_rate.push(
+                            dt.as_secs_f32().recip() * (360.0 / packet.delta_angle_per_point_deg()),
+                        );
+
+                        let points = std::mem::take(&mut self.current_scan);
+                        scans.push(Scan {
+                            raw: points.clone(),
+                            points,
+                            started,
+                            completed: now,
+                            mounting: self.mounting,
+                            merged: vec![],
+                        });
+                    }
+                    self.stats.last_start_angle = packet.start_angle_deg();
+                }
+                Ld19Frame::CRCError => self.stats.crc_errors += 1,
+            }
+        }
+
+        scans
+    }
+
+    /// Runs the pipeline on a scan of this sensor and updates the range stats.
+    pub fn process(&mut self, scan: &mut Scan) {
+        self.pipeline.process(scan);
+
+        let distances = scan.points.iter().map(|p| p.distance);
+        if let Some(max) = distances.clone().reduce(f32::max) {
+            self.stats.max_dist.push(max);
+            self.stats
+                .min_dist
+                .push(distances.reduce(f32::min).unwrap_or_default());
+        }
+    }
+
+    pub fn stats_ui(&self, ui: &mut egui::Ui) {
+        egui::Grid::new(("stats", &self.port))
+            .num_columns(2)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("CRC errors");
+                ui.label(format!("{}", self.stats.crc_errors));
+                ui.end_row();
+                ui.label("Sample rate");
+                ui.label(format!("{:.1}kHz", self.stats.sample_rate.get() * 1e-3));
+                ui.end_row();
+                ui.label("Angular rate");
+                ui.label(format!("{:.1}Hz", self.stats.angular_rate.get()));
+                ui.end_row();
+                ui.label("Angular resolution");
+                ui.label(format!("{:.2}°", self.stats.angular_resolution.get()));
+                ui.end_row();
+                ui.label("Min distance");
+                ui.label(format!("{:.2}m", self.stats.min_dist.get()));
+                ui.end_row();
+                ui.label("Max distance");
+                ui.label(format!("{:.2}m", self.stats.max_dist.get()));
+                ui.end_row();
+                for (name, removed) in self.pipeline.removed_points() {
+                    ui.label(format!("Removed ({})", name));
+                    ui.label(format!("{}", removed));
+                    ui.end_row();
+                }
+            });
+    }
+}
+
+pub fn usb_serial_number(port: &str) -> Option<String> {
+    tokio_serial::available_ports()
+        .ok()?
+        .into_iter()
+        .find(|p| p.port_name == port)
+        .and_then(|p| match p.port_type {
+            tokio_serial::SerialPortType::UsbPort(info) => info.serial_number,
+            _ => None,
+        })
+}
+
+pub fn available_serial_ports() -> Vec<String> {
+    if let Ok(ports) = tokio_serial::available_ports() {
+        // Note: filtering on port type does not work / is unreliable
+        return ports
+            .iter()
+            .map(|p| p.port_name.clone())
+            .filter(|p| !p.contains("ttyS"))
+            .collect();
+    }
+
+    vec![]
+}
@@ -0,0 +1,689 @@
+use std::f64::consts::{PI, TAU};
+use std::fmt::Write;
+use std::path::Path;
+use std::time::Instant;
+
+use eframe::egui::{self, Color32, Slider};
+use egui_plot::{Line, LineStyle, PlotPoints, PlotUi, Points};
+
+use crate::export::Svg;
+use crate::geometry::{distance, Pose2D, SkylineMatrix};
+use crate::icp::{IcpParams, Reference};
+use crate::occupancy::{beams, Beam, GridMap, MapLayer};
+use crate::overlay::MAX_RANGE;
+use crate::scan::Scan;
+
+const TRAJECTORY_COLOR: Color32 = Color32::from_rgb(0, 200, 255);
+const LOOP_COLOR: Color32 = Color32::from_rgb(255, 140, 0);
+
+/// Weights of the translation and rotation errors of the constraints.
+const TRANSLATION_WEIGHT: f64 = 100.0;
+const ROTATION_WEIGHT: f64 = 400.0;
+
+/// Keeps the first node in place during the optimization.
+const ANCHOR_WEIGHT: f64 = 1e6;
+
+fn wrap(angle: f64) -> f64 {
+    (angle + PI).rem_euclid(TAU) - PI
+}
+
+struct Node {
+    pose: Pose2D,
+    beams: Vec<Beam>,
+}
+
+impl Node {
+    fn points(&self) -> Vec<[f64; 2]> {
+        self.beams.iter().filter(|b| b.hit).map(|b| b.end).collect()
+    }
+
+    /// Reference of the points, the beams of each sensor share their origin.
+    fn reference(&self, cell_size: f64) -> Reference {
+        let runs: Vec<Vec<_>> = self
+            .beams
+            .chunk_by(|a, b| a.origin == b.origin)
+            .map(|run| run.iter().filter(|b| b.hit).map(|b| b.end).collect())
+            .collect();
+
+        Reference::from_runs(runs.iter().map(Vec::as_slice), cell_size)
+    }
+}
+
+/// Measured pose of node `to` relative to node `from`.
+struct Edge {
+    from: usize,
+    to: usize,
+    relative: Pose2D,
+    loop_closure: bool,
+}
+
+/// Moves the poses to best agree with the constraints (Gauss-Newton).
+fn optimize(poses: &mut [Pose2D], edges: &[Edge], iterations: usize) {
+    let n = poses.len() * 3;
+    if n == 0 {
+        return;
+    }
+
+    // the normal equations only couple the poses linked by an edge
+    let mut first_node: Vec<_> = (0..poses.len()).collect();
+    for edge in edges {
+        let (lo, hi) = (edge.from.min(edge.to), edge.from.max(edge.to));
+        first_node[hi] = first_node[hi].min(lo);
+    }
+    let first: Vec<_> = (0..n).map(|i| first_node[i / 3] * 3).collect();
+
+    for _ in 0..iterations {
+        let mut h = SkylineMatrix::new(first.clone());
+        let mut g = vec![0.0; n];
+        for edge in edges {
+            let (a, b) = (poses[edge.from], poses[edge.to]);
+            let z = edge.relative;
+            let (sa, ca) = a.theta.sin_cos();
+            let (sz, cz) = z.theta.sin_cos();
+
+            // error in the frame of the measurement
+            let d = [b.x - a.x, b.y - a.y];
+            let local = [ca * d[0] + sa * d[1], -sa * d[0] + ca * d[1]];
+            let t = [local[0] - z.x, local[1] - z.y];
+            let e = [
+                cz * t[0] + sz * t[1],
+                -sz * t[0] + cz * t[1],
+                wrap(b.theta - a.theta - z.theta),
+            ];
+
+            // jacobians with respect to both poses, rows are the error terms
+            let rot = |v: [f64; 2]| [cz * v[0] + sz * v[1], -sz * v[0] + cz * v[1]];
+            let dx = rot([ca, -sa]);
+            let dy = rot([sa, ca]);
+            let dtheta = rot([-sa * d[0] + ca * d[1], -ca * d[0] - sa * d[1]]);
+            let ja = [
+                [-dx[0], -dy[0], dtheta[0]],
+                [-dx[1], -dy[1], dtheta[1]],
+                [0.0, 0.0, -1.0],
+            ];
+            let jb = [[dx[0], dy[0], 0.0], [dx[1], dy[1], 0.0], [0.0, 0.0, 1.0]];
+            let weights = [TRANSLATION_WEIGHT, TRANSLATION_WEIGHT, ROTATION_WEIGHT];
+
+            let blocks = [(edge.from * 3, ja), (edge.to * 3, jb)];
+            for (row, ji) in blocks {
+                for (col, jj) in blocks {
+                    // the lower triangle, the upper one mirrors it
+                    let entries = (0..3).flat_map(|r| (0..3).map(move |c| (r, c)));
+                    for (r, c) in entries.filter(|(r, c)| row + r >= col + c) {
+                        let value = (0..3)
+                            .map(|k| ji[k][r] * weights[k] * jj[k][c])
+                            .sum::<f64>();
+                        h.add(row + r, col + c, value);
+                    }
+                }
+                for (r, g) in g[row..row + 3].iter_mut().enumerate() {
+                    *g -= (0..3).map(|k| ji[k][r] * weights[k] * e[k]).sum::<f64>();
+                }
+            }
+        }
+        for i in 0..3 {
+            h.add(i, i, ANCHOR_WEIGHT);
+        }
+
+        let Some(delta) = h.solve(&g) else {
+            return;
+        };
+        for (pose, d) in poses.iter_mut().zip(delta.chunks(3)) {
+            pose.x += d[0];
+            pose.y += d[1];
+            pose.theta = wrap(pose.theta + d[2]);
+        }
+
+        if delta.iter().all(|d| d.abs() < 1e-6) {
+            break;
+        }
+    }
+}
+
+/// Builds a consistent map from keyframes linked by scan matching and loop closures.
+pub struct Slam {
+    pub enabled: bool,
+    params: IcpParams,
+    keyframe_distance: f64,
+    keyframe_angle_deg: f64,
+    /// keyframes within this distance are checked for a loop closure
+    loop_radius: f64,
+    /// recent keyframes are not considered for a loop closure
+    loop_min_gap: usize,
+    /// matches with a higher error are not accepted as loop closure
+    loop_max_rms: f64,
+    resolution: f64,
+    size: f64,
+    opacity: f32,
+    show_graph: bool,
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+    /// scan matching reference of the last keyframe
+    reference: Option<Reference>,
+    /// robot pose in the map frame
+    pose: Pose2D,
+    velocity: Pose2D,
+    failures: usize,
+    grid: GridMap,
+    layer: MapLayer,
+    path: String,
+    status: Option<String>,
+    source: Option<Instant>,
+}
+
+impl Default for Slam {
+    fn default() -> Self {
+        let (resolution, size) = (0.05, 40.0);
+        Self {
+            enabled: false,
+            params: Default::default(),
+            keyframe_distance: 0.3,
+            keyframe_angle_deg: 15.0,
+            loop_radius: 2.0,
+            loop_min_gap: 20,
+            loop_max_rms: 0.03,
+            resolution,
+            size,
+            opacity: 1.0,
+            show_graph: true,
+            nodes: vec![],
+            edges: vec![],
+            reference: None,
+            pose: Default::default(),
+            velocity: Default::default(),
+            failures: 0,
+            grid: GridMap::new(resolution, size),
+            layer: MapLayer::new("slam"),
+            path: "survey.graph".to_owned(),
+            status: None,
+            source: None,
+        }
+    }
+}
+
+impl Slam {
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.edges.clear();
+        self.reference = None;
+        self.pose = Default::default();
+        self.velocity = Default::default();
+        self.failures = 0;
+        self.rebuild_map();
+        self.source = None;
+    }
+
+    fn rebuild_map(&mut self) {
+        self.grid = GridMap::new(self.resolution, self.size);
+        for node in &self.nodes {
+            self.grid.integrate_beams(node.pose, &node.beams);
+        }
+        self.layer.dirty = true;
+    }
+
+    fn add_node(&mut self, node: Node) {
+        self.reference = Some(node.reference(self.params.max_correspondence));
+        self.grid.integrate_beams(node.pose, &node.beams);
+        self.layer.dirty = true;
+        self.nodes.push(node);
+    }
+
+    /// Matches the scan with the last keyframe unless already done.
+    pub fn update(&mut self, scan: &Scan) {
+        if !self.enabled || self.source == Some(scan.completed) {
+            return;
+        }
+        self.source = Some(scan.completed);
+
+        let node = Node {
+            pose: self.pose,
+            beams: beams(scan, MAX_RANGE as f32),
+        };
+        let points = node.points();
+
+        let (Some(last), Some(reference)) = (self.nodes.last(), &self.reference) else {
+            self.add_node(node);
+            return;
+        };
+        let last_pose = last.pose;
+
+        let predicted = self.pose.compose(&self.velocity);
+        let guess = last_pose.inverse().compose(&predicted);
+        let Some(alignment) = reference.align(&points, guess, &self.params) else {
+            self.failures += 1;
+            self.pose = predicted;
+            return;
+        };
+
+        let pose = last_pose.compose(&alignment.pose);
+        self.velocity = self.pose.inverse().compose(&pose);
+        self.pose = pose;
+
+        let relative = alignment.pose;
+        if relative.x.hypot(relative.y) < self.keyframe_distance
+            && relative.theta.to_degrees().abs() < self.keyframe_angle_deg
+        {
+            return;
+        }
+
+        let index = self.nodes.len();
+        self.edges.push(Edge {
+            from: index - 1,
+            to: index,
+            relative,
+            loop_closure: false,
+        });
+        self.add_node(Node { pose, ..node });
+
+        if self.close_loop(index, &points) {
+            let mut poses: Vec<_> = self.nodes.iter().map(|n| n.pose).collect();
+            optimize(&mut poses, &self.edges, 10);
+            for (node, pose) in self.nodes.iter_mut().zip(poses) {
+                node.pose = pose;
+            }
+            self.pose = self.nodes[index].pose;
+            self.rebuild_map();
+        }
+    }
+
+    /// Looks for an older keyframe close to `index` and adds a constraint if the scans match.
+    fn close_loop(&mut self, index: usize, points: &[[f64; 2]]) -> bool {
+        let pose = self.nodes[index].pose;
+        let candidate = self.nodes[..index.saturating_sub(self.loop_min_gap)]
+            .iter()
+            .enumerate()
+            .map(|(i, n)| (i, distance([n.pose.x, n.pose.y], [pose.x, pose.y])))
+            .filter(|(_, d)| *d <= self.loop_radius)
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+
+        let Some((other, _)) = candidate else {
+            return false;
+        };
+
+        // a wider search, the drift may be large
+        let params = IcpParams {
+            max_correspondence: self.params.max_correspondence * 2.0,
+            ..self.params
+        };
+        let reference = self.nodes[other].reference(params.max_correspondence);
+        let guess = self.nodes[other].pose.inverse().compose(&pose);
+        let accepted = reference
+            .align(points, guess, &params)
+            .filter(|a| a.rms <= self.loop_max_rms && a.matches >= points.len() / 2);
+
+        match accepted {
+            Some(alignment) => {
+                self.edges.push(Edge {
+                    from: other,
+                    to: index,
+                    relative: alignment.pose,
+                    loop_closure: true,
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Writes the keyframes and constraints, and the map next to it.
+    fn save(&self, path: &Path) -> std::io::Result<()> {
+        let mut text = "# ld19 pose graph\n".to_owned();
+        for node in &self.nodes {
+            let _ = write!(
+                text,
+                "node {} {} {}",
+                node.pose.x, node.pose.y, node.pose.theta
+            );
+            for beam in &node.beams {
+                let _ = write!(
+                    text,
+                    " {} {} {} {} {}",
+                    beam.origin[0], beam.origin[1], beam.end[0], beam.end[1], beam.hit as u8
+                );
+            }
+            text.push('\n');
+        }
+        for edge in &self.edges {
+            let z = edge.relative;
+            let _ = writeln!(
+                text,
+                "edge {} {} {} {} {} {}",
+                edge.from, edge.to, z.x, z.y, z.theta, edge.loop_closure
+            );
+        }
+
+        std::fs::write(path, text)?;
+        self.grid.save(&path.with_extension("pgm"))
+    }
+
+    fn load(&mut self, path: &Path) -> Result<(), String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let mut nodes = vec![];
+        let mut edges = vec![];
+
+        for (number, line) in text.lines().enumerate() {
+            let invalid = || format!("invalid line {}", number + 1);
+
+            if let Some(values) = line.strip_prefix("node ") {
+                let values: Vec<f64> = values
+                    .split_whitespace()
+                    .map(|v| v.parse().map_err(|_| invalid()))
+                    .collect::<Result<_, _>>()?;
+                if values.len() < 3 || !(values.len() - 3).is_multiple_of(5) {
+                    return Err(invalid());
+                }
+
+                nodes.push(Node {
+                    pose: Pose2D::new(values[0], values[1], values[2]),
+                    beams: values[3..]
+                        .chunks(5)
+                        .map(|b| Beam {
+                            origin: [b[0], b[1]],
+                            end: [b[2], b[3]],
+                            hit: b[4] != 0.0,
+                        })
+                        .collect(),
+                });
+            } else if let Some(values) = line.strip_prefix("edge ") {
+                let values: Vec<_> = values.split_whitespace().collect();
+                let [from, to, x, y, theta, loop_closure] = values[..] else {
+                    return Err(invalid());
+                };
+                let index = |v: &str| {
+                    v.parse::<usize>()
+                        .ok()
+                        .filter(|i| *i < nodes.len())
+                        .ok_or_else(invalid)
+                };
+                let float = |v: &str| v.parse::<f64>().map_err(|_| invalid());
+
+                edges.push(Edge {
+                    from: index(from)?,
+                    to: index(to)?,
+                    relative: Pose2D::new(float(x)?, float(y)?, float(theta)?),
+                    loop_closure: loop_closure == "true",
+                });
+            }
+        }
+
+        self.clear();
+        self.nodes = nodes;
+        self.edges = edges;
+        if let Some(last) = self.nodes.last() {
+            // continue from the last keyframe
+            self.pose = last.pose;
+            self.reference = Some(last.reference(self.params.max_correspondence));
+        }
+        self.rebuild_map();
+
+        Ok(())
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.enabled, "Build map")
+            .on_hover_text("Continues from the last keyframe");
+        ui.add(
+            Slider::new(&mut self.params.max_correspondence, 0.05..=1.0)
+                .text("Max. match distance (m)"),
+        );
+        ui.add(Slider::new(&mut self.keyframe_distance, 0.05..=2.0).text("Keyframe every (m)"));
+        ui.add(Slider::new(&mut self.keyframe_angle_deg, 1.0..=90.0).text("or (°)"));
+        ui.add(Slider::new(&mut self.loop_radius, 0.5..=10.0).text("Loop search radius (m)"));
+        ui.add(Slider::new(&mut self.loop_min_gap, 2..=200).text("Loop min. keyframes"));
+        ui.add(
+            Slider::new(&mut self.loop_max_rms, 0.005..=0.1)
+                .text("Loop max. error (m)")
+                .logarithmic(true),
+        );
+
+        let before = (self.resolution, self.size);
+        ui.add(
+            Slider::new(&mut self.resolution, 0.01..=0.5)
+                .text("Resolution (m)")
+                .logarithmic(true),
+        );
+        ui.add(Slider::new(&mut self.size, 2.0..=200.0).text("Size (m)"));
+        self.resolution = self.resolution.max(GridMap::min_resolution(self.size));
+        if before != (self.resolution, self.size) {
+            self.rebuild_map();
+        }
+        ui.add(Slider::new(&mut self.opacity, 0.0..=1.0).text("Opacity"));
+        ui.checkbox(&mut self.show_graph, "Keyframes and loops");
+
+        let loops = self.edges.iter().filter(|e| e.loop_closure).count();
+        ui.label(format!(
+            "{} keyframes, {} loop closures, {} failed matches",
+            self.nodes.len(),
+            loops,
+            self.failures
+        ));
+        ui.label(format!(
+            "Pose ({:.2}, {:.2})m {:.1}°",
+            self.pose.x,
+            self.pose.y,
+            -self.pose.theta.to_degrees()
+        ));
+
+        ui.horizontal(|ui| {
+            if ui.button("Optimize").clicked() {
+                let mut poses: Vec<_> = self.nodes.iter().map(|n| n.pose).collect();
+                optimize(&mut poses, &self.edges, 20);
+                for (node, pose) in self.nodes.iter_mut().zip(poses) {
+                    node.pose = pose;
+                }
+                self.rebuild_map();
+            }
+            if ui.button("Clear").clicked() {
+                self.clear();
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(&mut self.path).desired_width(120.0));
+            if ui.button("Save").clicked() {
+                let path = Path::new(&self.path);
+                self.status = Some(match self.save(path) {
+                    Ok(()) => format!("Saved {} and its map", path.display()),
+                    Err(err) => format!("Cannot save: {}", err),
+                });
+            }
+            if ui.button("Load").clicked() {
+                let path = self.path.clone();
+                self.status = Some(match self.load(Path::new(&path)) {
+                    Ok(()) => format!("Loaded {} keyframes", self.nodes.len()),
+                    Err(err) => format!("Cannot load {}: {}", path, err),
+                });
+            }
+        });
+        if let Some(status) = &self.status {
+            ui.label(status);
+        }
+    }
+
+    /// Keyframe positions as seen from the current pose.
+    fn relative_keyframes(&self) -> Vec<[f64; 2]> {
+        let view = self.pose.inverse();
+        self.nodes
+            .iter()
+            .map(|n| view.transform([n.pose.x, n.pose.y]))
+            .collect()
+    }
+
+    pub fn show(&mut self, plot_ui: &mut PlotUi) {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        // everything relative to the current pose, to overlay the live scan
+        let view = self.pose.inverse();
+        self.layer.show(plot_ui, &self.grid, view, self.opacity);
+
+        if !self.show_graph {
+            return;
+        }
+
+        let keyframes = self.relative_keyframes();
+        let mut trajectory = keyframes.clone();
+        trajectory.push([0.0, 0.0]);
+
+        plot_ui.line(
+            Line::new(PlotPoints::new(trajectory))
+                .color(TRAJECTORY_COLOR)
+                .width(1.5)
+                .name("Trajectory"),
+        );
+        plot_ui.points(
+            Points::new(keyframes.clone())
+                .radius(3.0)
+                .color(TRAJECTORY_COLOR)
+                .name("Keyframes"),
+        );
+        for edge in self.edges.iter().filter(|e| e.loop_closure) {
+            plot_ui.line(
+                Line::new(PlotPoints::new(vec![
+                    keyframes[edge.from],
+                    keyframes[edge.to],
+                ]))
+                .color(LOOP_COLOR)
+                .style(LineStyle::dashed_dense())
+                .allow_hover(false),
+            );
+        }
+    }
+
+    pub fn write_svg(&self, svg: &mut Svg) {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        MapLayer::write_svg(svg, &self.grid, self.pose.inverse(), self.opacity);
+        if !self.show_graph || self.nodes.len() < 2 {
+            return;
+        }
+
+        let keyframes = self.relative_keyframes();
+        svg.polyline(&keyframes, 1.5, TRAJECTORY_COLOR, false);
+        for p in &keyframes {
+            svg.circle(*p, 3.0, TRAJECTORY_COLOR);
+        }
+        for edge in self.edges.iter().filter(|e| e.loop_closure) {
+            svg.polyline(
+                &[keyframes[edge.from], keyframes[edge.to]],
+                1.0,
+                LOOP_COLOR,
+                true,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::FRAC_PI_2;
+
+    use super::*;
+
+    /// Pose of `b` in the frame of `a`, as scan matching measures it.
+    fn relative(a: Pose2D, b: Pose2D) -> Pose2D {
+        let (s, c) = a.theta.sin_cos();
+        let d = [b.x - a.x, b.y - a.y];
+        Pose2D::new(
+            c * d[0] + s * d[1],
+            -s * d[0] + c * d[1],
+            wrap(b.theta - a.theta),
+        )
+    }
+
+    /// Drives around a 2m square, turning left at each corner.
+    fn square() -> Vec<Pose2D> {
+        (0..8)
+            .map(|i| {
+                let corner = [[0.0, 0.0], [2.0, 0.0], [2.0, 2.0], [0.0, 2.0]][i / 2];
+                let theta = FRAC_PI_2 * (i / 2) as f64;
+                let (s, c) = theta.sin_cos();
+                let along = if i % 2 == 1 { 1.0 } else { 0.0 };
+                Pose2D::new(corner[0] + along * c, corner[1] + along * s, wrap(theta))
+            })
+            .collect()
+    }
+
+    fn assert_close(poses: &[Pose2D], expected: &[Pose2D], tolerance: f64) {
+        for (pose, expected) in poses.iter().zip(expected) {
+            let error = distance([pose.x, pose.y], [expected.x, expected.y])
+                + wrap(pose.theta - expected.theta).abs();
+            assert!(error < tolerance, "{:?} != {:?}", pose, expected);
+        }
+    }
+
+    #[test]
+    fn loop_closure_removes_the_drift() {
+        let truth = square();
+        let n = truth.len();
+        let mut edges: Vec<_> = (1..n)
+            .map(|i| Edge {
+                from: i - 1,
+                to: i,
+                relative: relative(truth[i - 1], truth[i]),
+                loop_closure: false,
+            })
+            .collect();
+        edges.push(Edge {
+            from: n - 1,
+            to: 0,
+            relative: relative(truth[n - 1], truth[0]),
+            loop_closure: true,
+        });
+
+        // odometry that turns a little too much at every step
+        let mut poses = vec![truth[0]];
+        for edge in &edges[..n - 1] {
+            let step = Pose2D::new(edge.relative.x, edge.relative.y, edge.relative.theta + 0.05);
+            poses.push(poses.last().expect("start").compose(&step));
+        }
+        let drift = distance(
+            [poses[n - 1].x, poses[n - 1].y],
+            [truth[n - 1].x, truth[n - 1].y],
+        );
+        assert!(drift > 0.1, "{}", drift);
+
+        optimize(&mut poses, &edges, 20);
+        assert_close(&poses, &truth, 1e-6);
+    }
+
+    #[test]
+    fn conflicting_loop_spreads_the_error() {
+        let truth = square();
+        let n = truth.len();
+        let mut edges: Vec<_> = (1..n)
+            .map(|i| Edge {
+                from: i - 1,
+                to: i,
+                relative: relative(truth[i - 1], truth[i]),
+                loop_closure: false,
+            })
+            .collect();
+        // the loop closure claims the start is 8cm further
+        let mut closure = relative(truth[n - 1], truth[0]);
+        closure.x += 0.08;
+        edges.push(Edge {
+            from: n - 1,
+            to: 0,
+            relative: closure,
+            loop_closure: true,
+        });
+
+        let mut poses = truth.clone();
+        optimize(&mut poses, &edges, 20);
+
+        // the anchored start stays, no single edge takes the whole error
+        assert_close(&poses[..1], &truth[..1], 1e-4);
+        let worst = edges
+            .iter()
+            .map(|e| {
+                let r = relative(poses[e.from], poses[e.to]);
+                (r.x - e.relative.x).hypot(r.y - e.relative.y)
+            })
+            .fold(0.0, f64::max);
+        assert!(worst < 0.04, "{}", worst);
+    }
+}
@@ -0,0 +1,476 @@
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use eframe::egui::{self, Align2, Slider};
+use egui_plot::{Arrows, Line, PlotPoint, PlotPoints, PlotUi, Points, Text};
+
+use crate::clustering::{cluster_color, Cluster};
+use crate::export::Svg;
+use crate::geometry::distance;
+
+/// Constant velocity Kalman filter of one axis, the axes are independent.
+#[derive(Debug, Clone, Copy)]
+struct AxisFilter {
+    /// position and velocity
+    x: [f64; 2],
+    p: [[f64; 2]; 2],
+}
+
+impl AxisFilter {
+    fn new(position: f64, variance: f64) -> Self {
+        Self {
+            x: [position, 0.0],
+            // the velocity is unknown at first
+            p: [[variance, 0.0], [0.0, 1.0]],
+        }
+    }
+
+    /// `q` is the variance of the acceleration.
+    fn predict(&mut self, dt: f64, q: f64) {
+        let [[p00, p01], [p10, p11]] = self.p;
+
+        self.x[0] += self.x[1] * dt;
+        self.p = [
+            [
+                p00 + dt * (p10 + p01) + dt * dt * p11 + q * dt.powi(4) / 4.0,
+                p01 + dt * p11 + q * dt.powi(3) / 2.0,
+            ],
+            [p10 + dt * p11 + q * dt.powi(3) / 2.0, p11 + q * dt * dt],
+        ];
+    }
+
+    /// `r` is the variance of the measured position.
+    fn update(&mut self, z: f64, r: f64) {
+        let [[p00, p01], [p10, p11]] = self.p;
+        let s = p00 + r;
+        let k = [p00 / s, p10 / s];
+        let innovation = z - self.x[0];
+
+        self.x[0] += k[0] * innovation;
+        self.x[1] += k[1] * innovation;
+        self.p = [
+            [(1.0 - k[0]) * p00, (1.0 - k[0]) * p01],
+            [p10 - k[1] * p00, p11 - k[1] * p01],
+        ];
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Track {
+    pub id: usize,
+    axes: [AxisFilter; 2],
+    /// number of scans the track was associated with a cluster
+    hits: usize,
+    /// consecutive scans without a cluster
+    misses: usize,
+    created: Instant,
+    trail: VecDeque<[f64; 2]>,
+}
+
+impl Track {
+    pub fn position(&self) -> [f64; 2] {
+        [self.axes[0].x[0], self.axes[1].x[0]]
+    }
+
+    pub fn velocity(&self) -> [f64; 2] {
+        [self.axes[0].x[1], self.axes[1].x[1]]
+    }
+
+    pub fn speed(&self) -> f64 {
+        let [vx, vy] = self.velocity();
+        vx.hypot(vy)
+    }
+}
+
+/// Minimum cost assignment of rows to columns (Hungarian method).
+/// Returns the column assigned to each row, rows and columns may differ in number.
+/// Pairs with a non-finite cost are never assigned.
+pub fn hungarian(cost: &[Vec<f64>]) -> Vec<Option<usize>> {
+    let rows = cost.len();
+    let cols = cost.first().map(|r| r.len()).unwrap_or(0);
+    if rows == 0 || cols == 0 {
+        return vec![None; rows];
+    }
+
+    // square matrix, padded with zero cost dummies
+    let n = rows.max(cols);
+    let finite = || cost.iter().flatten().copied().filter(|c| c.is_finite());
+    let (lo, hi) = finite().fold((0.0f64, 0.0f64), |(lo, hi), c| (lo.min(c), hi.max(c)));
+    // dearer than any assignment of finite pairs only
+    let forbidden = hi + n as f64 * (hi - lo + 1.0);
+    let c = |i: usize, j: usize| {
+        if i < rows && j < cols {
+            Some(cost[i][j])
+                .filter(|c| c.is_finite())
+                .unwrap_or(forbidden)
+        } else {
+            0.0
+        }
+    };
+
+    // potentials and matching, 1-based with 0 as the virtual start column
+    let mut u = vec![0.0; n + 1];
+    let mut v = vec![0.0; n + 1];
+    let mut matched_row = vec![0usize; n + 1];
+    let mut way = vec![0usize; n + 1];
+
+    for i in 1..=n {
+        matched_row[0] = i;
+        let mut j0 = 0;
+        let mut min_v = vec![f64::INFINITY; n + 1];
+        let mut used = vec![false; n + 1];
+
+        loop {
+            used[j0] = true;
+            let i0 = matched_row[j0];
+            let mut delta = f64::INFINITY;
+            let mut j1 = 0;
+            for j in 1..=n {
+                if used[j] {
+                    continue;
+                }
+                let reduced = c(i0 - 1, j - 1) - u[i0] - v[j];
+                if reduced < min_v[j] {
+                    min_v[j] = reduced;
+                    way[j] = j0;
+                }
+                if min_v[j] < delta {
+                    delta = min_v[j];
+                    j1 = j;
+                }
+            }
+            for j in 0..=n {
+                if used[j] {
+                    u[matched_row[j]] += delta;
+                    v[j] -= delta;
+                } else {
+                    min_v[j] -= delta;
+                }
+            }
+            j0 = j1;
+            if matched_row[j0] == 0 {
+                break;
+            }
+        }
+
+        // augment along the path
+        loop {
+            let j1 = way[j0];
+            matched_row[j0] = matched_row[j1];
+            j0 = j1;
+            if j0 == 0 {
+                break;
+            }
+        }
+    }
+
+    let mut assignment = vec![None; rows];
+    for (j, &i) in matched_row.iter().enumerate().skip(1) {
+        if i >= 1 && i <= rows && j <= cols && cost[i - 1][j - 1].is_finite() {
+            assignment[i - 1] = Some(j - 1);
+        }
+    }
+
+    assignment
+}
+
+/// Follows clusters across scans, each with its own Kalman filter.
+pub struct Tracker {
+    pub enabled: bool,
+    /// clusters farther from the predicted position are not associated
+    gate: f64,
+    acceleration_noise: f64,
+    measurement_noise: f64,
+    /// hits before a track is shown
+    confirm_hits: usize,
+    max_misses: usize,
+    trail_length: usize,
+    show_trails: bool,
+    tracks: Vec<Track>,
+    next_id: usize,
+    /// time of the last processed scan
+    last: Option<Instant>,
+}
+
+impl Default for Tracker {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            gate: 0.5,
+            acceleration_noise: 1.0,
+            measurement_noise: 0.05,
+            confirm_hits: 3,
+            max_misses: 5,
+            trail_length: 50,
+            show_trails: true,
+            tracks: vec![],
+            next_id: 1,
+            last: None,
+        }
+    }
+}
+
+impl Tracker {
+    pub fn reset(&mut self) {
+        self.tracks.clear();
+        self.next_id = 1;
+        self.last = None;
+    }
+
+    /// Confirmed tracks.
+    pub fn tracks(&self) -> impl Iterator<Item = &Track> {
+        self.tracks.iter().filter(|t| t.hits >= self.confirm_hits)
+    }
+
+    /// Associates the clusters of the scan completed at `time` with the tracks.
+    pub fn update(&mut self, clusters: &[Cluster], time: Instant) {
+        if !self.enabled || self.last == Some(time) {
+            return;
+        }
+
+        // going back in time, e.g. stepping through the playback
+        let dt = match self.last {
+            Some(last) if time > last => time.duration_since(last).as_secs_f64(),
+            Some(_) => {
+                self.reset();
+                0.0
+            }
+            None => 0.0,
+        };
+        self.last = Some(time);
+
+        let q = self.acceleration_noise.powi(2);
+        let r = self.measurement_noise.powi(2);
+        for track in &mut self.tracks {
+            for axis in &mut track.axes {
+                axis.predict(dt, q);
+            }
+        }
+
+        // pairs beyond the gate are too expensive to be chosen over a miss
+        let cost: Vec<Vec<f64>> = self
+            .tracks
+            .iter()
+            .map(|t| {
+                clusters
+                    .iter()
+                    .map(|c| distance(t.position(), c.centroid).min(self.gate * 2.0))
+                    .collect()
+            })
+            .collect();
+        let assignment = hungarian(&cost);
+
+        let mut associated = vec![false; clusters.len()];
+        for (track, cluster) in self.tracks.iter_mut().zip(assignment) {
+            let cluster =
+                cluster.filter(|c| distance(track.position(), clusters[*c].centroid) <= self.gate);
+            match cluster {
+                Some(c) => {
+                    associated[c] = true;
+                    let z = clusters[c].centroid;
+                    track.axes[0].update(z[0], r);
+                    track.axes[1].update(z[1], r);
+                    track.hits += 1;
+                    track.misses = 0;
+                }
+                None => track.misses += 1,
+            }
+
+            track.trail.push_back(track.position());
+            if track.trail.len() > self.trail_length {
+                track.trail.pop_front();
+            }
+        }
+
+        // tentative tracks are dropped after the first miss
+        let (confirm, max_misses) = (self.confirm_hits, self.max_misses);
+        self.tracks
+            .retain(|t| t.misses <= max_misses && (t.hits >= confirm || t.misses == 0));
+
+        for (cluster, _) in clusters.iter().zip(associated).filter(|(_, a)| !a) {
+            let [x, y] = cluster.centroid;
+            self.tracks.push(Track {
+                id: self.next_id,
+                axes: [AxisFilter::new(x, r), AxisFilter::new(y, r)],
+                hits: 1,
+                misses: 0,
+                created: time,
+                trail: VecDeque::from([cluster.centroid]),
+            });
+            self.next_id += 1;
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        if ui.checkbox(&mut self.enabled, "Track clusters").changed() {
+            self.reset();
+        }
+        ui.add(Slider::new(&mut self.gate, 0.05..=2.0).text("Gate (m)"))
+            .on_hover_text("Maximum distance between a track and its next cluster");
+        ui.add(
+            Slider::new(&mut self.acceleration_noise, 0.01..=10.0)
+                .text("Accel. noise (m/s²)")
+                .logarithmic(true),
+        );
+        ui.add(
+            Slider::new(&mut self.measurement_noise, 0.001..=0.5)
+                .text("Position noise (m)")
+                .logarithmic(true),
+        );
+        ui.add(Slider::new(&mut self.confirm_hits, 1..=10).text("Confirm after"));
+        ui.add(Slider::new(&mut self.max_misses, 0..=30).text("Drop after misses"));
+        ui.checkbox(&mut self.show_trails, "Trails");
+        if self.show_trails {
+            ui.add(Slider::new(&mut self.trail_length, 2..=500).text("Trail length"));
+        }
+
+        if !self.enabled {
+            return;
+        }
+
+        let newest = self.last;
+        egui::ScrollArea::vertical()
+            .id_source("tracks")
+            .max_height(200.0)
+            .show(ui, |ui| {
+                egui::Grid::new("tracks")
+                    .num_columns(4)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.label("ID");
+                        ui.label("Position");
+                        ui.label("Speed");
+                        ui.label("Age");
+                        ui.end_row();
+                        for t in self.tracks() {
+                            let [x, y] = t.position();
+                            let age = newest
+                                .map(|n| n.duration_since(t.created).as_secs_f64())
+                                .unwrap_or_default();
+                            ui.colored_label(cluster_color(t.id), format!("{}", t.id));
+                            ui.label(format!("({:.2}, {:.2})", x, y));
+                            ui.label(format!("{:.2}m/s", t.speed()));
+                            ui.label(format!("{:.1}s", age));
+                            ui.end_row();
+                        }
+                    });
+            });
+    }
+
+    pub fn show(&self, plot_ui: &mut PlotUi) {
+        if !self.enabled {
+            return;
+        }
+
+        for t in self.tracks() {
+            let color = cluster_color(t.id);
+            let [x, y] = t.position();
+            let [vx, vy] = t.velocity();
+
+            if self.show_trails && t.trail.len() > 1 {
+                plot_ui.line(
+                    Line::new(PlotPoints::new(t.trail.iter().copied().collect()))
+                        .color(color.gamma_multiply(0.6))
+                        .allow_hover(false),
+                );
+            }
+
+            // where the track will be in one second
+            plot_ui.arrows(
+                Arrows::new(
+                    PlotPoints::new(vec![[x, y]]),
+                    PlotPoints::new(vec![[x + vx, y + vy]]),
+                )
+                .color(color)
+                .allow_hover(false),
+            );
+            plot_ui.points(
+                Points::new(vec![[x, y]])
+                    .radius(4.0)
+                    .color(color)
+                    .name(format!("Track {}", t.id)),
+            );
+            plot_ui.text(
+                Text::new(PlotPoint::new(x, y), format!("#{}", t.id))
+                    .color(color)
+                    .anchor(Align2::LEFT_TOP),
+            );
+        }
+    }
+
+    pub fn write_svg(&self, svg: &mut Svg) {
+        if !self.enabled {
+            return;
+        }
+
+        for t in self.tracks() {
+            let color = cluster_color(t.id);
+            let [x, y] = t.position();
+            let [vx, vy] = t.velocity();
+
+            if self.show_trails && t.trail.len() > 1 {
+                let trail: Vec<_> = t.trail.iter().copied().collect();
+                svg.polyline(&trail, 1.0, color.gamma_multiply(0.6), false);
+            }
+            svg.polyline(&[[x, y], [x + vx, y + vy]], 1.5, color, false);
+            svg.circle([x, y], 4.0, color);
+            svg.text([x, y], &format!("#{}", t.id), color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn total(cost: &[Vec<f64>], assignment: &[Option<usize>]) -> f64 {
+        assignment
+            .iter()
+            .enumerate()
+            .filter_map(|(i, j)| j.map(|j| cost[i][j]))
+            .sum()
+    }
+
+    #[test]
+    fn hungarian_finds_the_minimum() {
+        // the greedy choice of row 0 is not optimal
+        let cost = vec![
+            vec![1.0, 2.0, 9.0],
+            vec![1.5, 8.0, 9.0],
+            vec![9.0, 3.0, 4.0],
+        ];
+        let assignment = hungarian(&cost);
+
+        assert_eq!(assignment, vec![Some(1), Some(0), Some(2)]);
+        assert_eq!(total(&cost, &assignment), 7.5);
+    }
+
+    #[test]
+    fn hungarian_with_more_columns() {
+        let cost = vec![vec![5.0, 1.0, 3.0, 2.0], vec![4.0, 1.5, 7.0, 9.0]];
+
+        assert_eq!(hungarian(&cost), vec![Some(3), Some(1)]);
+    }
+
+    #[test]
+    fn hungarian_with_more_rows() {
+        let cost = vec![vec![3.0], vec![1.0], vec![2.0]];
+
+        assert_eq!(hungarian(&cost), vec![None, Some(0), None]);
+    }
+
+    #[test]
+    fn hungarian_skips_infeasible_pairs() {
+        let inf = f64::INFINITY;
+        let cost = vec![vec![inf, 1.0], vec![inf, 2.0], vec![3.0, inf]];
+
+        let assignment = hungarian(&cost);
+        assert_eq!(assignment[2], Some(0));
+        assert_eq!(assignment[0], Some(1));
+        assert_eq!(assignment[1], None);
+
+        // nothing can be assigned
+        assert_eq!(hungarian(&[vec![inf, inf]]), vec![None]);
+        assert_eq!(hungarian(&[vec![], vec![]]), vec![None, None]);
+    }
+}
@@ -0,0 +1,504 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Instant;
+
+use eframe::egui::{self, Align2, Color32, RichText, Slider};
+use egui_plot::{Line, PlotPoint, PlotPoints, PlotUi, Points, Polygon, Text};
+use notify_rust::Notification;
+
+use crate::events::EventLog;
+use crate::export::Svg;
+use crate::geometry::{is_convex, point_in_polygon};
+use crate::scan::Scan;
+
+/// Distance in screen pixels to the first corner that closes the polygon.
+const CLOSE_RADIUS_PX: f32 = 12.0;
+
+/// Sound played with the alarm notification, named as the platform expects.
+#[cfg(target_os = "windows")]
+const ALARM_SOUND: &str = "Reminder";
+#[cfg(target_os = "macos")]
+const ALARM_SOUND: &str = "Sosumi";
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+const ALARM_SOUND: &str = "alarm-clock-elapsed";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ZoneKind {
+    Warning,
+    Protective,
+}
+
+impl ZoneKind {
+    fn label(&self) -> &'static str {
+        match self {
+            ZoneKind::Warning => "Warning",
+            ZoneKind::Protective => "Protective",
+        }
+    }
+
+    fn color(&self) -> Color32 {
+        match self {
+            ZoneKind::Warning => Color32::from_rgb(255, 200, 0),
+            ZoneKind::Protective => Color32::from_rgb(255, 40, 40),
+        }
+    }
+}
+
+struct Zone {
+    name: String,
+    kind: ZoneKind,
+    polygon: Vec<[f64; 2]>,
+    enabled: bool,
+    /// points inside during the last scan
+    inside: usize,
+    /// consecutive scans disagreeing with `active`
+    streak: usize,
+    active: bool,
+}
+
+/// Watches polygonal zones and raises alarms when points enter them.
+pub struct ZoneMonitor {
+    zones: Vec<Zone>,
+    /// index of the zone being drawn
+    drawing: Option<usize>,
+    min_points: usize,
+    /// scans in a row before an alarm is raised or cleared
+    debounce_scans: usize,
+    /// desktop notification with sound when a zone is entered
+    notify: bool,
+    /// errors of the notifications, which are shown from a background thread
+    notify_errors: (Sender<String>, Receiver<String>),
+    notify_status: Option<String>,
+    pub events: EventLog,
+    /// the scan the zones were evaluated with
+    source: Option<Instant>,
+}
+
+impl Default for ZoneMonitor {
+    fn default() -> Self {
+        Self {
+            zones: vec![],
+            drawing: None,
+            min_points: 3,
+            debounce_scans: 2,
+            notify: true,
+            notify_errors: mpsc::channel(),
+            notify_status: None,
+            events: Default::default(),
+            source: None,
+        }
+    }
+}
+
+impl ZoneMonitor {
+    /// The most severe active alarm.
+    pub fn alarm(&self) -> Option<ZoneKind> {
+        self.zones
+            .iter()
+            .filter(|z| z.enabled && z.active)
+            .map(|z| z.kind)
+            .max()
+    }
+
+    pub fn reset(&mut self) {
+        for zone in &mut self.zones {
+            zone.inside = 0;
+            zone.streak = 0;
+            zone.active = false;
+        }
+        self.source = None;
+    }
+
+    /// Evaluates the zones with the processed points of the scan unless already done.
+    pub fn update(&mut self, scan: &Scan) {
+        if self.source == Some(scan.completed) {
+            return;
+        }
+        self.source = Some(scan.completed);
+
+        let points: Vec<_> = scan.positions().collect();
+        let mut entered = vec![];
+
+        for (i, zone) in self.zones.iter_mut().enumerate() {
+            if !zone.enabled || zone.polygon.len() < 3 || self.drawing == Some(i) {
+                zone.inside = 0;
+                zone.streak = 0;
+                zone.active = false;
+                continue;
+            }
+
+            zone.inside = points
+                .iter()
+                .filter(|p| point_in_polygon(**p, &zone.polygon))
+                .count();
+
+            let violated = zone.inside >= self.min_points;
+            if violated == zone.active {
+                zone.streak = 0;
+                continue;
+            }
+
+            zone.streak += 1;
+            if zone.streak >= self.debounce_scans {
+                zone.active = violated;
+                zone.streak = 0;
+
+                if violated {
+                    let message = format!(
+                        "{} zone \"{}\" entered, {} points",
+                        zone.kind.label(),
+                        zone.name,
+                        zone.inside
+                    );
+                    self.events.push(message.clone());
+                    entered.push(message);
+                } else {
+                    self.events.push(format!(
+                        "{} zone \"{}\" clear",
+                        zone.kind.label(),
+                        zone.name
+                    ));
+                }
+            }
+        }
+
+        if !entered.is_empty() && self.notify {
+            self.alert(entered.join("\n"));
+        }
+    }
+
+    /// Shows a desktop notification with the alarm sound, off the UI thread
+    /// as the notification service may be slow to answer.
+    fn alert(&self, message: String) {
+        let errors = self.notify_errors.0.clone();
+        std::thread::spawn(move || {
+            let mut notification = Notification::new();
+            notification
+                .appname("LD19 LIDAR Viewer")
+                .summary("Zone alarm")
+                .body(&message)
+                .sound_name(ALARM_SOUND);
+            #[cfg(all(unix, not(target_os = "macos")))]
+            notification.urgency(notify_rust::Urgency::Critical);
+
+            if let Err(err) = notification.show() {
+                let _ = errors.send(err.to_string());
+            }
+        });
+    }
+
+    fn add(&mut self, kind: ZoneKind) {
+        let number = self.zones.iter().filter(|z| z.kind == kind).count() + 1;
+        self.zones.push(Zone {
+            name: format!("{} {}", kind.label(), number),
+            kind,
+            polygon: vec![],
+            enabled: true,
+            inside: 0,
+            streak: 0,
+            active: false,
+        });
+        self.drawing = Some(self.zones.len() - 1);
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        match self.alarm() {
+            Some(kind) => {
+                ui.label(
+                    RichText::new(format!("⚠ {} zone violated", kind.label()))
+                        .strong()
+                        .size(18.0)
+                        .color(kind.color()),
+                );
+            }
+            None if !self.zones.is_empty() => {
+                ui.label("All zones clear");
+            }
+            None => {}
+        }
+
+        let mut remove = None;
+        for (i, zone) in self.zones.iter_mut().enumerate() {
+            ui.push_id(i, |ui| {
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut zone.enabled, "");
+                    ui.add(egui::TextEdit::singleline(&mut zone.name).desired_width(100.0));
+                    ui.selectable_value(&mut zone.kind, ZoneKind::Warning, "W")
+                        .on_hover_text("Warning zone");
+                    ui.selectable_value(&mut zone.kind, ZoneKind::Protective, "P")
+                        .on_hover_text("Protective zone");
+                });
+                ui.horizontal(|ui| {
+                    if self.drawing == Some(i) {
+                        if ui.button("Finish").clicked() {
+                            self.drawing = None;
+                        }
+                    } else if ui
+                        .button("Redraw")
+                        .on_hover_text("Click the corners on the plot")
+                        .clicked()
+                    {
+                        zone.polygon.clear();
+                        self.drawing = Some(i);
+                    }
+                    if ui.small_button("🗑").on_hover_text("Remove").clicked() {
+                        remove = Some(i);
+                    }
+                    if zone.active {
+                        ui.colored_label(zone.kind.color(), format!("{} points", zone.inside));
+                    } else {
+                        ui.label(format!("{} points", zone.inside));
+                    }
+                });
+            });
+        }
+        if let Some(i) = remove {
+            self.zones.remove(i);
+            self.drawing = None;
+        }
+
+        ui.horizontal(|ui| {
+            if ui.button("Add warning zone").clicked() {
+                self.add(ZoneKind::Warning);
+            }
+            if ui.button("Add protective zone").clicked() {
+                self.add(ZoneKind::Protective);
+            }
+        });
+
+        ui.add(Slider::new(&mut self.min_points, 1..=50).text("Min. points"));
+        ui.add(Slider::new(&mut self.debounce_scans, 1..=20).text("Debounce (scans)"));
+        ui.horizontal(|ui| {
+            if ui
+                .checkbox(&mut self.notify, "Audible alarm")
+                .on_hover_text("Desktop notification with the alarm sound")
+                .changed()
+            {
+                self.notify_status = None;
+            }
+            if ui.button("Test").clicked() {
+                self.notify_status = None;
+                self.alert("Test of the zone alarm".to_owned());
+            }
+        });
+        if let Some(err) = self.notify_errors.1.try_iter().last() {
+            self.notify_status = Some(format!("Alarm notification failed: {}", err));
+        }
+        if let Some(status) = &self.notify_status {
+            ui.colored_label(ui.visuals().error_fg_color, status);
+        }
+        self.events.ui(ui, "zone_events");
+    }
+
+    /// Takes the plot `click` while drawing a zone and draws the zones.
+    pub fn show(&mut self, plot_ui: &mut PlotUi, click: &mut Option<PlotPoint>) {
+        if let Some(index) = self.drawing {
+            if let Some(pointer) = click.take() {
+                let polygon = &mut self.zones[index].polygon;
+                let closes = polygon.first().is_some_and(|first| {
+                    let first = plot_ui.screen_from_plot(PlotPoint::new(first[0], first[1]));
+                    let pos = plot_ui.screen_from_plot(pointer);
+                    polygon.len() >= 3 && first.distance(pos) < CLOSE_RADIUS_PX
+                });
+
+                if closes {
+                    self.drawing = None;
+                } else {
+                    polygon.push([pointer.x, pointer.y]);
+                }
+            }
+        }
+
+        // active zones blink
+        let blink = (plot_ui.ctx().input(|i| i.time) * 2.0).fract() < 0.5;
+
+        for (i, zone) in self.zones.iter().enumerate() {
+            if zone.polygon.is_empty() || (!zone.enabled && self.drawing != Some(i)) {
+                continue;
+            }
+
+            let color = zone.kind.color();
+            let mut outline = zone.polygon.clone();
+            if self.drawing == Some(i) {
+                if let Some(pointer) = plot_ui.pointer_coordinate() {
+                    outline.push([pointer.x, pointer.y]);
+                }
+                plot_ui.points(
+                    Points::new(zone.polygon.clone())
+                        .radius(3.0)
+                        .color(color)
+                        .allow_hover(false),
+                );
+            } else {
+                outline.push(zone.polygon[0]);
+            }
+
+            // egui fills convex shapes only, concave zones are outlined
+            if is_convex(&zone.polygon) && self.drawing != Some(i) {
+                let alpha = match (zone.active, blink) {
+                    (true, true) => 0.5,
+                    (true, false) => 0.25,
+                    (false, _) => 0.08,
+                };
+                plot_ui.polygon(
+                    Polygon::new(PlotPoints::new(zone.polygon.clone()))
+                        .fill_color(color.gamma_multiply(alpha))
+                        .stroke((0.0, Color32::TRANSPARENT))
+                        .allow_hover(false),
+                );
+            }
+
+            plot_ui.line(
+                Line::new(PlotPoints::new(outline))
+                    .color(color)
+                    .width(if zone.active { 3.0 } else { 1.5 })
+                    .name(&zone.name),
+            );
+
+            let top = zone
+                .polygon
+                .iter()
+                .max_by(|a, b| a[1].total_cmp(&b[1]))
+                .expect("not empty");
+            let label = if zone.active {
+                format!("{} ⚠", zone.name)
+            } else {
+                zone.name.clone()
+            };
+            plot_ui.text(
+                Text::new(PlotPoint::new(top[0], top[1]), label)
+                    .color(color)
+                    .anchor(Align2::CENTER_BOTTOM),
+            );
+        }
+    }
+
+    pub fn write_svg(&self, svg: &mut Svg) {
+        for zone in self
+            .zones
+            .iter()
+            .filter(|z| z.enabled && z.polygon.len() >= 3)
+        {
+            let color = zone.kind.color();
+            let alpha = if zone.active { 0.4 } else { 0.08 };
+            svg.polygon(&zone.polygon, color.gamma_multiply(alpha), color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::overlay::bearing_deg;
+
+    /// An L-shaped zone, concave around (1, 1).
+    const L_SHAPE: [[f64; 2]; 6] = [
+        [0.0, 0.0],
+        [2.0, 0.0],
+        [2.0, 1.0],
+        [1.0, 1.0],
+        [1.0, 2.0],
+        [0.0, 2.0],
+    ];
+
+    fn monitor(kinds: &[ZoneKind]) -> ZoneMonitor {
+        let mut monitor = ZoneMonitor {
+            notify: false,
+            ..Default::default()
+        };
+        for kind in kinds {
+            monitor.add(*kind);
+            monitor.zones.last_mut().expect("added").polygon = L_SHAPE.to_vec();
+        }
+        monitor.drawing = None;
+        monitor
+    }
+
+    /// Scan `index` of a sequence with points at the positions.
+    fn scan(index: u64, positions: &[[f64; 2]]) -> Scan {
+        let mut scan = Scan::from_readings(
+            positions
+                .iter()
+                .map(|p| (bearing_deg(p[0], p[1]) as f32, p[0].hypot(p[1]) as f32, 200)),
+        );
+        scan.completed += Duration::from_millis(100 * index);
+        scan
+    }
+
+    const INSIDE: [[f64; 2]; 3] = [[0.5, 0.5], [1.5, 0.5], [0.5, 1.5]];
+
+    #[test]
+    fn points_in_the_notch_are_outside() {
+        let mut monitor = monitor(&[ZoneKind::Warning]);
+        let notch = [[1.5, 1.5], [1.2, 1.8], [1.9, 1.1]];
+        let mut positions = INSIDE.to_vec();
+        positions.extend(notch);
+        positions.push([-0.5, 1.0]);
+        monitor.update(&scan(0, &positions));
+
+        assert_eq!(monitor.zones[0].inside, 3);
+        for p in notch {
+            assert!(!point_in_polygon(p, &L_SHAPE), "{:?}", p);
+        }
+    }
+
+    #[test]
+    fn alarm_is_raised_and_cleared_after_the_debounce() {
+        let mut monitor = monitor(&[ZoneKind::Warning]);
+
+        monitor.update(&scan(0, &INSIDE));
+        assert_eq!(monitor.alarm(), None);
+        monitor.update(&scan(1, &INSIDE));
+        assert_eq!(monitor.alarm(), Some(ZoneKind::Warning));
+
+        monitor.update(&scan(2, &[]));
+        assert_eq!(monitor.alarm(), Some(ZoneKind::Warning));
+        monitor.update(&scan(3, &[]));
+        assert_eq!(monitor.alarm(), None);
+
+        let log = monitor.events.to_text();
+        let messages: Vec<_> = log
+            .lines()
+            .filter_map(|l| l.split(" UTC ").nth(1))
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "Warning zone \"Warning 1\" entered, 3 points",
+                "Warning zone \"Warning 1\" clear"
+            ]
+        );
+    }
+
+    #[test]
+    fn short_intrusion_and_few_points_raise_nothing() {
+        let mut monitor = monitor(&[ZoneKind::Warning]);
+
+        // a single scan is shorter than the debounce
+        monitor.update(&scan(0, &INSIDE));
+        monitor.update(&scan(1, &[]));
+        monitor.update(&scan(2, &INSIDE));
+        assert_eq!(monitor.alarm(), None);
+
+        // fewer points than needed
+        monitor.update(&scan(3, &INSIDE[..2]));
+        monitor.update(&scan(4, &INSIDE[..2]));
+        assert_eq!(monitor.alarm(), None);
+        assert!(monitor.events.is_empty());
+    }
+
+    #[test]
+    fn most_severe_alarm_wins() {
+        let mut monitor = monitor(&[ZoneKind::Protective, ZoneKind::Warning]);
+        monitor.update(&scan(0, &INSIDE));
+        monitor.update(&scan(1, &INSIDE));
+        assert_eq!(monitor.alarm(), Some(ZoneKind::Protective));
+
+        // disabled zones and zones being drawn are not watched
+        monitor.zones[0].enabled = false;
+        monitor.drawing = Some(1);
+        monitor.update(&scan(2, &INSIDE));
+        assert_eq!(monitor.alarm(), None);
+    }
+}